@@ -0,0 +1,122 @@
+use crate::ray::Ray;
+use crate::vec3::*;
+
+/// Axis-aligned bounding box
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Aabb {
+    pub minimum: Point3,
+    pub maximum: Point3,
+}
+
+impl Aabb {
+    pub fn new(a: Point3, b: Point3) -> Aabb {
+        Aabb {
+            minimum: a.min(&b),
+            maximum: a.max(&b),
+        }
+    }
+
+    /// Grows degenerate (flat) sides so the slab test still works for them
+    pub fn pad(&self, delta: f64) -> Aabb {
+        let mut padded = *self;
+        for a in 0..3 {
+            if padded.maximum[a] - padded.minimum[a] < delta {
+                padded.minimum[a] -= delta / 2.0;
+                padded.maximum[a] += delta / 2.0;
+            }
+        }
+        padded
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.minimum + self.maximum)
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        let extent = self.maximum - self.minimum;
+        if extent.x() > extent.y() && extent.x() > extent.z() {
+            0
+        } else if extent.y() > extent.z() {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// The parametric interval where the ray is inside the box, clipped to
+    /// [t_min, t_max]
+    pub fn intersect(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> Option<(f64, f64)> {
+        for a in 0..3 {
+            let inv_d = 1.0 / r.direction()[a];
+            let mut t0 = (self.minimum[a] - r.origin()[a]) * inv_d;
+            let mut t1 = (self.maximum[a] - r.origin()[a]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // Written so that NaNs from 0 * inf leave the interval unchanged
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max <= t_min {
+                return None;
+            }
+        }
+        Some((t_min, t_max))
+    }
+
+    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
+
+    /// The eight corners of the box
+    pub fn corners(&self) -> [Point3; 8] {
+        let (lo, hi) = (self.minimum, self.maximum);
+        [0, 1, 2, 3, 4, 5, 6, 7].map(|i| {
+            Point3::new(
+                if i & 1 == 0 { lo.x() } else { hi.x() },
+                if i & 2 == 0 { lo.y() } else { hi.y() },
+                if i & 4 == 0 { lo.z() } else { hi.z() },
+            )
+        })
+    }
+}
+
+pub fn surrounding_box(box0: &Aabb, box1: &Aabb) -> Aabb {
+    Aabb {
+        minimum: box0.minimum.min(&box1.minimum),
+        maximum: box0.maximum.max(&box1.maximum),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intersect_test() {
+        let b = Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(b.intersect(&r, 0.0, f64::INFINITY), Some((4.0, 6.0)));
+        assert_eq!(b.intersect(&r, 4.5, 5.0), Some((4.5, 5.0)));
+        assert!(!b.hit(&r, 0.0, 3.0));
+
+        let miss = Ray::new(Point3::new(2.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(!b.hit(&miss, 0.0, f64::INFINITY));
+    }
+
+    #[test]
+    fn surrounding_box_test() {
+        let a = Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+        let b = Aabb::new(Point3::new(-1.0, 0.5, 0.5), Point3::new(0.5, 2.0, 0.5));
+        assert_eq!(
+            surrounding_box(&a, &b),
+            Aabb::new(Point3::new(-1.0, 0.0, 0.0), Point3::new(1.0, 2.0, 1.0))
+        );
+    }
+
+    #[test]
+    fn pad_test() {
+        let flat = Aabb::new(Point3::new(0.0, 0.0, 1.0), Point3::new(1.0, 1.0, 1.0)).pad(0.2);
+        assert_eq!(flat.minimum, Point3::new(0.0, 0.0, 0.9));
+        assert_eq!(flat.maximum, Point3::new(1.0, 1.0, 1.1));
+    }
+}
@@ -0,0 +1,270 @@
+use std::sync::Arc;
+
+use crate::aabb::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::ray::*;
+use crate::vec3::*;
+
+/// Rectangle in the plane z = k
+pub struct XyRect {
+    x0: f64,
+    x1: f64,
+    y0: f64,
+    y1: f64,
+    k: f64,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+/// Rectangle in the plane y = k
+pub struct XzRect {
+    x0: f64,
+    x1: f64,
+    z0: f64,
+    z1: f64,
+    k: f64,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+/// Rectangle in the plane x = k
+pub struct YzRect {
+    y0: f64,
+    y1: f64,
+    z0: f64,
+    z1: f64,
+    k: f64,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+impl XyRect {
+    pub fn new(
+        x0: f64,
+        x1: f64,
+        y0: f64,
+        y1: f64,
+        k: f64,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> XyRect {
+        XyRect {
+            x0,
+            x1,
+            y0,
+            y1,
+            k,
+            material,
+        }
+    }
+}
+
+impl XzRect {
+    pub fn new(
+        x0: f64,
+        x1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> XzRect {
+        XzRect {
+            x0,
+            x1,
+            z0,
+            z1,
+            k,
+            material,
+        }
+    }
+}
+
+impl YzRect {
+    pub fn new(
+        y0: f64,
+        y1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> YzRect {
+        YzRect {
+            y0,
+            y1,
+            z0,
+            z1,
+            k,
+            material,
+        }
+    }
+}
+
+// Shared intersection for a rectangle [a0, a1] x [b0, b1] in the plane where
+// the `axis` coordinate equals k. `a` and `b` are the in-plane axes.
+fn hit_rect(
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+    (axis, a, b): (usize, usize, usize),
+    (a0, a1, b0, b1): (f64, f64, f64, f64),
+    k: f64,
+    material: &Arc<dyn Material + Send + Sync>,
+) -> Option<HitRecord> {
+    let t = (k - r.origin().e[axis]) / r.direction().e[axis];
+    if !t.is_finite() || t < t_min || t > t_max {
+        return None;
+    }
+
+    let p = r.at(t);
+    let (pa, pb) = (p.e[a], p.e[b]);
+    if pa < a0 || pa > a1 || pb < b0 || pb > b1 {
+        return None;
+    }
+
+    let mut outward_normal = Vec3::default();
+    outward_normal.e[axis] = 1.0;
+
+    let mut rec = HitRecord {
+        t,
+        p,
+        u: (pa - a0) / (a1 - a0),
+        v: (pb - b0) / (b1 - b0),
+        material: material.clone(),
+        ..Default::default()
+    };
+    rec.set_face_normal(r, &outward_normal);
+
+    Some(rec)
+}
+
+impl Hittable for XyRect {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        hit_rect(
+            r,
+            t_min,
+            t_max,
+            (2, 0, 1),
+            (self.x0, self.x1, self.y0, self.y1),
+            self.k,
+            &self.material,
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // Pad the zero-width axis
+        Some(Aabb::new(
+            Point3::new(self.x0, self.y0, self.k - 0.0001),
+            Point3::new(self.x1, self.y1, self.k + 0.0001),
+        ))
+    }
+}
+
+impl Hittable for XzRect {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        hit_rect(
+            r,
+            t_min,
+            t_max,
+            (1, 0, 2),
+            (self.x0, self.x1, self.z0, self.z1),
+            self.k,
+            &self.material,
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // Pad the zero-width axis
+        Some(Aabb::new(
+            Point3::new(self.x0, self.k - 0.0001, self.z0),
+            Point3::new(self.x1, self.k + 0.0001, self.z1),
+        ))
+    }
+}
+
+impl Hittable for YzRect {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        hit_rect(
+            r,
+            t_min,
+            t_max,
+            (0, 1, 2),
+            (self.y0, self.y1, self.z0, self.z1),
+            self.k,
+            &self.material,
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // Pad the zero-width axis
+        Some(Aabb::new(
+            Point3::new(self.k - 0.0001, self.y0, self.z0),
+            Point3::new(self.k + 0.0001, self.y1, self.z1),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn material() -> Arc<dyn Material + Send + Sync> {
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    #[test]
+    fn xy_rect_test() {
+        let rect = XyRect::new(0.0, 2.0, 0.0, 4.0, -1.0, material());
+        let r = Ray::new(Point3::new(0.5, 3.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let rec = rect.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 2.0);
+        assert_eq!((rec.u, rec.v), (0.25, 0.75));
+        assert!(rec.front_face);
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+
+        let outside = Ray::new(Point3::new(2.5, 3.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let parallel = Ray::new(Point3::new(0.5, 3.0, 1.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(rect.hit(&outside, 0.001, f64::INFINITY).is_none());
+        assert!(rect.hit(&parallel, 0.001, f64::INFINITY).is_none());
+        assert!(rect.hit(&r, 0.001, 1.5).is_none());
+
+        let b = rect.bounding_box().unwrap();
+        assert_eq!(b.minimum, Point3::new(0.0, 0.0, -1.0001));
+        assert_eq!(b.maximum, Point3::new(2.0, 4.0, -0.9999));
+    }
+
+    #[test]
+    fn xz_rect_test() {
+        let rect = XzRect::new(-1.0, 1.0, -1.0, 1.0, 2.0, material());
+        let r = Ray::new(Point3::new(0.5, 0.0, -0.5), Vec3::new(0.0, 1.0, 0.0));
+
+        let rec = rect.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 2.0);
+        assert_eq!((rec.u, rec.v), (0.75, 0.25));
+        assert!(!rec.front_face);
+        assert_eq!(rec.normal, Vec3::new(0.0, -1.0, 0.0));
+
+        let outside = Ray::new(Point3::new(0.5, 0.0, 1.5), Vec3::new(0.0, 1.0, 0.0));
+        assert!(rect.hit(&outside, 0.001, f64::INFINITY).is_none());
+
+        let b = rect.bounding_box().unwrap();
+        assert_eq!(b.minimum, Point3::new(-1.0, 1.9999, -1.0));
+        assert_eq!(b.maximum, Point3::new(1.0, 2.0001, 1.0));
+    }
+
+    #[test]
+    fn yz_rect_test() {
+        let rect = YzRect::new(0.0, 1.0, 0.0, 2.0, 3.0, material());
+        let r = Ray::new(Point3::new(5.0, 0.5, 1.5), Vec3::new(-1.0, 0.0, 0.0));
+
+        let rec = rect.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 2.0);
+        assert_eq!((rec.u, rec.v), (0.5, 0.75));
+        assert!(rec.front_face);
+        assert_eq!(rec.normal, Vec3::new(1.0, 0.0, 0.0));
+
+        let outside = Ray::new(Point3::new(5.0, 1.5, 1.5), Vec3::new(-1.0, 0.0, 0.0));
+        assert!(rect.hit(&outside, 0.001, f64::INFINITY).is_none());
+
+        let b = rect.bounding_box().unwrap();
+        assert_eq!(b.minimum, Point3::new(2.9999, 0.0, 0.0));
+        assert_eq!(b.maximum, Point3::new(3.0001, 1.0, 2.0));
+    }
+}
@@ -0,0 +1,479 @@
+//! Keyframed parameters and rendering of frame sequences.
+//!
+//! A `Track` interpolates a value between keyframes. Cameras and transforms
+//! are animated by a track per parameter, anything else, such as material
+//! parameters, by sampling tracks while building the world of each frame.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::bvh;
+use crate::camera::Camera;
+use crate::hittable::*;
+use crate::integrator::Integrator;
+use crate::render::*;
+use crate::vec3::*;
+use crate::y4m::Y4mWriter;
+
+/// Values that can be keyframed
+pub trait Animatable: Copy {
+    fn lerp(&self, other: &Self, t: f64) -> Self;
+
+    /// `self` moved by `t` times the change from `from` to `to`, used to
+    /// place Bézier handles
+    fn offset(&self, from: &Self, to: &Self, t: f64) -> Self;
+}
+
+impl Animatable for f64 {
+    fn lerp(&self, other: &f64, t: f64) -> f64 {
+        self + t * (other - self)
+    }
+
+    fn offset(&self, from: &f64, to: &f64, t: f64) -> f64 {
+        self + t * (to - from)
+    }
+}
+
+impl Animatable for Vec3 {
+    fn lerp(&self, other: &Vec3, t: f64) -> Vec3 {
+        *self + t * (*other - *self)
+    }
+
+    fn offset(&self, from: &Vec3, to: &Vec3, t: f64) -> Vec3 {
+        *self + t * (*to - *from)
+    }
+}
+
+/// Rotations interpolate along the shortest arc
+impl Animatable for Quat {
+    fn lerp(&self, other: &Quat, t: f64) -> Quat {
+        self.slerp(other, t)
+    }
+
+    fn offset(&self, from: &Quat, to: &Quat, t: f64) -> Quat {
+        let change = *to * from.conjugate();
+        Quat::identity().slerp(&change, t) * *self
+    }
+}
+
+/// How a keyframe moves on to the next one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Holds the value until the next key
+    Step,
+    Linear,
+    /// Cubic Bézier with handles set from the neighbouring keys, so the
+    /// motion passes smoothly through every key
+    Bezier,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe<T> {
+    pub time: f64,
+    pub value: T,
+    pub interpolation: Interpolation,
+}
+
+/// Keyframes sorted by time. Before the first and after the last key the
+/// value is held.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track<T> {
+    keys: Vec<Keyframe<T>>,
+}
+
+impl<T: Animatable> Track<T> {
+    pub fn new(time: f64, value: T, interpolation: Interpolation) -> Track<T> {
+        Track {
+            keys: vec![Keyframe {
+                time,
+                value,
+                interpolation,
+            }],
+        }
+    }
+
+    /// The same value at all times
+    pub fn constant(value: T) -> Track<T> {
+        Track::new(0.0, value, Interpolation::Linear)
+    }
+
+    /// Adds a key, replacing any key at the same time
+    pub fn with_key(mut self, time: f64, value: T, interpolation: Interpolation) -> Track<T> {
+        let key = Keyframe {
+            time,
+            value,
+            interpolation,
+        };
+        let index = self.keys.partition_point(|k| k.time < time);
+        match self.keys.get(index) {
+            Some(existing) if existing.time == time => self.keys[index] = key,
+            _ => self.keys.insert(index, key),
+        }
+        self
+    }
+
+    pub fn keys(&self) -> &[Keyframe<T>] {
+        &self.keys
+    }
+
+    pub fn sample(&self, time: f64) -> T {
+        let next = self.keys.partition_point(|k| k.time <= time);
+        if next == 0 {
+            return self.keys[0].value;
+        }
+        if next == self.keys.len() {
+            return self.keys[next - 1].value;
+        }
+
+        let i = next - 1;
+        let (a, b) = (&self.keys[i], &self.keys[next]);
+        let s = (time - a.time) / (b.time - a.time);
+        match a.interpolation {
+            Interpolation::Step => a.value,
+            Interpolation::Linear => a.value.lerp(&b.value, s),
+            Interpolation::Bezier => {
+                let c1 = self.handle(i, next);
+                let c2 = self.handle(next, i);
+                // de Casteljau, which also keeps rotations on the sphere
+                let p01 = a.value.lerp(&c1, s);
+                let p12 = c1.lerp(&c2, s);
+                let p23 = c2.lerp(&b.value, s);
+                p01.lerp(&p12, s).lerp(&p12.lerp(&p23, s), s)
+            }
+        }
+    }
+
+    // Handle of key `i` pointing towards its neighbour `towards`, along the
+    // Catmull-Rom tangent scaled to the segment's duration
+    fn handle(&self, i: usize, towards: usize) -> T {
+        let last = self.keys.len() - 1;
+        let prev = &self.keys[i.saturating_sub(1)];
+        let next = &self.keys[(i + 1).min(last)];
+        let key = &self.keys[i];
+        let segment = self.keys[towards].time - key.time;
+        let span = next.time - prev.time;
+        key.value
+            .offset(&prev.value, &next.value, segment / (3.0 * span))
+    }
+}
+
+/// Camera parameters as tracks, see `Camera::new`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimatedCamera {
+    pub lookfrom: Track<Point3>,
+    pub lookat: Track<Point3>,
+    pub vup: Track<Vec3>,
+    pub vfov: Track<f64>,
+    pub aperture: Track<f64>,
+    pub focus_dist: Track<f64>,
+}
+
+impl AnimatedCamera {
+    /// A still camera, replace its tracks to animate it
+    pub fn new(
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        vfov: f64,
+        aperture: f64,
+        focus_dist: f64,
+    ) -> AnimatedCamera {
+        AnimatedCamera {
+            lookfrom: Track::constant(lookfrom),
+            lookat: Track::constant(lookat),
+            vup: Track::constant(vup),
+            vfov: Track::constant(vfov),
+            aperture: Track::constant(aperture),
+            focus_dist: Track::constant(focus_dist),
+        }
+    }
+
+    pub fn camera_at(&self, time: f64, aspect_ratio: f64) -> Camera {
+        Camera::new(
+            self.lookfrom.sample(time),
+            self.lookat.sample(time),
+            self.vup.sample(time),
+            self.vfov.sample(time),
+            aspect_ratio,
+            self.aperture.sample(time),
+            self.focus_dist.sample(time),
+        )
+    }
+}
+
+/// Scales, then rotates, then translates
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimatedTransform {
+    pub translation: Track<Vec3>,
+    pub rotation: Track<Quat>,
+    pub scale: Track<Vec3>,
+}
+
+impl Default for AnimatedTransform {
+    fn default() -> Self {
+        AnimatedTransform {
+            translation: Track::constant(Vec3::default()),
+            rotation: Track::constant(Quat::identity()),
+            scale: Track::constant(Vec3::new(1.0, 1.0, 1.0)),
+        }
+    }
+}
+
+impl AnimatedTransform {
+    /// `None` while a scale factor is zero
+    pub fn transform_at(&self, time: f64) -> Option<Transform> {
+        let scale = Transform::new(Mat4::scaling(self.scale.sample(time)))?;
+        Some(
+            Transform::translation(self.translation.sample(time))
+                * Transform::rotation(self.rotation.sample(time))
+                * scale,
+        )
+    }
+}
+
+/// Where the objects of each frame come from
+pub enum AnimatedWorld {
+    /// Put under a BVH once and shared by all frames, for scenes where only
+    /// the camera moves
+    Static(HittableList),
+    /// Called with the time of every frame to build its objects
+    Dynamic(Box<dyn Fn(f64) -> HittableList>),
+}
+
+/// Renders a range of frames to numbered images
+pub struct FrameSequence {
+    pub frames: Range<u32>,
+    pub fps: u32,
+    pub settings: RenderSettings,
+    /// Image path with a run of `#` standing for the zero padded frame
+    /// number, such as "frames/orbit_####.png"
+    pub path: String,
+    /// Also streams the frames into this .y4m video
+    pub video: Option<PathBuf>,
+}
+
+impl FrameSequence {
+    pub fn time(&self, frame: u32) -> f64 {
+        frame as f64 / self.fps as f64
+    }
+
+    pub fn render(
+        &self,
+        world: &AnimatedWorld,
+        camera: &AnimatedCamera,
+        integrator: &dyn Integrator,
+    ) -> io::Result<()> {
+        // Frame times divide by the frame rate
+        if self.fps == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame rate must be positive",
+            ));
+        }
+        let mut video = match &self.video {
+            Some(path) => {
+                create_parent(path)?;
+                Some(Y4mWriter::new(
+                    BufWriter::new(File::create(path)?),
+                    self.settings.image_width,
+                    self.settings.image_height,
+                    self.fps,
+                )?)
+            }
+            None => None,
+        };
+        let shared = match world {
+            AnimatedWorld::Static(list) => Some(bvh::accelerate(HittableList {
+                objects: list.objects.clone(),
+            })),
+            AnimatedWorld::Dynamic(_) => None,
+        };
+
+        for frame in self.frames.clone() {
+            let time = self.time(frame);
+            let cam = camera.camera_at(time, self.settings.aspect_ratio());
+            let image = match (&shared, world) {
+                (Some(shared), _) => render(shared, &cam, integrator, &self.settings),
+                (None, AnimatedWorld::Dynamic(build)) => {
+                    let world = bvh::accelerate(build(time));
+                    render(&world, &cam, integrator, &self.settings)
+                }
+                (None, AnimatedWorld::Static(_)) => unreachable!(),
+            };
+
+            let path = frame_path(&self.path, frame);
+            create_parent(&path)?;
+            image.save(&path).map_err(io::Error::other)?;
+            if let Some(video) = &mut video {
+                video.write_frame(&image)?;
+            }
+        }
+        if let Some(video) = video {
+            video.finish()?;
+        }
+        Ok(())
+    }
+}
+
+fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => fs::create_dir_all(parent),
+        _ => Ok(()),
+    }
+}
+
+/// Replaces the last run of `#` in `pattern` with the frame number padded to
+/// its length. Without one the number goes before the extension.
+pub fn frame_path(pattern: &str, frame: u32) -> PathBuf {
+    let end = match pattern.rfind('#') {
+        Some(last) => last + 1,
+        None => {
+            let path = Path::new(pattern);
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let name = match path.extension() {
+                Some(ext) => format!("{}_{:04}.{}", stem, frame, ext.to_string_lossy()),
+                None => format!("{}_{:04}", stem, frame),
+            };
+            return path.with_file_name(name);
+        }
+    };
+    let start = pattern[..end].trim_end_matches('#').len();
+    PathBuf::from(format!(
+        "{}{:0width$}{}",
+        &pattern[..start],
+        frame,
+        &pattern[end..],
+        width = end - start
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrator::PathTracer;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::Arc;
+
+    #[test]
+    fn linear_step_test() {
+        let track = Track::new(1.0, 0.0, Interpolation::Linear)
+            .with_key(3.0, 4.0, Interpolation::Step)
+            .with_key(5.0, 10.0, Interpolation::Linear);
+        assert_eq!(track.sample(0.0), 0.0);
+        assert_eq!(track.sample(2.0), 2.0);
+        assert_eq!(track.sample(4.9), 4.0);
+        assert_eq!(track.sample(5.0), 10.0);
+        assert_eq!(track.sample(9.0), 10.0);
+
+        let track = track.with_key(3.0, 6.0, Interpolation::Linear);
+        assert_eq!(track.keys().len(), 3);
+        assert_eq!(track.sample(4.0), 8.0);
+    }
+
+    #[test]
+    fn bezier_test() {
+        // Evenly moving keys stay linear
+        let track = Track::new(0.0, 0.0, Interpolation::Bezier)
+            .with_key(1.0, 1.0, Interpolation::Bezier)
+            .with_key(2.0, 2.0, Interpolation::Bezier);
+        assert!((track.sample(0.5) - 0.5).abs() < 1e-12);
+        assert!((track.sample(1.5) - 1.5).abs() < 1e-12);
+
+        // A peak eases in and out of the middle key
+        let track = Track::new(0.0, 0.0, Interpolation::Bezier)
+            .with_key(1.0, 1.0, Interpolation::Bezier)
+            .with_key(2.0, 0.0, Interpolation::Bezier);
+        assert_eq!(track.sample(1.0), 1.0);
+        assert!((track.sample(0.5) - 0.625).abs() < 1e-12);
+        assert!((track.sample(0.99) - track.sample(1.01)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn transform_test() {
+        let transform = AnimatedTransform {
+            translation: Track::new(0.0, Vec3::default(), Interpolation::Linear).with_key(
+                2.0,
+                Vec3::new(0.0, 2.0, 0.0),
+                Interpolation::Linear,
+            ),
+            rotation: Track::new(0.0, Quat::identity(), Interpolation::Bezier).with_key(
+                2.0,
+                Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 90.0),
+                Interpolation::Bezier,
+            ),
+            ..Default::default()
+        };
+        let p = transform
+            .transform_at(1.0)
+            .unwrap()
+            .point(&Point3::new(1.0, 0.0, 0.0));
+        let half = std::f64::consts::FRAC_1_SQRT_2;
+        assert!((p - Point3::new(half, 1.0, -half)).length() < 1e-9);
+    }
+
+    #[test]
+    fn frame_path_test() {
+        assert_eq!(
+            frame_path("out/f_###.png", 7),
+            PathBuf::from("out/f_007.png")
+        );
+        assert_eq!(frame_path("#.png", 12), PathBuf::from("12.png"));
+        assert_eq!(frame_path("out/f.png", 7), PathBuf::from("out/f_0007.png"));
+    }
+
+    #[test]
+    fn sequence_test() {
+        let dir = std::env::temp_dir().join("animation_sequence_test");
+        let _ = fs::remove_dir_all(&dir);
+        let times = Rc::new(RefCell::new(Vec::new()));
+        let recorded = times.clone();
+        let world = AnimatedWorld::Dynamic(Box::new(move |time| {
+            recorded.borrow_mut().push(time);
+            let mut list = HittableList::default();
+            list.add(Arc::new(Sphere::new(
+                Point3::new(0.0, 0.0, -2.0),
+                0.5 + time,
+                Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+            )));
+            list
+        }));
+        let sequence = FrameSequence {
+            frames: 1..3,
+            fps: 4,
+            settings: RenderSettings {
+                image_width: 4,
+                image_height: 3,
+                samples_per_pixel: 1,
+            },
+            path: dir.join("##.png").to_string_lossy().into_owned(),
+            video: Some(dir.join("video.y4m")),
+        };
+        let camera = AnimatedCamera::new(
+            Point3::default(),
+            Point3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            90.0,
+            0.0,
+            1.0,
+        );
+        sequence
+            .render(&world, &camera, &PathTracer::new(2))
+            .unwrap();
+
+        assert_eq!(*times.borrow(), vec![0.25, 0.5]);
+        assert!(dir.join("01.png").exists() && dir.join("02.png").exists());
+        let video = fs::read(dir.join("video.y4m")).unwrap();
+        let header = "YUV4MPEG2 W4 H3 F4:1 Ip A1:1 C444\n".len();
+        assert_eq!(video.len(), header + 2 * (6 + 3 * 12));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let still = FrameSequence { fps: 0, ..sequence };
+        assert!(still.render(&world, &camera, &PathTracer::new(2)).is_err());
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,617 @@
+//! Bidirectional path tracing.
+//!
+//! Every camera sample traces one path from the camera and one from a light
+//! and joins each prefix of the one with each prefix of the other. Multiple
+//! importance sampling with the balance heuristic weights these strategies,
+//! so each kind of light transport comes mostly from the strategy that
+//! samples it best: caustics through glass from light paths splatted onto
+//! the film, small openings from connections, and so on. The structure
+//! follows pbrt's BDPT.
+//!
+//! Surfaces whose material cannot `eval` its BSDF, such as mirrors and
+//! glass, are only passed by scattering. The sky is only found by camera
+//! paths.
+
+use crate::camera::Camera;
+use crate::hittable::*;
+use crate::integrator::{Background, Integrator};
+use crate::light::{pick_light, AreaLight};
+use crate::ray::Ray;
+use crate::render::{RenderSettings, SplatBuffer};
+use crate::vec3::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Camera,
+    Light,
+    Surface,
+}
+
+#[derive(Clone)]
+struct Vertex {
+    kind: Kind,
+    p: Point3,
+    /// Geometric normal, unused for the camera
+    n: Vec3,
+    /// Unit direction towards the previous vertex of surface vertices
+    wo: Vec3,
+    rec: Option<HitRecord>,
+    /// Throughput from the path's start up to here
+    beta: Color,
+    /// Reached by sampling a material without a BSDF to connect to
+    delta: bool,
+    /// Area density of sampling this vertex from the previous one, and the
+    /// other way round
+    pdf_fwd: f64,
+    pdf_rev: f64,
+    /// Light this vertex lies on
+    light: Option<usize>,
+}
+
+impl Vertex {
+    fn camera(p: Point3, beta: Color) -> Vertex {
+        Vertex {
+            kind: Kind::Camera,
+            p,
+            n: Vec3::default(),
+            wo: Vec3::default(),
+            rec: None,
+            beta,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+            light: None,
+        }
+    }
+
+    fn light(light: usize, p: Point3, n: Vec3, beta: Color, pdf_fwd: f64) -> Vertex {
+        Vertex {
+            kind: Kind::Light,
+            n,
+            pdf_fwd,
+            light: Some(light),
+            ..Vertex::camera(p, beta)
+        }
+    }
+
+    fn connectible(&self) -> bool {
+        self.kind != Kind::Surface || !self.delta
+    }
+}
+
+fn remap0(pdf: f64) -> f64 {
+    if pdf == 0.0 {
+        1.0
+    } else {
+        pdf
+    }
+}
+
+pub struct Bdpt {
+    camera: Camera,
+    settings: RenderSettings,
+    lights: Vec<AreaLight>,
+    /// Longest path in bounces
+    pub max_depth: usize,
+    pub background: Background,
+}
+
+impl Bdpt {
+    /// `camera` and `settings` must be the ones rendered with, and the
+    /// hittables of `lights` must be in the world. Light paths reaching the
+    /// camera need the splat buffer of `render`; `li` alone leaves them out
+    /// and weights the other strategies as if they did not exist.
+    pub fn new(
+        camera: Camera,
+        settings: &RenderSettings,
+        lights: Vec<AreaLight>,
+        max_depth: usize,
+    ) -> Bdpt {
+        Bdpt {
+            camera,
+            settings: *settings,
+            lights,
+            max_depth,
+            background: Background::Sky,
+        }
+    }
+
+    pub fn with_background(mut self, background: Background) -> Bdpt {
+        self.background = background;
+        self
+    }
+
+    // Film area at unit distance covered by the samples of all pixels
+    fn film_area(&self) -> f64 {
+        self.camera.film_area() * self.settings.film_extent()
+    }
+
+    // Pinholes have no area but pick their single point with certainty
+    fn lens_area(&self) -> f64 {
+        match self.camera.lens_area() {
+            a if a > 0.0 => a,
+            _ => 1.0,
+        }
+    }
+
+    // Cosine between `w` and the view direction, if the ray from `lens`
+    // along it lands on a pixel
+    fn camera_cos(&self, lens: &Point3, w: &Vec3) -> Option<f64> {
+        let cos = dot(w, &self.camera.forward());
+        if cos <= 0.0 {
+            return None;
+        }
+        let (s, t) = self.camera.film_coordinates(lens, &(*lens + *w))?;
+        self.settings.pixel(s, t).map(|_| cos)
+    }
+
+    /// Density over solid angle of camera rays from `lens` along `w`
+    fn camera_pdf_dir(&self, lens: &Point3, w: &Vec3) -> f64 {
+        self.camera_cos(lens, w)
+            .map_or(0.0, |cos| 1.0 / (self.film_area() * cos * cos * cos))
+    }
+
+    /// Importance, normalized so that camera samples see radiance
+    fn camera_importance(&self, lens: &Point3, w: &Vec3) -> f64 {
+        self.camera_cos(lens, w).map_or(0.0, |cos| {
+            1.0 / (self.film_area() * self.lens_area() * cos.powi(4))
+        })
+    }
+
+    fn light_index(&self, rec: &HitRecord) -> Option<usize> {
+        self.lights.iter().position(|light| light.is_hit(rec))
+    }
+
+    fn light_select_pdf(&self) -> f64 {
+        1.0 / self.lights.len() as f64
+    }
+
+    // Solid angle density at `from` to area density at `to`
+    fn convert(pdf: f64, from: &Vertex, to: &Vertex) -> f64 {
+        let d = to.p - from.p;
+        let dist2 = d.length_squared();
+        if dist2 == 0.0 {
+            return 0.0;
+        }
+        let cos = match to.kind {
+            Kind::Camera => 1.0,
+            _ => dot(&to.n, &d).abs() / dist2.sqrt(),
+        };
+        pdf * cos / dist2
+    }
+
+    fn f(v: &Vertex, next: &Point3) -> Color {
+        match &v.rec {
+            Some(rec) => rec
+                .material
+                .eval(rec, &v.wo, &unit_vector(*next - v.p))
+                .map_or(Color::default(), |(f, _)| f),
+            None => Color::default(),
+        }
+    }
+
+    /// Area density with which `v`, reached from `prev`, samples `next`
+    fn pdf(&self, v: &Vertex, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        if v.kind == Kind::Light {
+            return self.pdf_light(v, next);
+        }
+        let w = unit_vector(next.p - v.p);
+        let pdf_dir = match (v.kind, &v.rec, prev) {
+            (Kind::Camera, _, _) => self.camera_pdf_dir(&v.p, &w),
+            (_, Some(rec), Some(prev)) => rec
+                .material
+                .eval(rec, &unit_vector(prev.p - v.p), &w)
+                .map_or(0.0, |(_, pdf)| pdf),
+            _ => 0.0,
+        };
+        Bdpt::convert(pdf_dir, v, next)
+    }
+
+    /// Area density of the light under `v` emitting towards `to`
+    fn pdf_light(&self, v: &Vertex, to: &Vertex) -> f64 {
+        let light = match v.light {
+            Some(light) => &self.lights[light],
+            None => return 0.0,
+        };
+        let w = unit_vector(to.p - v.p);
+        Bdpt::convert(light.pdf_direction(&v.n, &w), v, to)
+    }
+
+    /// Area density of light paths starting at `v`
+    fn pdf_light_origin(&self, v: &Vertex) -> f64 {
+        v.light.map_or(0.0, |light| {
+            self.light_select_pdf() / self.lights[light].area()
+        })
+    }
+
+    fn visible(world: &dyn Hittable, a: &Point3, b: &Point3) -> bool {
+        let d = *b - *a;
+        let dist = d.length();
+        let shadow = Ray::new(*a, d / dist);
+        world.hit(&shadow, 0.001, dist - 0.001).is_none()
+    }
+
+    // Extends `path` by scattering until it has `max_vertices`. Returns the
+    // ray that left the scene, if any, and the throughput it carries.
+    fn walk(
+        &self,
+        world: &dyn Hittable,
+        mut ray: Ray,
+        mut beta: Color,
+        mut pdf_dir: f64,
+        max_vertices: usize,
+        path: &mut Vec<Vertex>,
+    ) -> Option<(Ray, Color)> {
+        while path.len() < max_vertices {
+            let rec = match world.hit(&ray, 0.001, f64::INFINITY) {
+                Some(rec) => rec,
+                None => return Some((ray, beta)),
+            };
+            let wo = -unit_vector(ray.direction());
+            let delta = rec.material.eval(&rec, &wo, &wo).is_none();
+            let mut vertex = Vertex {
+                kind: Kind::Surface,
+                p: rec.p,
+                n: rec.normal,
+                wo,
+                rec: None,
+                beta,
+                delta,
+                pdf_fwd: 0.0,
+                pdf_rev: 0.0,
+                light: self.light_index(&rec),
+            };
+            vertex.pdf_fwd = Bdpt::convert(pdf_dir, path.last().unwrap(), &vertex);
+            let scatter = rec.material.scatter(&ray, &rec);
+            let pdfs = match &scatter {
+                Some((_, scattered)) if !delta => {
+                    let wi = unit_vector(scattered.direction());
+                    let pdf = |wo: &Vec3, wi: &Vec3| {
+                        rec.material.eval(&rec, wo, wi).map_or(0.0, |(_, pdf)| pdf)
+                    };
+                    (pdf(&wo, &wi), pdf(&wi, &wo))
+                }
+                _ => (0.0, 0.0),
+            };
+            vertex.rec = Some(rec);
+            path.push(vertex);
+
+            let (attenuation, scattered) = match scatter {
+                Some(scatter) if path.len() < max_vertices => scatter,
+                _ => break,
+            };
+            beta = beta * attenuation;
+            pdf_dir = pdfs.0;
+            let n = path.len();
+            path[n - 2].pdf_rev = Bdpt::convert(pdfs.1, &path[n - 1], &path[n - 2]);
+            ray = scattered;
+        }
+        None
+    }
+
+    fn light_path(&self, world: &dyn Hittable) -> Vec<Vertex> {
+        let mut path = Vec::new();
+        let (index, light, pick_pdf) = match pick_light(&self.lights) {
+            Some(pick) => pick,
+            None => return path,
+        };
+        let (p, n) = light.sample_point();
+        let pdf_origin = pick_pdf / light.area();
+        let (direction, pdf_dir) = light.sample_direction(&n);
+        let emit = light.radiance(&n, &direction);
+
+        path.push(Vertex::light(index, p, n, emit / pdf_origin, pdf_origin));
+        if pdf_dir > 0.0 {
+            let beta = emit * dot(&n, &direction).abs() / (pdf_origin * pdf_dir);
+            let ray = Ray::new(p, direction);
+            self.walk(world, ray, beta, pdf_dir, self.max_depth + 1, &mut path);
+        }
+        path
+    }
+
+    // Contribution of the strategy with `s` light and `t` camera vertices,
+    // and the film coordinates to splat it at when `t == 1`. Without
+    // `light_tracing` the strategies with `t == 1` are not in use.
+    fn connect(
+        &self,
+        world: &dyn Hittable,
+        light_path: &[Vertex],
+        camera_path: &[Vertex],
+        (s, t): (usize, usize),
+        light_tracing: bool,
+    ) -> (Color, Option<(f64, f64)>) {
+        let black = (Color::default(), None);
+        let mut sampled = None;
+        let mut film = None;
+
+        let l = if s == 0 {
+            // The camera path hit an emitter
+            let pt = &camera_path[t - 1];
+            match &pt.rec {
+                Some(rec) => pt.beta * rec.material.emitted_at(rec),
+                None => return black,
+            }
+        } else if t == 1 {
+            // Connect the light path to a point on the lens
+            let qs = &light_path[s - 1];
+            if !qs.connectible() {
+                return black;
+            }
+            let lens = self.camera.sample_lens();
+            let d = qs.p - lens;
+            let dist = d.length();
+            let w = d / dist;
+            let importance = self.camera_importance(&lens, &w);
+            if importance == 0.0 || !Bdpt::visible(world, &qs.p, &lens) {
+                return black;
+            }
+            film = self.camera.film_coordinates(&lens, &qs.p);
+            // Density over solid angle at qs of picking this lens point
+            let cos = dot(&w, &self.camera.forward());
+            let pdf = dist * dist / (cos * self.lens_area());
+            let camera = Vertex::camera(lens, Color::new(1.0, 1.0, 1.0) * (importance / pdf));
+            let l = qs.beta * Bdpt::f(qs, &lens) * camera.beta * dot(&w, &qs.n).abs();
+            sampled = Some(camera);
+            l
+        } else if s == 1 {
+            // Connect the camera path to a new point on a light
+            let pt = &camera_path[t - 1];
+            if !pt.connectible() {
+                return black;
+            }
+            let (index, light, pick_pdf) = match pick_light(&self.lights) {
+                Some(pick) => pick,
+                None => return black,
+            };
+            let (p, n) = light.sample_point();
+            let d = p - pt.p;
+            let dist = d.length();
+            let w = d / dist;
+            let cos_light = dot(&n, &w).abs();
+            let emit = light.radiance(&n, &-w);
+            if cos_light == 0.0 || emit.near_zero() || !Bdpt::visible(world, &pt.p, &p) {
+                return black;
+            }
+            // Density over solid angle at pt of picking this light point
+            let pdf = dist * dist / (cos_light * light.area());
+            let pdf_origin = pick_pdf / light.area();
+            let vertex = Vertex::light(index, p, n, emit / (pdf * pick_pdf), pdf_origin);
+            let l = pt.beta * Bdpt::f(pt, &p) * vertex.beta * dot(&w, &pt.n).abs();
+            sampled = Some(vertex);
+            l
+        } else {
+            let (qs, pt) = (&light_path[s - 1], &camera_path[t - 1]);
+            if !qs.connectible() || !pt.connectible() {
+                return black;
+            }
+            let d = pt.p - qs.p;
+            let dist2 = d.length_squared();
+            let g = dot(&qs.n, &d).abs() * dot(&pt.n, &d).abs() / (dist2 * dist2);
+            let l = qs.beta * Bdpt::f(qs, &pt.p) * Bdpt::f(pt, &qs.p) * pt.beta * g;
+            if l.near_zero() || !Bdpt::visible(world, &qs.p, &pt.p) {
+                return black;
+            }
+            l
+        };
+
+        if l.near_zero() {
+            return black;
+        }
+        let weight = self.mis_weight(
+            light_path,
+            camera_path,
+            sampled.as_ref(),
+            (s, t),
+            light_tracing,
+        );
+        (weight * l, film)
+    }
+
+    // Balance heuristic weight of a strategy, comparing the density of its
+    // path with the densities of all other strategies for the same path
+    fn mis_weight(
+        &self,
+        light_path: &[Vertex],
+        camera_path: &[Vertex],
+        sampled: Option<&Vertex>,
+        (s, t): (usize, usize),
+        light_tracing: bool,
+    ) -> f64 {
+        if s + t == 2 {
+            return 1.0;
+        }
+        // Only camera paths find emitters that are not among the lights
+        if s == 0 && camera_path[t - 1].light.is_none() {
+            return 1.0;
+        }
+
+        // The vertices next to the connection, with a freshly sampled
+        // endpoint standing in for the path's own
+        let qs = match (s, sampled) {
+            (0, _) => None,
+            (1, Some(v)) => Some(v),
+            _ => Some(&light_path[s - 1]),
+        };
+        let pt = match (t, sampled) {
+            (1, Some(v)) => v,
+            _ => &camera_path[t - 1],
+        };
+        let qs_minus = if s > 1 {
+            Some(&light_path[s - 2])
+        } else {
+            None
+        };
+        let pt_minus = if t > 1 {
+            Some(&camera_path[t - 2])
+        } else {
+            None
+        };
+
+        // Copies of the densities and flags, as the connection changes some
+        let mut camera: Vec<(f64, f64, bool)> = camera_path[..t]
+            .iter()
+            .map(|v| (v.pdf_fwd, v.pdf_rev, v.delta))
+            .collect();
+        let mut light: Vec<(f64, f64, bool)> = light_path[..s]
+            .iter()
+            .map(|v| (v.pdf_fwd, v.pdf_rev, v.delta))
+            .collect();
+        if t == 1 {
+            camera[0] = (pt.pdf_fwd, pt.pdf_rev, pt.delta);
+        }
+        if let (1, Some(q)) = (s, qs) {
+            light[0] = (q.pdf_fwd, q.pdf_rev, q.delta);
+        }
+
+        camera[t - 1].2 = false;
+        camera[t - 1].1 = match qs {
+            Some(q) => self.pdf(q, qs_minus, pt),
+            None => self.pdf_light_origin(pt),
+        };
+        if let Some(pm) = pt_minus {
+            camera[t - 2].1 = match qs {
+                Some(q) => self.pdf(pt, Some(q), pm),
+                None => self.pdf_light(pt, pm),
+            };
+        }
+        if let Some(q) = qs {
+            light[s - 1].2 = false;
+            light[s - 1].1 = self.pdf(pt, pt_minus, q);
+            if let Some(qm) = qs_minus {
+                light[s - 2].1 = self.pdf(q, Some(pt), qm);
+            }
+        }
+
+        let mut sum = 0.0;
+        let mut ri = 1.0;
+        for i in (1..t).rev() {
+            ri *= remap0(camera[i].1) / remap0(camera[i].0);
+            // With i == 1 this is light tracing
+            if !camera[i].2 && !camera[i - 1].2 && (i > 1 || light_tracing) {
+                sum += ri;
+            }
+        }
+        ri = 1.0;
+        for i in (0..s).rev() {
+            ri *= remap0(light[i].1) / remap0(light[i].0);
+            // Area lights are never delta
+            let delta_before = i > 0 && light[i - 1].2;
+            if !light[i].2 && !delta_before {
+                sum += ri;
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+
+    // Light along `r`, adding light paths that reach the camera to `splats`
+    // with the mask applied, or leaving them out without splats
+    fn trace(
+        &self,
+        r: &Ray,
+        world: &dyn Hittable,
+        splats: Option<(&SplatBuffer, &Color)>,
+    ) -> Color {
+        let direction = unit_vector(r.direction());
+        let mut camera_path = vec![Vertex::camera(r.origin(), Color::new(1.0, 1.0, 1.0))];
+        let pdf_dir = self.camera_pdf_dir(&r.origin(), &direction);
+        let ray = Ray::new(r.origin(), direction);
+        let escaped = self.walk(
+            world,
+            ray,
+            Color::new(1.0, 1.0, 1.0),
+            pdf_dir,
+            self.max_depth + 2,
+            &mut camera_path,
+        );
+        let light_path = self.light_path(world);
+
+        let mut l = escaped.map_or(Color::default(), |(ray, beta)| {
+            beta * self.background.radiance(&ray)
+        });
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                let depth = s as isize + t as isize - 2;
+                if (s == 1 && t == 1) || depth < 0 || depth > self.max_depth as isize {
+                    continue;
+                }
+                if t == 1 && splats.is_none() {
+                    continue;
+                }
+                let (contribution, film) =
+                    self.connect(world, &light_path, &camera_path, (s, t), splats.is_some());
+                if t > 1 {
+                    l += contribution;
+                } else if let (Some((fs, ft)), Some((splats, mask))) = (film, splats) {
+                    splats.add(fs, ft, *mask * contribution);
+                }
+            }
+        }
+        l
+    }
+}
+
+impl Integrator for Bdpt {
+    fn li(&self, r: &Ray, world: &dyn Hittable) -> Color {
+        self.trace(r, world, None)
+    }
+
+    fn li_splat(&self, r: &Ray, world: &dyn Hittable, splats: &SplatBuffer, mask: &Color) -> Color {
+        self.trace(r, world, Some((splats, mask)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrator::PathTracer;
+    use crate::test_scenes::{box_camera, box_scene, pixel_means, seeded};
+
+    #[test]
+    fn matches_path_tracer_test() {
+        let (world, light) = box_scene();
+        let settings = RenderSettings {
+            image_width: 6,
+            image_height: 4,
+            samples_per_pixel: 1,
+        };
+        let camera = box_camera(&settings);
+        let bdpt = Bdpt::new(camera.clone(), &settings, vec![light], 4);
+        let path_tracer = PathTracer::new(5).with_roulette_depth(usize::MAX);
+        let splats = SplatBuffer::new(&settings);
+        let white = Color::new(1.0, 1.0, 1.0);
+
+        // Pixel means summed over the image
+        let n = 500;
+        let sampled = RenderSettings {
+            samples_per_pixel: n,
+            ..settings
+        };
+        let total = |li: &dyn Fn(&Ray) -> Color| {
+            pixel_means(&camera, &sampled, li)
+                .iter()
+                .map(|c| c.x())
+                .sum::<f64>()
+        };
+        let path_mean = seeded(1, || total(&|r| path_tracer.li(r, &world)));
+        let unsplatted_mean = seeded(2, || total(&|r| bdpt.li(r, &world)));
+        let mut bdpt_mean = seeded(3, || total(&|r| bdpt.li_splat(r, &world, &splats, &white)));
+        for j in 0..settings.image_height {
+            for i in 0..settings.image_width {
+                bdpt_mean += splats.get(i, j).x() / n as f64;
+            }
+        }
+        // Without splats, the remaining strategies make up for light tracing
+        for (name, mean) in [
+            ("bdpt", bdpt_mean),
+            ("bdpt without splats", unsplatted_mean),
+        ] {
+            assert!(
+                (mean - path_mean).abs() < 0.05 * path_mean,
+                "{} {} path tracer {}",
+                name,
+                mean,
+                path_mean
+            );
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use crate::vec3::*;
+
+/// Spectral radiance of a blackbody from Planck's law, in W / (sr m^2 nm)
+pub fn planck(wavelength_nm: f64, kelvin: f64) -> f64 {
+    const C: f64 = 299_792_458.0;
+    const H: f64 = 6.626_070_15e-34;
+    const KB: f64 = 1.380_649e-23;
+
+    if kelvin <= 0.0 {
+        return 0.0;
+    }
+    let l = wavelength_nm * 1e-9;
+    let radiance = 2.0 * H * C * C / (l.powi(5) * ((H * C / (l * KB * kelvin)).exp() - 1.0));
+    radiance * 1e-9
+}
+
+/// CIE 1931 2-degree color matching functions, using the multi-lobe
+/// Gaussian fit of Wyman, Sloan and Shirley (2013)
+pub fn cie_xyz(wavelength_nm: f64) -> Vec3 {
+    let g = |x: f64, mu: f64, sigma1: f64, sigma2: f64| {
+        let t = (x - mu) / if x < mu { sigma1 } else { sigma2 };
+        (-0.5 * t * t).exp()
+    };
+    let l = wavelength_nm;
+
+    Vec3::new(
+        1.056 * g(l, 599.8, 37.9, 31.0) + 0.362 * g(l, 442.0, 16.0, 26.7)
+            - 0.065 * g(l, 501.1, 20.4, 26.2),
+        0.821 * g(l, 568.8, 46.9, 40.5) + 0.286 * g(l, 530.9, 16.3, 31.1),
+        1.217 * g(l, 437.0, 11.8, 36.0) + 0.681 * g(l, 459.0, 26.0, 13.8),
+    )
+}
+
+/// Integrates the blackbody spectrum against the color matching functions
+pub fn blackbody_xyz(kelvin: f64) -> Vec3 {
+    let step = 5.0;
+    (0..=94)
+        .map(|i| {
+            let l = 360.0 + step * i as f64;
+            planck(l, kelvin) * step * cie_xyz(l)
+        })
+        .fold(Vec3::default(), |acc, xyz| acc + xyz)
+}
+
+/// CIE XYZ to linear sRGB (D65 white point)
+pub fn xyz_to_rgb(xyz: &Vec3) -> Color {
+    let (x, y, z) = (xyz.x(), xyz.y(), xyz.z());
+    Color::new(
+        3.240_454 * x - 1.537_139 * y - 0.498_531 * z,
+        -0.969_266 * x + 1.876_011 * y + 0.041_556 * z,
+        0.055_643 * x - 0.204_026 * y + 1.057_225 * z,
+    )
+}
+
+/// Linear sRGB color of a blackbody, normalized to unit luminance. Colors
+/// outside the sRGB gamut are clipped.
+pub fn blackbody_color(kelvin: f64) -> Color {
+    let xyz = blackbody_xyz(kelvin);
+    if xyz.y() <= 0.0 {
+        return Color::default();
+    }
+    xyz_to_rgb(&(xyz / xyz.y())).max(&Color::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wien_peak_test() {
+        // Wien's displacement law puts the 5000 K peak near 579.6 nm
+        let peak = (400_i32..800)
+            .max_by(|&a, &b| planck(a as f64, 5000.0).total_cmp(&planck(b as f64, 5000.0)))
+            .unwrap();
+        assert!((peak - 580).abs() <= 1);
+    }
+
+    #[test]
+    fn blackbody_color_test() {
+        let white = blackbody_color(6500.0);
+        assert!((white.x() - white.z()).abs() < 0.1);
+        assert!((white.y() - 1.0).abs() < 0.1);
+
+        let fire = blackbody_color(1500.0);
+        assert!(fire.x() > fire.y() && fire.y() > fire.z());
+
+        assert_eq!(blackbody_color(0.0), Color::default());
+    }
+
+    #[test]
+    fn hotter_is_brighter_test() {
+        assert!(blackbody_xyz(2000.0).y() > 10.0 * blackbody_xyz(1000.0).y());
+    }
+}
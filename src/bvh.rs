@@ -0,0 +1,309 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::aabb::*;
+use crate::hittable::*;
+use crate::ray::Ray;
+use crate::vec3::*;
+
+/// Bounding volume hierarchy over a set of hittables.
+///
+/// Objects are split at the median of their centroids along the longest
+/// axis of the node's bounds.
+pub struct BvhNode {
+    left: Arc<dyn Hittable + Send + Sync>,
+    right: Arc<dyn Hittable + Send + Sync>,
+    bbox: Aabb,
+}
+
+impl BvhNode {
+    /// Panics if `objects` is empty or contains an unbounded object
+    pub fn new(objects: Vec<Arc<dyn Hittable + Send + Sync>>) -> BvhNode {
+        assert!(!objects.is_empty(), "cannot build a BVH without objects");
+
+        let boxes: Vec<Aabb> = objects
+            .iter()
+            .map(|object| {
+                object
+                    .bounding_box()
+                    .expect("no bounding box in BvhNode constructor")
+            })
+            .collect();
+        let bounds = boxes
+            .iter()
+            .skip(1)
+            .fold(boxes[0], |acc, b| surrounding_box(&acc, b));
+        let axis = bounds.longest_axis();
+
+        let mut order: Vec<usize> = (0..objects.len()).collect();
+        order.sort_by(|&a, &b| boxes[a].centroid()[axis].total_cmp(&boxes[b].centroid()[axis]));
+        let mut sorted: Vec<_> = order.into_iter().map(|i| objects[i].clone()).collect();
+
+        let (left, right): (
+            Arc<dyn Hittable + Send + Sync>,
+            Arc<dyn Hittable + Send + Sync>,
+        ) = match sorted.len() {
+            1 => (sorted[0].clone(), sorted[0].clone()),
+            2 => (sorted[0].clone(), sorted[1].clone()),
+            n => {
+                let upper = sorted.split_off(n / 2);
+                (
+                    Arc::new(BvhNode::new(sorted)),
+                    Arc::new(BvhNode::new(upper)),
+                )
+            }
+        };
+
+        BvhNode {
+            left,
+            right,
+            bbox: bounds,
+        }
+    }
+}
+
+/// Puts the bounded objects of `list` into a BVH and keeps the unbounded
+/// ones, such as planes, next to it
+pub fn accelerate(list: HittableList) -> HittableList {
+    let (bounded, unbounded): (Vec<_>, Vec<_>) = list
+        .objects
+        .into_iter()
+        .partition(|object| object.bounding_box().is_some());
+
+    let mut world = HittableList { objects: unbounded };
+    if !bounded.is_empty() {
+        world.add(Arc::new(BvhNode::new(bounded)));
+    }
+    world
+}
+
+impl From<HittableList> for BvhNode {
+    fn from(list: HittableList) -> BvhNode {
+        BvhNode::new(list.objects)
+    }
+}
+
+impl Hittable for BvhNode {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        if !self.bbox.hit(r, t_min, t_max) {
+            return None;
+        }
+
+        let hit_left = self.left.hit(r, t_min, t_max);
+        let closest = hit_left.as_ref().map_or(t_max, |rec| rec.t);
+        let hit_right = self.right.hit(r, t_min, closest);
+
+        hit_right.or(hit_left)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bbox)
+    }
+
+    fn intervals(&self, r: &Ray) -> Vec<HitInterval> {
+        if !self.bbox.hit(r, f64::NEG_INFINITY, f64::INFINITY) {
+            return Vec::new();
+        }
+        if Arc::ptr_eq(&self.left, &self.right) {
+            return self.left.intervals(r);
+        }
+
+        let mut intervals = self.left.intervals(r);
+        intervals.extend(self.right.intervals(r));
+        merge_intervals(intervals)
+    }
+}
+
+// Interior nodes have count == 0, their left child follows them and `offset`
+// is the right child; leaves cover primitives[offset..offset + count]
+struct FlatNode {
+    min: [f32; 3],
+    max: [f32; 3],
+    offset: u32,
+    count: u32,
+}
+
+impl FlatNode {
+    fn bounds(&self) -> Aabb {
+        let v = |a: [f32; 3]| Vec3::new(a[0] as f64, a[1] as f64, a[2] as f64);
+        Aabb::new(v(self.min), v(self.max))
+    }
+
+    fn set_bounds(&mut self, bbox: &Aabb) {
+        // Round outwards so the f32 box still contains every primitive
+        let down = |v: f64| match v as f32 {
+            x if x as f64 > v => x.next_down(),
+            x => x,
+        };
+        let up = |v: f64| match v as f32 {
+            x if (x as f64) < v => x.next_up(),
+            x => x,
+        };
+        let (lo, hi) = (bbox.minimum, bbox.maximum);
+        self.min = [down(lo.x()), down(lo.y()), down(lo.z())];
+        self.max = [up(hi.x()), up(hi.y()), up(hi.z())];
+    }
+}
+
+fn union_bounds<T, F>(primitives: &[T], bounds: &F) -> Aabb
+where
+    F: Fn(&T) -> Aabb,
+{
+    primitives
+        .iter()
+        .skip(1)
+        .fold(bounds(&primitives[0]), |acc, p| {
+            surrounding_box(&acc, &bounds(p))
+        })
+}
+
+/// Compact BVH stored as a flat array of nodes over primitives kept by the
+/// caller.
+///
+/// Shapes made of many small primitives, such as point clouds and meshes,
+/// use it instead of wrapping every primitive in its own hittable.
+#[derive(Default)]
+pub struct FlatBvh {
+    nodes: Vec<FlatNode>,
+}
+
+impl FlatBvh {
+    const LEAF_SIZE: usize = 4;
+
+    /// Reorders `primitives` so that every leaf covers a contiguous range
+    pub fn build<T, F>(primitives: &mut [T], bounds: F) -> FlatBvh
+    where
+        F: Fn(&T) -> Aabb,
+    {
+        let mut nodes = Vec::new();
+        if !primitives.is_empty() {
+            FlatBvh::build_node(primitives, 0, &bounds, &mut nodes);
+        }
+        FlatBvh { nodes }
+    }
+
+    fn build_node<T, F>(primitives: &mut [T], offset: usize, bounds: &F, nodes: &mut Vec<FlatNode>)
+    where
+        F: Fn(&T) -> Aabb,
+    {
+        let bbox = union_bounds(primitives, bounds);
+        let index = nodes.len();
+        let mut node = FlatNode {
+            min: [0.0; 3],
+            max: [0.0; 3],
+            offset: offset as u32,
+            count: primitives.len() as u32,
+        };
+        node.set_bounds(&bbox);
+        nodes.push(node);
+        if primitives.len() <= FlatBvh::LEAF_SIZE {
+            return;
+        }
+
+        let axis = bbox.longest_axis();
+        let mid = primitives.len() / 2;
+        primitives.select_nth_unstable_by(mid, |a, b| {
+            bounds(a).centroid()[axis].total_cmp(&bounds(b).centroid()[axis])
+        });
+        let (left, right) = primitives.split_at_mut(mid);
+        FlatBvh::build_node(left, offset, bounds, nodes);
+        let right_index = nodes.len();
+        FlatBvh::build_node(right, offset + mid, bounds, nodes);
+
+        nodes[index].offset = right_index as u32;
+        nodes[index].count = 0;
+    }
+
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.nodes.first().map(|root| root.bounds())
+    }
+
+    /// Updates the node bounds after primitives moved, keeping the tree
+    /// built for the old positions.
+    ///
+    /// `primitives` must be in the order `build` left them. Much cheaper
+    /// than a rebuild, but traversal slows down as the tree drifts away from
+    /// the geometry, so rebuild after large changes.
+    pub fn refit<T, F>(&mut self, primitives: &[T], bounds: F)
+    where
+        F: Fn(&T) -> Aabb,
+    {
+        // Children always come after their parent
+        for index in (0..self.nodes.len()).rev() {
+            let node = &self.nodes[index];
+            let bbox = if node.count == 0 {
+                surrounding_box(
+                    &self.nodes[index + 1].bounds(),
+                    &self.nodes[node.offset as usize].bounds(),
+                )
+            } else {
+                let start = node.offset as usize;
+                union_bounds(&primitives[start..start + node.count as usize], &bounds)
+            };
+            self.nodes[index].set_bounds(&bbox);
+        }
+    }
+
+    /// Closest hit along `r`, where `hit_leaf` intersects a range of
+    /// primitives against the ray up to the given `t_max`
+    pub fn hit<F>(&self, r: &Ray, t_min: f64, t_max: f64, mut hit_leaf: F) -> Option<HitRecord>
+    where
+        F: FnMut(Range<usize>, f64) -> Option<HitRecord>,
+    {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut closest: Option<HitRecord> = None;
+        let mut closest_so_far = t_max;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.bounds().hit(r, t_min, closest_so_far) {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.offset as usize);
+                stack.push(index + 1);
+                continue;
+            }
+            let start = node.offset as usize;
+            if let Some(rec) = hit_leaf(start..start + node.count as usize, closest_so_far) {
+                closest_so_far = rec.t;
+                closest = Some(rec);
+            }
+        }
+        closest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+
+    #[test]
+    fn matches_list_test() {
+        let material = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let mut list = HittableList::default();
+        for i in 0..10 {
+            list.add(Arc::new(Sphere::new(
+                Point3::new(i as f64 * 0.7, (i % 3) as f64, -(i as f64)),
+                0.5,
+                material.clone(),
+            )));
+        }
+        let bvh = BvhNode::new(list.objects.clone());
+
+        for i in 0..10 {
+            let r = Ray::new(
+                Point3::new(0.0, 0.5, 5.0),
+                Vec3::new(i as f64 * 0.3, -0.1, -1.0),
+            );
+            let expected = list.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t);
+            let actual = bvh.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t);
+            assert_eq!(expected, actual);
+        }
+    }
+}
@@ -1,6 +1,173 @@
 use crate::ray::Ray;
 use crate::vec3::*;
 
+/// Brown-Conrady radial and tangential lens distortion, with OpenCV's
+/// rational, thin prism and tilted sensor extensions.
+///
+/// The coefficients follow the OpenCV convention, so the output of a camera
+/// calibration (`k1, k2, p1, p2, k3` and optionally `k4, k5, k6, s1, s2, s3,
+/// s4, tau_x, tau_y`) can be used directly. They act on normalized image
+/// coordinates, i.e. positions on the image plane at unit distance from the
+/// pinhole with the y axis pointing down.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct LensDistortion {
+    pub k1: f64,
+    pub k2: f64,
+    pub k3: f64,
+    pub p1: f64,
+    pub p2: f64,
+    /// Denominator of the rational model
+    pub k4: f64,
+    pub k5: f64,
+    pub k6: f64,
+    /// Thin prism terms
+    pub s1: f64,
+    pub s2: f64,
+    pub s3: f64,
+    pub s4: f64,
+    /// Sensor tilt in radians
+    pub tau_x: f64,
+    pub tau_y: f64,
+}
+
+impl LensDistortion {
+    pub fn new(k1: f64, k2: f64, k3: f64, p1: f64, p2: f64) -> LensDistortion {
+        LensDistortion {
+            k1,
+            k2,
+            k3,
+            p1,
+            p2,
+            ..LensDistortion::default()
+        }
+    }
+
+    pub fn with_rational(mut self, k4: f64, k5: f64, k6: f64) -> LensDistortion {
+        (self.k4, self.k5, self.k6) = (k4, k5, k6);
+        self
+    }
+
+    pub fn with_thin_prism(mut self, s1: f64, s2: f64, s3: f64, s4: f64) -> LensDistortion {
+        (self.s1, self.s2, self.s3, self.s4) = (s1, s2, s3, s4);
+        self
+    }
+
+    pub fn with_tilt(mut self, tau_x: f64, tau_y: f64) -> LensDistortion {
+        (self.tau_x, self.tau_y) = (tau_x, tau_y);
+        self
+    }
+
+    /// Builds the distortion from an OpenCV coefficient vector
+    /// `[k1, k2, p1, p2, k3, k4, k5, k6, s1, s2, s3, s4, tau_x, tau_y]`.
+    /// Trailing coefficients may be omitted.
+    pub fn from_opencv(coeffs: &[f64]) -> LensDistortion {
+        let c = |i: usize| coeffs.get(i).copied().unwrap_or(0.0);
+        LensDistortion::new(c(0), c(1), c(4), c(2), c(3))
+            .with_rational(c(5), c(6), c(7))
+            .with_thin_prism(c(8), c(9), c(10), c(11))
+            .with_tilt(c(12), c(13))
+    }
+
+    /// Maps undistorted normalized coordinates to distorted ones
+    pub fn distort(&self, x: f64, y: f64) -> (f64, f64) {
+        let (radial, dx, dy) = self.terms(x, y);
+        let (xd, yd) = (x * radial + dx, y * radial + dy);
+        project(&self.tilt(), xd, yd)
+    }
+
+    /// Inverse of `distort`, found by fixed point iteration
+    pub fn undistort(&self, xd: f64, yd: f64) -> (f64, f64) {
+        let (xd, yd) = project(&invert(&self.tilt()), xd, yd);
+        let (mut x, mut y) = (xd, yd);
+        for _ in 0..20 {
+            let (radial, dx, dy) = self.terms(x, y);
+            x = (xd - dx) / radial;
+            y = (yd - dy) / radial;
+        }
+
+        (x, y)
+    }
+
+    // Radial factor and tangential plus thin prism offsets at (x, y)
+    fn terms(&self, x: f64, y: f64) -> (f64, f64, f64) {
+        let r2 = x * x + y * y;
+        let radial = (1.0 + r2 * (self.k1 + r2 * (self.k2 + r2 * self.k3)))
+            / (1.0 + r2 * (self.k4 + r2 * (self.k5 + r2 * self.k6)));
+        let dx =
+            2.0 * self.p1 * x * y + self.p2 * (r2 + 2.0 * x * x) + r2 * (self.s1 + r2 * self.s2);
+        let dy =
+            self.p1 * (r2 + 2.0 * y * y) + 2.0 * self.p2 * x * y + r2 * (self.s3 + r2 * self.s4);
+        (radial, dx, dy)
+    }
+
+    // Projection onto the tilted sensor, as in OpenCV's
+    // computeTiltProjectionMatrix
+    fn tilt(&self) -> [[f64; 3]; 3] {
+        let (sx, cx) = self.tau_x.sin_cos();
+        let (sy, cy) = self.tau_y.sin_cos();
+        let rot_x = [[1.0, 0.0, 0.0], [0.0, cx, sx], [0.0, -sx, cx]];
+        let rot_y = [[cy, 0.0, -sy], [0.0, 1.0, 0.0], [sy, 0.0, cy]];
+        let rot = multiply(&rot_y, &rot_x);
+        let proj_z = [
+            [rot[2][2], 0.0, -rot[0][2]],
+            [0.0, rot[2][2], -rot[1][2]],
+            [0.0, 0.0, 1.0],
+        ];
+        multiply(&proj_z, &rot)
+    }
+}
+
+fn multiply(a: &[[f64; 3]; 3], b: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let mut m = [[0.0; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+fn invert(m: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let cofactor = |i: usize, j: usize| {
+        let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+        let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let det: f64 = (0..3).map(|j| m[0][j] * cofactor(0, j)).sum();
+    let mut inverse = [[0.0; 3]; 3];
+    for (i, row) in inverse.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = cofactor(j, i) / det;
+        }
+    }
+    inverse
+}
+
+// Applies a homography to the point (x, y)
+fn project(m: &[[f64; 3]; 3], x: f64, y: f64) -> (f64, f64) {
+    let w = m[2][0] * x + m[2][1] * y + m[2][2];
+    (
+        (m[0][0] * x + m[0][1] * y + m[0][2]) / w,
+        (m[1][0] * x + m[1][1] * y + m[1][2]) / w,
+    )
+}
+
+/// Lateral chromatic aberration, modelled as a per-channel magnification of
+/// the image relative to the green channel.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ChromaticAberration {
+    pub scale: [f64; 3],
+}
+
+impl ChromaticAberration {
+    pub fn new(red_scale: f64, blue_scale: f64) -> ChromaticAberration {
+        ChromaticAberration {
+            scale: [red_scale, 1.0, blue_scale],
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Camera {
     origin: Point3,
     lower_left_corner: Point3,
@@ -8,8 +175,12 @@ pub struct Camera {
     vertical: Vec3,
     u: Vec3,
     v: Vec3,
-    _w: Vec3,
+    w: Vec3,
     lens_radius: f64,
+    viewport_width: f64,
+    viewport_height: f64,
+    distortion: Option<LensDistortion>,
+    chromatic_aberration: Option<ChromaticAberration>,
 }
 
 impl Camera {
@@ -45,12 +216,41 @@ impl Camera {
             vertical,
             u,
             v,
-            _w: w,
+            w,
             lens_radius,
+            viewport_width,
+            viewport_height,
+            distortion: None,
+            chromatic_aberration: None,
         }
     }
 
+    pub fn with_distortion(mut self, distortion: LensDistortion) -> Camera {
+        self.distortion = Some(distortion);
+        self
+    }
+
+    pub fn with_chromatic_aberration(mut self, aberration: ChromaticAberration) -> Camera {
+        self.chromatic_aberration = Some(aberration);
+        self
+    }
+
+    /// Whether each color channel needs its own ray
+    pub fn has_chromatic_aberration(&self) -> bool {
+        self.chromatic_aberration.is_some()
+    }
+
     pub fn get_ray(&self, s: f64, t: f64) -> Ray {
+        self.get_channel_ray(s, t, 1)
+    }
+
+    /// Ray for a single color channel (0 = red, 1 = green, 2 = blue)
+    pub fn get_channel_ray(&self, s: f64, t: f64, channel: usize) -> Ray {
+        let magnification = self
+            .chromatic_aberration
+            .map_or(1.0, |aberration| aberration.scale[channel]);
+        let (s, t) = self.undistort(s, t, magnification);
+
         let rd = self.lens_radius * Vec3::random_in_unit_disk();
         let offset = self.u * rd.x() + self.v * rd.y();
 
@@ -59,4 +259,150 @@ impl Camera {
             self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
         )
     }
+
+    /// Direction the camera looks in
+    pub fn forward(&self) -> Vec3 {
+        -self.w
+    }
+
+    /// Zero for a pinhole camera
+    pub fn lens_area(&self) -> f64 {
+        std::f64::consts::PI * self.lens_radius * self.lens_radius
+    }
+
+    /// Area covered by film coordinates in [0, 1] on a plane at unit
+    /// distance
+    pub fn film_area(&self) -> f64 {
+        self.viewport_width * self.viewport_height
+    }
+
+    /// A uniformly distributed point on the lens
+    pub fn sample_lens(&self) -> Point3 {
+        let rd = self.lens_radius * Vec3::random_in_unit_disk();
+        self.origin + self.u * rd.x() + self.v * rd.y()
+    }
+
+    /// Film coordinates, as given to `get_ray`, of the ray from the point
+    /// `lens` on the lens through `p`, or `None` if `p` is not in front of
+    /// the camera. Lens distortion and chromatic aberration are ignored.
+    pub fn film_coordinates(&self, lens: &Point3, p: &Point3) -> Option<(f64, f64)> {
+        let d = *p - *lens;
+        let along = dot(&d, &self.w);
+        if along >= 0.0 {
+            return None;
+        }
+        // Where the ray crosses the plane in focus
+        let focus = *lens + dot(&(self.lower_left_corner - *lens), &self.w) / along * d;
+        let rel = focus - self.lower_left_corner;
+        Some((
+            dot(&rel, &self.horizontal) / self.horizontal.length_squared(),
+            dot(&rel, &self.vertical) / self.vertical.length_squared(),
+        ))
+    }
+
+    // Maps a position on the film to where the ideal pinhole image of the
+    // same scene point would be
+    fn undistort(&self, s: f64, t: f64, magnification: f64) -> (f64, f64) {
+        if self.distortion.is_none() && magnification == 1.0 {
+            return (s, t);
+        }
+
+        let xd = (s - 0.5) * self.viewport_width / magnification;
+        let yd = (0.5 - t) * self.viewport_height / magnification;
+        let (x, y) = match self.distortion {
+            Some(distortion) => distortion.undistort(xd, yd),
+            None => (xd, yd),
+        };
+
+        (
+            x / self.viewport_width + 0.5,
+            0.5 - y / self.viewport_height,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_opencv_test() {
+        let d = LensDistortion::from_opencv(&[0.1, -0.2, 0.01, 0.02, 0.3]);
+        assert_eq!(d, LensDistortion::new(0.1, -0.2, 0.3, 0.01, 0.02));
+
+        let coeffs: Vec<f64> = (1..=14).map(|i| i as f64 / 100.0).collect();
+        let d = LensDistortion::from_opencv(&coeffs);
+        assert_eq!((d.k4, d.k5, d.k6), (0.06, 0.07, 0.08));
+        assert_eq!((d.s1, d.s2, d.s3, d.s4), (0.09, 0.1, 0.11, 0.12));
+        assert_eq!((d.tau_x, d.tau_y), (0.13, 0.14));
+        assert_eq!(LensDistortion::from_opencv(&coeffs[..8]).s1, 0.0);
+    }
+
+    #[test]
+    fn rational_prism_tilt_test() {
+        // On the x axis only the radial fraction and the prism terms act
+        let d = LensDistortion::new(0.1, 0.0, 0.0, 0.0, 0.0)
+            .with_rational(0.4, 0.0, 0.0)
+            .with_thin_prism(0.0, 0.0, 0.2, 0.0);
+        let (xd, yd) = d.distort(0.5, 0.0);
+        assert!((xd - 0.5 * 1.025 / 1.1).abs() < 1e-12);
+        assert!((yd - 0.2 * 0.25).abs() < 1e-12);
+
+        // Tilting about x leaves the center in place and scales y by the
+        // perspective division
+        let d = LensDistortion::default().with_tilt(0.1, 0.0);
+        assert_eq!(d.distort(0.0, 0.0), (0.0, 0.0));
+        let (_, yd) = d.distort(0.0, 0.2);
+        assert!((yd - 0.2 / (0.1f64.cos() - 0.2 * 0.1f64.sin())).abs() < 1e-12);
+    }
+
+    #[test]
+    fn undistort_test() {
+        let d = LensDistortion::new(-0.28, 0.07, 0.01, 0.001, -0.002);
+        let full = d
+            .with_rational(0.02, -0.01, 0.005)
+            .with_thin_prism(0.001, -0.0005, 0.002, 0.0003)
+            .with_tilt(0.02, -0.03);
+        for d in [d, full] {
+            let (xd, yd) = d.distort(0.3, -0.2);
+            let (x, y) = d.undistort(xd, yd);
+            assert!((x - 0.3).abs() < 1e-9);
+            assert!((y + 0.2).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn no_distortion_test() {
+        let cam = Camera::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            90.0,
+            1.0,
+            0.0,
+            1.0,
+        );
+        assert_eq!(cam.get_ray(0.5, 0.5).direction(), Vec3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn film_coordinates_test() {
+        let cam = Camera::new(
+            Point3::new(1.0, 2.0, 3.0),
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            40.0,
+            1.5,
+            0.5,
+            4.0,
+        );
+        for (s, t) in [(0.5, 0.5), (0.1, 0.8), (0.9, 0.2)] {
+            let r = cam.get_ray(s, t);
+            let (fs, ft) = cam.film_coordinates(&r.origin(), &r.at(3.0)).unwrap();
+            assert!((fs - s).abs() < 1e-9 && (ft - t).abs() < 1e-9);
+        }
+        assert!(cam
+            .film_coordinates(&Point3::new(1.0, 2.0, 3.0), &Point3::new(2.0, 4.0, 6.0))
+            .is_none());
+    }
 }
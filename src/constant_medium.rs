@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use crate::aabb::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::ray::*;
+use crate::vec3::*;
+
+/// Homogeneous participating medium such as fog or smoke, filling the inside
+/// of a closed boundary shape.
+///
+/// A ray passing through the medium scatters after a free-flight distance
+/// sampled from the density, so the volume plugs into `PathTracer` like any
+/// other surface.
+pub struct ConstantMedium {
+    boundary: Arc<dyn Hittable + Send + Sync>,
+    neg_inv_density: f64,
+    phase_function: Arc<dyn Material + Send + Sync>,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Arc<dyn Hittable + Send + Sync>, density: f64, albedo: Color) -> Self {
+        Self::with_phase_function(boundary, density, Arc::new(Isotropic::new(albedo)))
+    }
+
+    pub fn with_phase_function(
+        boundary: Arc<dyn Hittable + Send + Sync>,
+        density: f64,
+        phase_function: Arc<dyn Material + Send + Sync>,
+    ) -> Self {
+        ConstantMedium {
+            boundary,
+            neg_inv_density: -1.0 / density,
+            phase_function,
+        }
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        // Find where the ray enters and leaves the boundary, even if the
+        // origin is already inside
+        let entry = self.boundary.hit(r, f64::NEG_INFINITY, f64::INFINITY)?;
+        let exit = self.boundary.hit(r, entry.t + 0.0001, f64::INFINITY)?;
+
+        let t_enter = entry.t.max(t_min).max(0.0);
+        let t_exit = exit.t.min(t_max);
+        if t_enter >= t_exit {
+            return None;
+        }
+
+        let ray_length = r.direction().length();
+        let distance_inside_boundary = (t_exit - t_enter) * ray_length;
+        let hit_distance = self.neg_inv_density * random_double(0.0..1.0).ln();
+        if hit_distance > distance_inside_boundary {
+            return None;
+        }
+
+        let t = t_enter + hit_distance / ray_length;
+        Some(HitRecord {
+            t,
+            p: r.at(t),
+            // Normal and face are arbitrary inside a volume
+            normal: Vec3::new(1.0, 0.0, 0.0),
+            front_face: true,
+            material: self.phase_function.clone(),
+            ..Default::default()
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+
+    fn unit_sphere() -> Arc<dyn Hittable + Send + Sync> {
+        Arc::new(Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        ))
+    }
+
+    #[test]
+    fn scatters_inside_boundary_test() {
+        let medium = ConstantMedium::new(unit_sphere(), 10.0, Color::new(1.0, 1.0, 1.0));
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        for _ in 0..100 {
+            if let Some(rec) = medium.hit(&r, 0.001, f64::INFINITY) {
+                assert!(rec.t >= 4.0 && rec.t <= 6.0);
+            }
+        }
+    }
+
+    #[test]
+    fn thin_medium_test() {
+        let medium = ConstantMedium::new(unit_sphere(), 1e-12, Color::new(1.0, 1.0, 1.0));
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(medium.hit(&r, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn henyey_greenstein_mean_cosine_test() {
+        let g = 0.6;
+        let n = 10000;
+        let mean = (0..n)
+            .map(|i| HenyeyGreenstein::sample_cos_theta(g, (i as f64 + 0.5) / n as f64))
+            .sum::<f64>()
+            / n as f64;
+        assert!((mean - g).abs() < 1e-2);
+    }
+}
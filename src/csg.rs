@@ -0,0 +1,227 @@
+use std::sync::Arc;
+
+use crate::aabb::*;
+use crate::hittable::*;
+use crate::ray::Ray;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    /// The left object with the right one carved out of it
+    Difference,
+}
+
+impl CsgOp {
+    fn apply(&self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOp::Union => in_left || in_right,
+            CsgOp::Intersection => in_left && in_right,
+            CsgOp::Difference => in_left && !in_right,
+        }
+    }
+}
+
+/// Constructive solid geometry node combining two closed hittables.
+///
+/// Surfaces carved by a difference take the material of the right object.
+pub struct Csg {
+    op: CsgOp,
+    left: Arc<dyn Hittable + Send + Sync>,
+    right: Arc<dyn Hittable + Send + Sync>,
+}
+
+impl Csg {
+    pub fn new(
+        op: CsgOp,
+        left: Arc<dyn Hittable + Send + Sync>,
+        right: Arc<dyn Hittable + Send + Sync>,
+    ) -> Csg {
+        Csg { op, left, right }
+    }
+
+    pub fn union(
+        left: Arc<dyn Hittable + Send + Sync>,
+        right: Arc<dyn Hittable + Send + Sync>,
+    ) -> Csg {
+        Csg::new(CsgOp::Union, left, right)
+    }
+
+    pub fn intersection(
+        left: Arc<dyn Hittable + Send + Sync>,
+        right: Arc<dyn Hittable + Send + Sync>,
+    ) -> Csg {
+        Csg::new(CsgOp::Intersection, left, right)
+    }
+
+    pub fn difference(
+        left: Arc<dyn Hittable + Send + Sync>,
+        right: Arc<dyn Hittable + Send + Sync>,
+    ) -> Csg {
+        Csg::new(CsgOp::Difference, left, right)
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.intervals(r)
+            .into_iter()
+            .flat_map(|interval| [interval.enter, interval.exit])
+            .find(|rec| t_min <= rec.t && rec.t <= t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        match self.op {
+            CsgOp::Union => Some(surrounding_box(
+                &self.left.bounding_box()?,
+                &self.right.bounding_box()?,
+            )),
+            CsgOp::Intersection | CsgOp::Difference => self.left.bounding_box(),
+        }
+    }
+
+    fn intervals(&self, r: &Ray) -> Vec<HitInterval> {
+        // Sweep over the boundaries of both operands in order, tracking
+        // whether the ray is inside each of them
+        let mut events: Vec<(bool, HitRecord)> = Vec::new();
+        for (is_left, object) in [(true, &self.left), (false, &self.right)] {
+            for interval in object.intervals(r) {
+                events.push((is_left, interval.enter));
+                events.push((is_left, interval.exit));
+            }
+        }
+        events.sort_by(|a, b| a.1.t.total_cmp(&b.1.t));
+
+        let mut intervals = Vec::new();
+        let (mut in_left, mut in_right) = (false, false);
+        let mut enter: Option<HitRecord> = None;
+        for (is_left, mut rec) in events {
+            let inside_before = self.op.apply(in_left, in_right);
+            if is_left {
+                in_left = rec.front_face;
+            } else {
+                in_right = rec.front_face;
+            }
+            let inside_after = self.op.apply(in_left, in_right);
+            if inside_before == inside_after {
+                continue;
+            }
+
+            // Stored normals always face the incoming ray, so only the face
+            // flag changes when a boundary flips its role
+            rec.front_face = inside_after;
+            if inside_after {
+                enter = Some(rec);
+            } else if let Some(enter) = enter.take() {
+                // Coincident surfaces leave empty intervals behind
+                if rec.t > enter.t {
+                    intervals.push(HitInterval { enter, exit: rec });
+                }
+            }
+        }
+
+        intervals
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+    use crate::vec3::*;
+
+    fn sphere(x: f64) -> Arc<dyn Hittable + Send + Sync> {
+        Arc::new(Sphere::new(
+            Point3::new(x, 0.0, 0.0),
+            1.0,
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        ))
+    }
+
+    fn ray() -> Ray {
+        Ray::new(Point3::new(-5.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0))
+    }
+
+    fn spans(object: &dyn Hittable) -> Vec<(f64, f64)> {
+        object
+            .intervals(&ray())
+            .iter()
+            .map(|i| (i.enter.t, i.exit.t))
+            .collect()
+    }
+
+    #[test]
+    fn default_intervals_test() {
+        assert_eq!(spans(&*sphere(0.0)), vec![(4.0, 6.0)]);
+    }
+
+    #[test]
+    fn union_test() {
+        assert_eq!(
+            spans(&Csg::union(sphere(0.0), sphere(1.0))),
+            vec![(4.0, 7.0)]
+        );
+        assert_eq!(
+            spans(&Csg::union(sphere(0.0), sphere(3.0))),
+            vec![(4.0, 6.0), (7.0, 9.0)]
+        );
+    }
+
+    #[test]
+    fn intersection_test() {
+        let lens = Csg::intersection(sphere(0.0), sphere(1.0));
+        assert_eq!(spans(&lens), vec![(5.0, 6.0)]);
+        assert!(Csg::intersection(sphere(0.0), sphere(3.0))
+            .hit(&ray(), 0.001, f64::INFINITY)
+            .is_none());
+    }
+
+    #[test]
+    fn difference_test() {
+        let cut = Csg::difference(sphere(0.0), sphere(1.0));
+        assert_eq!(spans(&cut), vec![(4.0, 5.0)]);
+
+        // The ray leaves the result where it enters the carved sphere, with
+        // the normal still facing the ray
+        let rec = cut.hit(&ray(), 4.5, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 5.0);
+        assert!(!rec.front_face);
+        assert_eq!(rec.normal, Vec3::new(-1.0, 0.0, 0.0));
+
+        // Seen from the other side, the carved surface is an entry
+        let back = Ray::new(Point3::new(0.5, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        let rec = cut.hit(&back, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 0.5);
+        assert!(rec.front_face);
+        assert_eq!(rec.normal, Vec3::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn nested_csg_test() {
+        let shell = Csg::difference(sphere(0.0), sphere(0.0));
+        assert!(spans(&shell).is_empty());
+
+        let chain = Csg::union(
+            Arc::new(Csg::difference(sphere(0.0), sphere(1.0))),
+            sphere(1.5),
+        );
+        assert_eq!(spans(&chain), vec![(4.0, 5.0), (5.5, 7.5)]);
+    }
+
+    #[test]
+    fn overlapping_list_test() {
+        let mut list = HittableList::default();
+        for x in [0.0, 1.0, 4.0] {
+            list.add(sphere(x));
+        }
+        assert_eq!(spans(&list), vec![(4.0, 7.0), (8.0, 10.0)]);
+
+        let bvh = crate::bvh::BvhNode::new(list.objects.clone());
+        assert_eq!(spans(&bvh), vec![(4.0, 7.0), (8.0, 10.0)]);
+
+        // Carving out of the list carves out of both overlapping spheres
+        let cut = Csg::difference(Arc::new(list), sphere(0.5));
+        assert_eq!(spans(&cut), vec![(4.0, 4.5), (6.5, 7.0), (8.0, 10.0)]);
+    }
+}
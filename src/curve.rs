@@ -0,0 +1,391 @@
+//! Cubic Bézier curves with varying width, for hair, fur and grass.
+//!
+//! Intersection follows the approach of pbrt: the curve is moved into a
+//! space where the ray runs along +z from the origin, then recursively
+//! split until each piece is nearly straight and can be tested as a line
+//! segment.
+//!
+//! Strands are read from a text format with one strand per line: the root
+//! and tip widths, followed by the control points of a piecewise cubic
+//! Bézier (3n + 1 points, three coordinates each). Blank lines and lines
+//! starting with `#` are ignored.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::aabb::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::ray::*;
+use crate::vec3::*;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CurveMode {
+    /// Flat strip always facing the incoming ray
+    Ribbon,
+    /// Round tube, with depth and normals of a cylinder
+    Cylinder,
+}
+
+pub struct Curve {
+    cp: [Point3; 4],
+    width: (f64, f64),
+    mode: CurveMode,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    (1.0 - t) * a + t * b
+}
+
+fn blossom(cp: &[Point3; 4], t: f64) -> Point3 {
+    let a = [0, 1, 2].map(|i| (1.0 - t) * cp[i] + t * cp[i + 1]);
+    let b = [0, 1].map(|i| (1.0 - t) * a[i] + t * a[i + 1]);
+    (1.0 - t) * b[0] + t * b[1]
+}
+
+fn derivative(cp: &[Point3; 4], t: f64) -> Vec3 {
+    let d = [0, 1, 2].map(|i| 3.0 * (cp[i + 1] - cp[i]));
+    (1.0 - t) * (1.0 - t) * d[0] + 2.0 * (1.0 - t) * t * d[1] + t * t * d[2]
+}
+
+/// Splits at t = 0.5 with de Casteljau's algorithm
+fn subdivide(cp: &[Point3; 4]) -> ([Point3; 4], [Point3; 4]) {
+    let m = [0, 1, 2].map(|i| 0.5 * (cp[i] + cp[i + 1]));
+    let n = [0.5 * (m[0] + m[1]), 0.5 * (m[1] + m[2])];
+    let mid = 0.5 * (n[0] + n[1]);
+    ([cp[0], m[0], n[0], mid], [mid, n[1], m[2], cp[3]])
+}
+
+// Hit found in ray space, before converting to a HitRecord
+struct CurveHit {
+    // Distance along the unit ray direction
+    z: f64,
+    u: f64,
+    // Curve center at the hit, in ray space
+    center: Point3,
+}
+
+impl Curve {
+    pub fn new(
+        cp: [Point3; 4],
+        width0: f64,
+        width1: f64,
+        mode: CurveMode,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> Curve {
+        Curve {
+            cp,
+            width: (width0, width1),
+            mode,
+            material,
+        }
+    }
+
+    fn max_width(&self) -> f64 {
+        self.width.0.max(self.width.1)
+    }
+
+    fn recursive_intersect(
+        &self,
+        cp: &[Point3; 4],
+        (u0, u1): (f64, f64),
+        (z_min, z_max): (f64, f64),
+        depth: usize,
+    ) -> Option<CurveHit> {
+        // Cull against the bounds of this piece, grown by the half width
+        let half_width =
+            0.5 * lerp(self.width.0, self.width.1, u0).max(lerp(self.width.0, self.width.1, u1));
+        let lo = cp.iter().skip(1).fold(cp[0], |acc, p| acc.min(p));
+        let hi = cp.iter().skip(1).fold(cp[0], |acc, p| acc.max(p));
+        if lo.x() > half_width
+            || hi.x() < -half_width
+            || lo.y() > half_width
+            || hi.y() < -half_width
+            || lo.z() > z_max + half_width
+            || hi.z() < z_min - half_width
+        {
+            return None;
+        }
+
+        if depth > 0 {
+            let (first, second) = subdivide(cp);
+            let u_mid = 0.5 * (u0 + u1);
+            let near = self.recursive_intersect(&first, (u0, u_mid), (z_min, z_max), depth - 1);
+            let z_max = near.as_ref().map_or(z_max, |hit| hit.z);
+            let far = self.recursive_intersect(&second, (u_mid, u1), (z_min, z_max), depth - 1);
+            return far.or(near);
+        }
+
+        // Nearly straight: find the closest point of the chord to the ray
+        let (p0, p3) = (cp[0], cp[3]);
+        let (dx, dy) = (p3.x() - p0.x(), p3.y() - p0.y());
+        let len2 = dx * dx + dy * dy;
+        if len2 == 0.0 {
+            return None;
+        }
+        let w = -(p0.x() * dx + p0.y() * dy) / len2;
+        // Only the true ends of the curve are cut off, inner joints between
+        // pieces are clamped so rays through a joint are not lost
+        if (w < 0.0 && u0 == 0.0) || (w > 1.0 && u1 == 1.0) {
+            return None;
+        }
+        let w = w.clamp(0.0, 1.0);
+
+        let center = blossom(cp, w);
+        let u = lerp(u0, u1, w);
+        let half_width = 0.5 * lerp(self.width.0, self.width.1, u);
+        let dist2 = center.x() * center.x() + center.y() * center.y();
+        if dist2 > half_width * half_width {
+            return None;
+        }
+
+        let z = match self.mode {
+            CurveMode::Ribbon => center.z(),
+            CurveMode::Cylinder => center.z() - (half_width * half_width - dist2).sqrt(),
+        };
+        if z < z_min || z > z_max {
+            return None;
+        }
+
+        Some(CurveHit { z, u, center })
+    }
+
+    // Enough splits that each leaf deviates from its chord by less than a
+    // fraction of the width
+    fn refinement_depth(cp: &[Point3; 4], width: f64) -> usize {
+        let l0 = (0..2)
+            .map(|i| {
+                let d = cp[i] - 2.0 * cp[i + 1] + cp[i + 2];
+                d.x().abs().max(d.y().abs())
+            })
+            .fold(0.0, f64::max);
+        let eps = width / 20.0;
+        let depth = ((std::f64::consts::SQRT_2 * 6.0 * l0 / (8.0 * eps)).log2() / 2.0).round();
+        depth.clamp(0.0, 10.0) as usize
+    }
+}
+
+impl Hittable for Curve {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let speed = r.direction().length();
+        let w = r.direction() / speed;
+        let a = if w.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let u_axis = unit_vector(cross(&a, &w));
+        let v_axis = cross(&w, &u_axis);
+
+        let to_ray_space = |p: &Point3| {
+            let d = *p - r.origin();
+            Point3::new(dot(&d, &u_axis), dot(&d, &v_axis), dot(&d, &w))
+        };
+        let cp = self.cp.map(|p| to_ray_space(&p));
+        let depth = Curve::refinement_depth(&cp, self.width.0.min(self.width.1));
+
+        let z_range = (t_min * speed, t_max.min(f64::MAX) * speed);
+        let hit = self.recursive_intersect(&cp, (0.0, 1.0), z_range, depth)?;
+
+        let t = hit.z / speed;
+        let half_width = 0.5 * lerp(self.width.0, self.width.1, hit.u);
+        let tangent = unit_vector(derivative(&self.cp, hit.u));
+        let side = unit_vector(cross(&tangent, &w));
+        // Signed offset of the ray from the curve center, across the curve
+        let offset = -(hit.center.x() * u_axis + hit.center.y() * v_axis);
+        let s = dot(&offset, &side);
+
+        let outward_normal = match self.mode {
+            CurveMode::Ribbon => -w,
+            CurveMode::Cylinder => {
+                let facing = unit_vector(-w - dot(&-w, &tangent) * tangent);
+                let sin_theta = (s / half_width).clamp(-1.0, 1.0);
+                let cos_theta = (1.0 - sin_theta * sin_theta).sqrt();
+                cos_theta * facing + sin_theta * side
+            }
+        };
+
+        let mut rec = HitRecord {
+            t,
+            p: r.at(t),
+            u: hit.u,
+            v: (0.5 + 0.5 * s / half_width).clamp(0.0, 1.0),
+            tangent,
+            material: self.material.clone(),
+            ..Default::default()
+        };
+        rec.set_face_normal(r, &outward_normal);
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let half = 0.5 * self.max_width();
+        let pad = Vec3::new(half, half, half);
+        let lo = self.cp.iter().skip(1).fold(self.cp[0], |acc, p| acc.min(p));
+        let hi = self.cp.iter().skip(1).fold(self.cp[0], |acc, p| acc.max(p));
+        Some(Aabb::new(lo - pad, hi + pad))
+    }
+}
+
+/// Reads strands from a file, see the module documentation for the format
+pub fn load_strands<P: AsRef<Path>>(
+    path: P,
+    mode: CurveMode,
+    material: Arc<dyn Material + Send + Sync>,
+) -> io::Result<HittableList> {
+    read_strands(BufReader::new(File::open(path)?), mode, material)
+}
+
+pub fn read_strands<R: BufRead>(
+    reader: R,
+    mode: CurveMode,
+    material: Arc<dyn Material + Send + Sync>,
+) -> io::Result<HittableList> {
+    let mut curves = HittableList::default();
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let invalid = |message: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {}", number + 1, message),
+            )
+        };
+        let values = line
+            .split_whitespace()
+            .map(|token| token.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid("expected numbers"))?;
+        if values.len() < 14 || (values.len() - 2) % 3 != 0 || (values.len() - 5) % 9 != 0 {
+            return Err(invalid("expected two widths and 3n + 1 control points"));
+        }
+
+        let (root, tip) = (values[0], values[1]);
+        let points: Vec<Point3> = values[2..]
+            .chunks_exact(3)
+            .map(|c| Point3::new(c[0], c[1], c[2]))
+            .collect();
+        let segments = (points.len() - 1) / 3;
+        for s in 0..segments {
+            let cp = [
+                points[3 * s],
+                points[3 * s + 1],
+                points[3 * s + 2],
+                points[3 * s + 3],
+            ];
+            let w0 = lerp(root, tip, s as f64 / segments as f64);
+            let w1 = lerp(root, tip, (s + 1) as f64 / segments as f64);
+            curves.add(Arc::new(Curve::new(cp, w0, w1, mode, material.clone())));
+        }
+    }
+
+    Ok(curves)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn material() -> Arc<dyn Material + Send + Sync> {
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    fn straight(mode: CurveMode) -> Curve {
+        Curve::new(
+            [
+                Point3::new(-1.0, 0.0, -5.0),
+                Point3::new(-0.3, 0.0, -5.0),
+                Point3::new(0.3, 0.0, -5.0),
+                Point3::new(1.0, 0.0, -5.0),
+            ],
+            0.2,
+            0.2,
+            mode,
+            material(),
+        )
+    }
+
+    #[test]
+    fn ribbon_test() {
+        let curve = straight(CurveMode::Ribbon);
+        let r = Ray::new(Point3::new(0.0, 0.05, 0.0), Vec3::new(0.0, 0.0, -2.0));
+        let rec = curve.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 2.5).abs() < 1e-9);
+        assert!((rec.u - 0.5).abs() < 1e-6);
+        assert!((rec.v - 0.75).abs() < 1e-6 || (rec.v - 0.25).abs() < 1e-6);
+        assert!(rec.front_face);
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+
+        let miss = Ray::new(Point3::new(0.0, 0.15, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(curve.hit(&miss, 0.001, f64::INFINITY).is_none());
+        let past_end = Ray::new(Point3::new(1.2, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(curve.hit(&past_end, 0.001, f64::INFINITY).is_none());
+        assert!(curve.hit(&r, 0.001, 2.0).is_none());
+    }
+
+    #[test]
+    fn cylinder_test() {
+        let curve = straight(CurveMode::Cylinder);
+        let center = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = curve.hit(&center, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 4.9).abs() < 1e-9);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+
+        // Towards the edge the normal turns away from the ray
+        let edge = Ray::new(Point3::new(0.0, 0.08, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = curve.hit(&edge, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - (5.0 - 0.06)).abs() < 1e-9);
+        assert!((rec.normal - Vec3::new(0.0, 0.8, 0.6)).length() < 1e-9);
+    }
+
+    #[test]
+    fn bent_curve_test() {
+        let curve = Curve::new(
+            [
+                Point3::new(-1.0, -1.0, -3.0),
+                Point3::new(-1.0, 1.0, -3.0),
+                Point3::new(1.0, 1.0, -3.0),
+                Point3::new(1.0, -1.0, -3.0),
+            ],
+            0.05,
+            0.05,
+            CurveMode::Ribbon,
+            material(),
+        );
+        // The apex of the arch is at y = 0.5
+        let r = Ray::new(Point3::new(0.0, 0.5, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = curve.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 3.0).abs() < 1e-9);
+        assert!((rec.u - 0.5).abs() < 1e-6);
+        let above = Ray::new(Point3::new(0.0, 0.53, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(curve.hit(&above, 0.001, f64::INFINITY).is_none());
+
+        // Crossing the side of the arch
+        let side = Ray::new(Point3::new(-0.77, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(curve.hit(&side, 0.001, f64::INFINITY).is_some());
+    }
+
+    #[test]
+    fn read_strands_test() {
+        let text = "# two strands\n\
+                    0.1 0.01 0 0 0 0 1 0 0 2 0 0 3 0 0 4 0 0 5 0 0 6 0\n\
+                    \n\
+                    0.1 0.1 1 0 0 1 1 0 1 2 0 1 3 0\n";
+        let curves = read_strands(text.as_bytes(), CurveMode::Ribbon, material()).unwrap();
+        assert_eq!(curves.objects.len(), 3);
+
+        let bad = "0.1 0.1 0 0 0 1 1 1";
+        let err = read_strands(bad.as_bytes(), CurveMode::Ribbon, material())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("line 1"));
+    }
+}
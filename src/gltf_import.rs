@@ -0,0 +1,533 @@
+//! glTF 2.0 scene import (`.gltf` with external or embedded buffers, and
+//! binary `.glb`).
+//!
+//! The default scene's node hierarchy is flattened into instances of shared
+//! meshes with `PbrMaterial`s. Perspective cameras become `Camera`s. Point
+//! and spot lights from `KHR_lights_punctual` become small emissive spheres,
+//! spot cones are not modelled. Directional lights cannot be represented by
+//! geometry and are only listed in `GltfScene::lights`.
+//!
+//! Only triangle primitives and the first texture coordinate set are used.
+//! Orthographic cameras are skipped. Vertex attributes whose count differs
+//! from the positions are dropped with a message in `GltfScene::warnings`.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+
+use gltf::khr_lights_punctual::Kind;
+
+use crate::camera::Camera;
+use crate::hittable::*;
+use crate::instance::Instance;
+use crate::material::*;
+use crate::mesh::{Mesh, TriangleMesh};
+use crate::pbr::PbrMaterial;
+use crate::sphere::Sphere;
+use crate::texture::{srgb_to_linear, ImageTexture};
+use crate::vec3::*;
+
+/// Radius of the spheres standing in for point and spot lights
+pub const LIGHT_RADIUS: f64 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightKind {
+    Directional,
+    Point,
+    /// Cone angles in radians
+    Spot {
+        inner_cone_angle: f64,
+        outer_cone_angle: f64,
+    },
+}
+
+/// A `KHR_lights_punctual` light placed in world space
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PunctualLight {
+    pub kind: LightKind,
+    pub color: Color,
+    /// Candela for point and spot lights, lux for directional ones
+    pub intensity: f64,
+    pub position: Point3,
+    pub direction: Vec3,
+}
+
+pub struct GltfScene {
+    pub world: HittableList,
+    pub cameras: Vec<Camera>,
+    pub lights: Vec<PunctualLight>,
+    pub warnings: Vec<String>,
+}
+
+/// Imports the default scene, or the first one if there is no default.
+/// `aspect_ratio` is used for cameras that do not specify one.
+pub fn load_gltf<P: AsRef<Path>>(path: P, aspect_ratio: f64) -> gltf::Result<GltfScene> {
+    let (document, buffers, images) = gltf::import(path)?;
+    Ok(Importer::new(&buffers, &images, aspect_ratio).import(&document))
+}
+
+/// Like `load_gltf`, for a `.glb` or self-contained `.gltf` in memory
+pub fn read_gltf(data: &[u8], aspect_ratio: f64) -> gltf::Result<GltfScene> {
+    let (document, buffers, images) = gltf::import_slice(data)?;
+    Ok(Importer::new(&buffers, &images, aspect_ratio).import(&document))
+}
+
+struct Importer<'a> {
+    buffers: &'a [gltf::buffer::Data],
+    images: &'a [gltf::image::Data],
+    aspect_ratio: f64,
+    textures: HashMap<(usize, bool), Arc<ImageTexture>>,
+    materials: HashMap<Option<usize>, Arc<PbrMaterial>>,
+    meshes: HashMap<usize, Vec<Arc<dyn Hittable + Send + Sync>>>,
+    scene: GltfScene,
+}
+
+impl<'a> Importer<'a> {
+    fn new(
+        buffers: &'a [gltf::buffer::Data],
+        images: &'a [gltf::image::Data],
+        aspect_ratio: f64,
+    ) -> Importer<'a> {
+        Importer {
+            buffers,
+            images,
+            aspect_ratio,
+            textures: HashMap::new(),
+            materials: HashMap::new(),
+            meshes: HashMap::new(),
+            scene: GltfScene {
+                world: HittableList::default(),
+                cameras: Vec::new(),
+                lights: Vec::new(),
+                warnings: Vec::new(),
+            },
+        }
+    }
+
+    fn import(mut self, document: &gltf::Document) -> GltfScene {
+        if let Some(scene) = document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+        {
+            for node in scene.nodes() {
+                self.visit(&node, Mat4::identity());
+            }
+        }
+        self.scene
+    }
+
+    fn visit(&mut self, node: &gltf::Node, parent: Mat4) {
+        let world = parent * column_major(node.transform().matrix());
+
+        if let Some(mesh) = node.mesh() {
+            // Nodes scaled to nothing are invisible anyway
+            if let Some(transform) = Transform::new(world) {
+                for primitive in self.mesh(&mesh) {
+                    self.scene
+                        .world
+                        .add(Arc::new(Instance::with_transform(primitive, transform)));
+                }
+            }
+        }
+
+        let position = world.transform_point(&Point3::default());
+        let forward = world.transform_vector(&Vec3::new(0.0, 0.0, -1.0));
+
+        if let Some(camera) = node.camera() {
+            if let gltf::camera::Projection::Perspective(perspective) = camera.projection() {
+                let up = world.transform_vector(&Vec3::new(0.0, 1.0, 0.0));
+                self.scene.cameras.push(Camera::new(
+                    position,
+                    position + forward,
+                    up,
+                    (perspective.yfov() as f64).to_degrees(),
+                    perspective
+                        .aspect_ratio()
+                        .map_or(self.aspect_ratio, |a| a as f64),
+                    0.0,
+                    1.0,
+                ));
+            }
+        }
+
+        if let Some(light) = node.light() {
+            let kind = match light.kind() {
+                Kind::Directional => LightKind::Directional,
+                Kind::Point => LightKind::Point,
+                Kind::Spot {
+                    inner_cone_angle,
+                    outer_cone_angle,
+                } => LightKind::Spot {
+                    inner_cone_angle: inner_cone_angle as f64,
+                    outer_cone_angle: outer_cone_angle as f64,
+                },
+            };
+            let light = PunctualLight {
+                kind,
+                color: color3(light.color()),
+                intensity: light.intensity() as f64,
+                position,
+                direction: unit_vector(forward),
+            };
+            if kind != LightKind::Directional {
+                // A sphere of radiance L has an intensity of L pi r^2
+                let radiance = light.intensity / (PI * LIGHT_RADIUS * LIGHT_RADIUS) * light.color;
+                self.scene.world.add(Arc::new(Sphere::new(
+                    position,
+                    LIGHT_RADIUS,
+                    Arc::new(DiffuseLight::new(radiance)),
+                )));
+            }
+            self.scene.lights.push(light);
+        }
+
+        for child in node.children() {
+            self.visit(&child, world);
+        }
+    }
+
+    fn mesh(&mut self, mesh: &gltf::Mesh) -> Vec<Arc<dyn Hittable + Send + Sync>> {
+        if let Some(primitives) = self.meshes.get(&mesh.index()) {
+            return primitives.clone();
+        }
+
+        let mut primitives: Vec<Arc<dyn Hittable + Send + Sync>> = Vec::new();
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                continue;
+            }
+            let reader = primitive.reader(|buffer| Some(&self.buffers[buffer.index()].0[..]));
+            let positions: Vec<Point3> = match reader.read_positions() {
+                Some(positions) => positions.map(vec3).collect(),
+                None => continue,
+            };
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+            let triangles = indices
+                .chunks_exact(3)
+                .map(|c| [c[0], c[1], c[2]])
+                .filter(|t| t.iter().all(|&i| (i as usize) < positions.len()))
+                .collect();
+
+            let mut data = TriangleMesh::new(positions, triangles);
+            if let Some(normals) = reader.read_normals() {
+                data.normals = normals.map(vec3).collect();
+            }
+            if let Some(uvs) = reader.read_tex_coords(0) {
+                // glTF puts the texture origin at the top left
+                data.uvs = uvs
+                    .into_f32()
+                    .map(|[u, v]| (u as f64, 1.0 - v as f64))
+                    .collect();
+            }
+            if let Some(colors) = reader.read_colors(0) {
+                data.colors = colors.into_rgb_f32().map(color3).collect();
+            }
+
+            // The gltf crate leaves matching the accessor counts to us
+            let n = data.positions.len();
+            let mut check = |len: usize, attribute: &str| {
+                let matches = len == 0 || len == n;
+                if !matches {
+                    self.scene.warnings.push(format!(
+                        "mesh {}: dropped {} with {} entries for {} positions",
+                        mesh.index(),
+                        attribute,
+                        len,
+                        n
+                    ));
+                }
+                matches
+            };
+            if !check(data.normals.len(), "NORMAL") {
+                data.normals.clear();
+            }
+            if !check(data.uvs.len(), "TEXCOORD_0") {
+                data.uvs.clear();
+            }
+            if !check(data.colors.len(), "COLOR_0") {
+                data.colors.clear();
+            }
+
+            let material = primitive.material();
+            let mut hittable = Mesh::new(data, self.material(&material));
+            if material.index().is_none() {
+                hittable = hittable.with_vertex_colors();
+            }
+            primitives.push(Arc::new(hittable));
+        }
+
+        self.meshes.insert(mesh.index(), primitives.clone());
+        primitives
+    }
+
+    fn material(&mut self, material: &gltf::Material) -> Arc<PbrMaterial> {
+        if let Some(material) = self.materials.get(&material.index()) {
+            return material.clone();
+        }
+
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, _] = pbr.base_color_factor();
+        let mut result = PbrMaterial::new(
+            color3([r, g, b]),
+            pbr.metallic_factor() as f64,
+            pbr.roughness_factor() as f64,
+        );
+
+        if let Some(texture) = self.texture(pbr.base_color_texture(), true) {
+            result = result.with_base_color_texture(texture);
+        }
+        if let Some(texture) = self.texture(pbr.metallic_roughness_texture(), false) {
+            result = result.with_metallic_roughness_texture(texture);
+        }
+        if let Some(normal) = material.normal_texture() {
+            if normal.tex_coord() == 0 {
+                let texture = self.image(normal.texture().source().index(), false);
+                result = result.with_normal_texture(texture, normal.scale() as f64);
+            }
+        }
+        let strength = material.emissive_strength().unwrap_or(1.0) as f64;
+        let emissive = strength * color3(material.emissive_factor());
+        let emissive_texture = self.texture(material.emissive_texture(), true);
+        result = result.with_emissive(emissive, emissive_texture);
+
+        let result = Arc::new(result);
+        self.materials.insert(material.index(), result.clone());
+        result
+    }
+
+    fn texture(
+        &mut self,
+        info: Option<gltf::texture::Info>,
+        srgb: bool,
+    ) -> Option<Arc<ImageTexture>> {
+        let info = info.filter(|info| info.tex_coord() == 0)?;
+        Some(self.image(info.texture().source().index(), srgb))
+    }
+
+    fn image(&mut self, index: usize, srgb: bool) -> Arc<ImageTexture> {
+        let images = self.images;
+        self.textures
+            .entry((index, srgb))
+            .or_insert_with(|| Arc::new(convert_image(&images[index], srgb)))
+            .clone()
+    }
+}
+
+fn column_major(m: [[f32; 4]; 4]) -> Mat4 {
+    let mut rows = [[0.0; 4]; 4];
+    for (c, column) in m.iter().enumerate() {
+        for (r, value) in column.iter().enumerate() {
+            rows[r][c] = *value as f64;
+        }
+    }
+    Mat4::new(rows)
+}
+
+fn vec3(v: [f32; 3]) -> Vec3 {
+    Vec3::new(v[0] as f64, v[1] as f64, v[2] as f64)
+}
+
+fn color3(c: [f32; 3]) -> Color {
+    vec3(c)
+}
+
+/// Converts decoded image data to linear float texels
+fn convert_image(image: &gltf::image::Data, srgb: bool) -> ImageTexture {
+    use gltf::image::Format;
+
+    let (channels, bytes) = match image.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT => (3, 4),
+        Format::R32G32B32A32FLOAT => (4, 4),
+    };
+    let value = |b: &[u8]| -> f32 {
+        match bytes {
+            1 => b[0] as f32 / 255.0,
+            2 => u16::from_ne_bytes([b[0], b[1]]) as f32 / 65535.0,
+            _ => f32::from_ne_bytes([b[0], b[1], b[2], b[3]]),
+        }
+    };
+    let decode = |c: f32| {
+        if srgb && bytes < 4 {
+            srgb_to_linear(c as f64) as f32
+        } else {
+            c
+        }
+    };
+
+    let texels = image
+        .pixels
+        .chunks_exact(channels * bytes)
+        .map(|pixel| {
+            let mut c: Vec<f32> = pixel.chunks_exact(bytes).map(value).collect();
+            // Gray images fill all color channels
+            if channels <= 2 {
+                c = vec![c[0], c[0], c[0], c.get(1).copied().unwrap_or(1.0)];
+            }
+            [
+                decode(c[0]),
+                decode(c[1]),
+                decode(c[2]),
+                c.get(3).copied().unwrap_or(1.0),
+            ]
+        })
+        .collect();
+    ImageTexture::new(image.width as usize, image.height as usize, texels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+
+    // A binary glTF with the given JSON and buffer
+    fn glb(json: &str, bin: &[u8]) -> Vec<u8> {
+        let mut json = json.as_bytes().to_vec();
+        json.resize(json.len().div_ceil(4) * 4, b' ');
+        let mut bin = bin.to_vec();
+        bin.resize(bin.len().div_ceil(4) * 4, 0);
+
+        let length = 12 + 8 + json.len() + 8 + bin.len();
+        let mut data = Vec::new();
+        data.extend(b"glTF");
+        data.extend(2u32.to_le_bytes());
+        data.extend((length as u32).to_le_bytes());
+        data.extend((json.len() as u32).to_le_bytes());
+        data.extend(b"JSON");
+        data.extend(json);
+        data.extend((bin.len() as u32).to_le_bytes());
+        data.extend(b"BIN\0");
+        data.extend(bin);
+        data
+    }
+
+    fn triangle_scene() -> Vec<u8> {
+        let mut bin = Vec::new();
+        for v in [[-1.0f32, -1.0, 0.0], [1.0, -1.0, 0.0], [0.0, 1.0, 0.0]] {
+            for x in v {
+                bin.extend(x.to_le_bytes());
+            }
+        }
+        let json = r#"{
+            "asset": {"version": "2.0"},
+            "extensionsUsed": ["KHR_lights_punctual"],
+            "extensions": {"KHR_lights_punctual": {"lights": [
+                {"type": "point", "color": [1, 1, 1], "intensity": 2},
+                {"type": "directional"}
+            ]}},
+            "scene": 0,
+            "scenes": [{"nodes": [0, 2, 3, 4]}],
+            "nodes": [
+                {"translation": [0, 0, -5], "children": [1]},
+                {"mesh": 0, "scale": [2, 2, 2]},
+                {"camera": 0, "translation": [0, 0, 1]},
+                {"translation": [0, 3, 0],
+                 "extensions": {"KHR_lights_punctual": {"light": 0}}},
+                {"extensions": {"KHR_lights_punctual": {"light": 1}}}
+            ],
+            "cameras": [{"type": "perspective",
+                "perspective": {"yfov": 0.8, "znear": 0.1}}],
+            "meshes": [{"primitives": [
+                {"attributes": {"POSITION": 0}, "material": 0}
+            ]}],
+            "materials": [{"pbrMetallicRoughness": {
+                "baseColorFactor": [1, 0, 0, 1], "metallicFactor": 0.0
+            }, "emissiveFactor": [0, 0.5, 0]}],
+            "accessors": [{"bufferView": 0, "componentType": 5126, "count": 3,
+                "type": "VEC3", "min": [-1, -1, 0], "max": [1, 1, 0]}],
+            "bufferViews": [{"buffer": 0, "byteLength": 36}],
+            "buffers": [{"byteLength": 36}]
+        }"#;
+        glb(json, &bin)
+    }
+
+    #[test]
+    fn import_test() {
+        let scene = read_gltf(&triangle_scene(), 1.5).unwrap();
+        assert_eq!(scene.cameras.len(), 1);
+        assert_eq!(scene.lights.len(), 2);
+        assert_eq!(scene.lights[0].kind, LightKind::Point);
+        assert_eq!(scene.lights[0].position, Point3::new(0.0, 3.0, 0.0));
+        assert_eq!(scene.lights[1].direction, Vec3::new(0.0, 0.0, -1.0));
+
+        // The triangle is scaled by its node and moved by the parent
+        let r = Ray::new(Point3::new(1.5, -1.5, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = scene.world.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 5.0).abs() < 1e-9);
+        assert_eq!(
+            rec.material.emitted(rec.u, rec.v, &rec.p),
+            Color::new(0.0, 0.5, 0.0)
+        );
+        let r = Ray::new(Point3::new(2.5, -1.5, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(scene.world.hit(&r, 0.001, f64::INFINITY).is_none());
+
+        // The point light is an emissive sphere
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let rec = scene.world.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - (3.0 - LIGHT_RADIUS)).abs() < 1e-9);
+        assert!(rec.material.emitted(rec.u, rec.v, &rec.p).x() > 0.0);
+    }
+
+    #[test]
+    fn mismatched_attribute_test() {
+        let mut bin = Vec::new();
+        for v in [[-1.0f32, -1.0, 0.0], [1.0, -1.0, 0.0], [0.0, 1.0, 0.0]] {
+            for x in v {
+                bin.extend(x.to_le_bytes());
+            }
+        }
+        for x in [0.0f32, 0.0, 1.0] {
+            bin.extend(x.to_le_bytes());
+        }
+        // One normal for three positions
+        let json = r#"{
+            "asset": {"version": "2.0"},
+            "scenes": [{"nodes": [0]}],
+            "nodes": [{"mesh": 0}],
+            "meshes": [{"primitives": [{"attributes": {"POSITION": 0, "NORMAL": 1}}]}],
+            "accessors": [
+                {"bufferView": 0, "componentType": 5126, "count": 3,
+                 "type": "VEC3", "min": [-1, -1, 0], "max": [1, 1, 0]},
+                {"bufferView": 1, "componentType": 5126, "count": 1, "type": "VEC3"}
+            ],
+            "bufferViews": [
+                {"buffer": 0, "byteLength": 36},
+                {"buffer": 0, "byteOffset": 36, "byteLength": 12}
+            ],
+            "buffers": [{"byteLength": 48}]
+        }"#;
+        let scene = read_gltf(&glb(json, &bin), 1.0).unwrap();
+        assert_eq!(scene.warnings.len(), 1);
+
+        let r = Ray::new(Point3::new(0.5, -0.5, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = scene.world.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn convert_image_test() {
+        let image = gltf::image::Data {
+            pixels: vec![255, 128, 0, 0],
+            format: gltf::image::Format::R8G8,
+            width: 2,
+            height: 1,
+        };
+        let texture = convert_image(&image, false);
+        assert_eq!(
+            texture.sample(0.25, 0.5),
+            [1.0, 1.0, 1.0, (128.0_f32 / 255.0) as f64]
+        );
+        assert_eq!(texture.sample(0.75, 0.5), [0.0, 0.0, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,451 @@
+//! Physically based hair scattering after Chiang et al., "A Practical and
+//! Controllable Hair and Fur Model for Production Path Tracing" (2016), as
+//! formulated in pbrt-v3.
+//!
+//! Light is split into the R (reflection), TT (transmission) and TRT lobes
+//! plus a residual term for longer paths. Each lobe factors into a
+//! longitudinal part with roughness `beta_m` and an azimuthal part with
+//! roughness `beta_n`. Meant for `Curve` geometry, which provides the hair
+//! direction and the offset across the strand through the hit's tangent and
+//! v coordinate.
+
+use std::f64::consts::PI;
+
+use crate::hittable::*;
+use crate::material::*;
+use crate::ray::*;
+use crate::vec3::*;
+
+const P_MAX: usize = 3;
+const SQRT_PI_OVER_8: f64 = 0.626_657_068_657_750_1;
+
+/// Hair fiber scattering, see the module documentation
+pub struct HairMaterial {
+    pub sigma_a: Color,
+    pub eta: f64,
+    pub beta_m: f64,
+    pub beta_n: f64,
+    /// Tilt of the cuticle scales, in degrees
+    pub alpha: f64,
+}
+
+impl HairMaterial {
+    pub fn new(sigma_a: Color, eta: f64, beta_m: f64, beta_n: f64, alpha: f64) -> HairMaterial {
+        HairMaterial {
+            sigma_a,
+            eta,
+            beta_m,
+            beta_n,
+            alpha,
+        }
+    }
+
+    /// Absorption from the concentrations of eumelanin (dark brown to black)
+    /// and pheomelanin (red), with typical human hair roughness
+    pub fn from_melanin(eumelanin: f64, pheomelanin: f64) -> HairMaterial {
+        let sigma_a =
+            eumelanin * Color::new(0.419, 0.697, 1.37) + pheomelanin * Color::new(0.187, 0.4, 1.05);
+        HairMaterial::new(sigma_a, 1.55, 0.3, 0.3, 2.0)
+    }
+
+    /// Absorption that gives roughly the requested diffuse reflectance for
+    /// the azimuthal roughness `beta_n`
+    pub fn from_color(color: Color, beta_n: f64) -> HairMaterial {
+        let b = beta_n;
+        let denom = 5.969 - 0.215 * b + 2.532 * b.powi(2) - 10.73 * b.powi(3)
+            + 5.574 * b.powi(4)
+            + 0.245 * b.powi(5);
+        let channel = |c: f64| (c.max(1e-4).ln() / denom).powi(2);
+        let sigma_a = Color::new(channel(color.x()), channel(color.y()), channel(color.z()));
+        HairMaterial::new(sigma_a, 1.55, 0.3, beta_n, 2.0)
+    }
+
+    fn lobes(&self, h: f64) -> HairLobes {
+        let beta_m = self.beta_m;
+        let v0 = (0.726 * beta_m + 0.812 * beta_m.powi(2) + 3.7 * beta_m.powi(20)).powi(2);
+        let s = SQRT_PI_OVER_8
+            * (0.265 * self.beta_n + 1.194 * self.beta_n.powi(2) + 5.372 * self.beta_n.powi(22));
+
+        // Rotations by 2^k alpha for the tilt of each lobe
+        let mut sin_2k_alpha = [0.0; 3];
+        let mut cos_2k_alpha = [0.0; 3];
+        sin_2k_alpha[0] = self.alpha.to_radians().sin();
+        cos_2k_alpha[0] = (1.0 - sin_2k_alpha[0].powi(2)).max(0.0).sqrt();
+        for i in 1..3 {
+            sin_2k_alpha[i] = 2.0 * cos_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+            cos_2k_alpha[i] = cos_2k_alpha[i - 1].powi(2) - sin_2k_alpha[i - 1].powi(2);
+        }
+
+        HairLobes {
+            h,
+            gamma_o: h.clamp(-1.0, 1.0).asin(),
+            v: [v0, 0.25 * v0, 4.0 * v0, 4.0 * v0],
+            s,
+            sin_2k_alpha,
+            cos_2k_alpha,
+            eta: self.eta,
+            sigma_a: self.sigma_a,
+        }
+    }
+}
+
+// Per-hit parameters of the lobes
+struct HairLobes {
+    h: f64,
+    gamma_o: f64,
+    v: [f64; P_MAX + 1],
+    s: f64,
+    sin_2k_alpha: [f64; 3],
+    cos_2k_alpha: [f64; 3],
+    eta: f64,
+    sigma_a: Color,
+}
+
+fn i0(x: f64) -> f64 {
+    let mut value = 0.0;
+    let mut x2i = 1.0;
+    let mut ifact = 1.0;
+    let mut i4 = 1.0;
+    for i in 0..10 {
+        if i > 1 {
+            ifact *= i as f64;
+        }
+        value += x2i / (i4 * ifact * ifact);
+        x2i *= x * x;
+        i4 *= 4.0;
+    }
+    value
+}
+
+fn log_i0(x: f64) -> f64 {
+    if x > 12.0 {
+        x + 0.5 * (-(2.0 * PI).ln() + (1.0 / x).ln() + 1.0 / (8.0 * x))
+    } else {
+        i0(x).ln()
+    }
+}
+
+/// Longitudinal scattering function
+fn mp(cos_theta_i: f64, cos_theta_o: f64, sin_theta_i: f64, sin_theta_o: f64, v: f64) -> f64 {
+    let a = cos_theta_i * cos_theta_o / v;
+    let b = sin_theta_i * sin_theta_o / v;
+    if v <= 0.1 {
+        (log_i0(a) - b - 1.0 / v + std::f64::consts::LN_2 + (1.0 / (2.0 * v)).ln()).exp()
+    } else {
+        (-b).exp() * i0(a) / ((1.0 / v).sinh() * 2.0 * v)
+    }
+}
+
+fn fr_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let (cos_i, eta) = if cos_theta_i < 0.0 {
+        (-cos_theta_i, 1.0 / eta)
+    } else {
+        (cos_theta_i, eta)
+    };
+    let sin_t = (1.0 - cos_i * cos_i).max(0.0).sqrt() / eta;
+    if sin_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin_t * sin_t).max(0.0).sqrt();
+    let r_parl = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_parl * r_parl + r_perp * r_perp)
+}
+
+fn exp_color(c: Color) -> Color {
+    Color::new(c.x().exp(), c.y().exp(), c.z().exp())
+}
+
+fn luminance(c: &Color) -> f64 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
+/// Attenuation of each lobe
+fn ap(cos_theta_o: f64, eta: f64, h: f64, t: Color) -> [Color; P_MAX + 1] {
+    let cos_gamma_o = (1.0 - h * h).max(0.0).sqrt();
+    let f = fr_dielectric(cos_theta_o * cos_gamma_o, eta);
+    let white = Color::new(1.0, 1.0, 1.0);
+
+    let r = f * white;
+    let tt = (1.0 - f).powi(2) * t;
+    let trt = f * (tt * t);
+    let ft = f * t;
+    let rest = f
+        * (trt * t)
+        * Color::new(
+            1.0 / (1.0 - ft.x()),
+            1.0 / (1.0 - ft.y()),
+            1.0 / (1.0 - ft.z()),
+        );
+    [r, tt, trt, rest]
+}
+
+fn phi(p: usize, gamma_o: f64, gamma_t: f64) -> f64 {
+    2.0 * p as f64 * gamma_t - 2.0 * gamma_o + p as f64 * PI
+}
+
+fn logistic(x: f64, s: f64) -> f64 {
+    let x = x.abs();
+    (-x / s).exp() / (s * (1.0 + (-x / s).exp()).powi(2))
+}
+
+fn logistic_cdf(x: f64, s: f64) -> f64 {
+    1.0 / (1.0 + (-x / s).exp())
+}
+
+fn trimmed_logistic(x: f64, s: f64, a: f64, b: f64) -> f64 {
+    logistic(x, s) / (logistic_cdf(b, s) - logistic_cdf(a, s))
+}
+
+fn sample_trimmed_logistic(u: f64, s: f64, a: f64, b: f64) -> f64 {
+    let k = logistic_cdf(b, s) - logistic_cdf(a, s);
+    let x = -s * (1.0 / (u * k + logistic_cdf(a, s)) - 1.0).ln();
+    x.clamp(a, b)
+}
+
+/// Azimuthal scattering function
+fn np(phi_diff: f64, p: usize, s: f64, gamma_o: f64, gamma_t: f64) -> f64 {
+    let mut dphi = phi_diff - phi(p, gamma_o, gamma_t);
+    while dphi > PI {
+        dphi -= 2.0 * PI;
+    }
+    while dphi < -PI {
+        dphi += 2.0 * PI;
+    }
+    trimmed_logistic(dphi, s, -PI, PI)
+}
+
+impl HairLobes {
+    // Refracted geometry for outgoing direction wo, in the hair frame
+    fn transmission(&self, sin_theta_o: f64, cos_theta_o: f64) -> (f64, Color) {
+        let sin_theta_t = sin_theta_o / self.eta;
+        let cos_theta_t = (1.0 - sin_theta_t * sin_theta_t).max(0.0).sqrt();
+        let etap = (self.eta * self.eta - sin_theta_o * sin_theta_o).sqrt() / cos_theta_o;
+        let sin_gamma_t = (self.h / etap).clamp(-1.0, 1.0);
+        let cos_gamma_t = (1.0 - sin_gamma_t * sin_gamma_t).max(0.0).sqrt();
+        let t = exp_color(-(2.0 * cos_gamma_t / cos_theta_t) * self.sigma_a);
+        (sin_gamma_t.asin(), t)
+    }
+
+    // sin and cos of theta_o rotated by the cuticle tilt of lobe p
+    fn tilt(&self, p: usize, sin_theta_o: f64, cos_theta_o: f64) -> (f64, f64) {
+        let (sin_a, cos_a, sign) = match p {
+            0 => (self.sin_2k_alpha[1], self.cos_2k_alpha[1], -1.0),
+            1 => (self.sin_2k_alpha[0], self.cos_2k_alpha[0], 1.0),
+            2 => (self.sin_2k_alpha[2], self.cos_2k_alpha[2], 1.0),
+            _ => return (sin_theta_o, cos_theta_o),
+        };
+        let sin_op = sin_theta_o * cos_a + sign * cos_theta_o * sin_a;
+        let cos_op = cos_theta_o * cos_a - sign * sin_theta_o * sin_a;
+        (sin_op, cos_op.abs())
+    }
+
+    fn ap_pdf(&self, sin_theta_o: f64, cos_theta_o: f64) -> [f64; P_MAX + 1] {
+        let (_, t) = self.transmission(sin_theta_o, cos_theta_o);
+        let ap = ap(cos_theta_o, self.eta, self.h, t).map(|a| luminance(&a));
+        let sum: f64 = ap.iter().sum();
+        ap.map(|a| a / sum)
+    }
+
+    /// BSDF times the cosine with the hair normal, for local directions
+    fn f_cos(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        let sin_theta_o = wo.x();
+        let cos_theta_o = (1.0 - sin_theta_o * sin_theta_o).max(0.0).sqrt();
+        let phi_o = wo.y().atan2(wo.z());
+        let sin_theta_i = wi.x();
+        let cos_theta_i = (1.0 - sin_theta_i * sin_theta_i).max(0.0).sqrt();
+        let phi_i = wi.y().atan2(wi.z());
+
+        let (gamma_t, t) = self.transmission(sin_theta_o, cos_theta_o);
+        let ap = ap(cos_theta_o, self.eta, self.h, t);
+        let phi_diff = phi_i - phi_o;
+
+        let mut f = Color::default();
+        for (p, ap_p) in ap.iter().enumerate().take(P_MAX) {
+            let (sin_op, cos_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+            f += mp(cos_theta_i, cos_op, sin_theta_i, sin_op, self.v[p])
+                * np(phi_diff, p, self.s, self.gamma_o, gamma_t)
+                * *ap_p;
+        }
+        f += mp(
+            cos_theta_i,
+            cos_theta_o,
+            sin_theta_i,
+            sin_theta_o,
+            self.v[P_MAX],
+        ) * ap[P_MAX]
+            / (2.0 * PI);
+        f
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        let sin_theta_o = wo.x();
+        let cos_theta_o = (1.0 - sin_theta_o * sin_theta_o).max(0.0).sqrt();
+        let phi_o = wo.y().atan2(wo.z());
+        let sin_theta_i = wi.x();
+        let cos_theta_i = (1.0 - sin_theta_i * sin_theta_i).max(0.0).sqrt();
+        let phi_i = wi.y().atan2(wi.z());
+
+        let (gamma_t, _) = self.transmission(sin_theta_o, cos_theta_o);
+        let ap_pdf = self.ap_pdf(sin_theta_o, cos_theta_o);
+        let phi_diff = phi_i - phi_o;
+
+        let mut pdf = 0.0;
+        for (p, ap_p) in ap_pdf.iter().enumerate().take(P_MAX) {
+            let (sin_op, cos_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+            pdf += mp(cos_theta_i, cos_op, sin_theta_i, sin_op, self.v[p])
+                * ap_p
+                * np(phi_diff, p, self.s, self.gamma_o, gamma_t);
+        }
+        pdf += mp(
+            cos_theta_i,
+            cos_theta_o,
+            sin_theta_i,
+            sin_theta_o,
+            self.v[P_MAX],
+        ) * ap_pdf[P_MAX]
+            / (2.0 * PI);
+        pdf
+    }
+
+    /// Samples an incident direction, returning it with the sample weight
+    /// f cos / pdf
+    fn sample(&self, wo: &Vec3, u: [f64; 4]) -> Option<(Vec3, Color)> {
+        let sin_theta_o = wo.x();
+        let cos_theta_o = (1.0 - sin_theta_o * sin_theta_o).max(0.0).sqrt();
+        let phi_o = wo.y().atan2(wo.z());
+
+        // Pick a lobe in proportion to its attenuation
+        let ap_pdf = self.ap_pdf(sin_theta_o, cos_theta_o);
+        let mut p = 0;
+        let mut u0 = u[0];
+        while p < P_MAX && u0 >= ap_pdf[p] {
+            u0 -= ap_pdf[p];
+            p += 1;
+        }
+
+        // Longitudinal angle
+        let (sin_op, cos_op) = self.tilt(p, sin_theta_o, cos_theta_o);
+        let v = self.v[p];
+        let u2 = u[2].max(1e-5);
+        let cos_theta = 1.0 + v * (u2 + (1.0 - u2) * (-2.0 / v).exp()).ln();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let cos_phi = (2.0 * PI * u[3]).cos();
+        let sin_theta_i = -cos_theta * sin_op + sin_theta * cos_phi * cos_op;
+        let cos_theta_i = (1.0 - sin_theta_i * sin_theta_i).max(0.0).sqrt();
+
+        // Azimuthal angle
+        let (gamma_t, _) = self.transmission(sin_theta_o, cos_theta_o);
+        let dphi = if p < P_MAX {
+            phi(p, self.gamma_o, gamma_t) + sample_trimmed_logistic(u[1], self.s, -PI, PI)
+        } else {
+            2.0 * PI * u[1]
+        };
+        let phi_i = phi_o + dphi;
+
+        let wi = Vec3::new(
+            sin_theta_i,
+            cos_theta_i * phi_i.cos(),
+            cos_theta_i * phi_i.sin(),
+        );
+        let pdf = self.pdf(wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some((wi, self.f_cos(wo, &wi) / pdf))
+    }
+}
+
+impl Material for HairMaterial {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        // Frame with x along the hair and z facing the viewer across it
+        let wo_world = -unit_vector(r_in.direction());
+        let x = if rec.tangent.near_zero() {
+            let a = if rec.normal.x().abs() > 0.9 {
+                Vec3::new(0.0, 1.0, 0.0)
+            } else {
+                Vec3::new(1.0, 0.0, 0.0)
+            };
+            unit_vector(cross(&a, &rec.normal))
+        } else {
+            unit_vector(rec.tangent)
+        };
+        let facing = wo_world - dot(&wo_world, &x) * x;
+        let z = if facing.near_zero() {
+            rec.normal
+        } else {
+            unit_vector(facing)
+        };
+        let y = cross(&z, &x);
+
+        let wo = Vec3::new(dot(&wo_world, &x), dot(&wo_world, &y), dot(&wo_world, &z));
+        let lobes = self.lobes((2.0 * rec.v - 1.0).clamp(-0.999, 0.999));
+
+        let u = [0; 4].map(|_| random_double(0.0..1.0));
+        let (wi, weight) = lobes.sample(&wo, u)?;
+
+        let direction = wi.x() * x + wi.y() * y + wi.z() * z;
+        Some((weight, Ray::new(rec.p, direction)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    fn random_direction() -> Vec3 {
+        Vec3::random_unit_vector()
+    }
+
+    #[test]
+    fn white_furnace_test() {
+        // Without absorption every bit of light is scattered somewhere
+        let mut rng = rand::thread_rng();
+        for beta in [0.2, 0.5, 0.8] {
+            let hair = HairMaterial::new(Color::default(), 1.55, beta, beta, 2.0);
+            let n = 20000;
+            let mut sum = 0.0;
+            for _ in 0..n {
+                let lobes = hair.lobes(rng.gen_range(-1.0..1.0));
+                let wo = random_direction();
+                let u = [0; 4].map(|_| rng.gen_range(0.0..1.0));
+                if let Some((_, weight)) = lobes.sample(&wo, u) {
+                    sum += weight.y();
+                }
+            }
+            let average = sum / n as f64;
+            assert!((average - 1.0).abs() < 0.05, "beta {}: {}", beta, average);
+        }
+    }
+
+    #[test]
+    fn pdf_integrates_to_one_test() {
+        let hair = HairMaterial::new(Color::new(0.5, 0.8, 1.5), 1.55, 0.4, 0.4, 2.0);
+        let lobes = hair.lobes(0.3);
+        let wo = unit_vector(Vec3::new(0.3, 0.2, 0.9));
+
+        let n = 100000;
+        let sum: f64 = (0..n)
+            .map(|_| {
+                // Uniform sphere sampling has pdf 1 / (4 pi)
+                let wi = random_direction();
+                lobes.pdf(&wo, &wi) * 4.0 * PI
+            })
+            .sum();
+        assert!((sum / n as f64 - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn melanin_test() {
+        let dark = HairMaterial::from_melanin(8.0, 0.0);
+        let blonde = HairMaterial::from_melanin(0.3, 0.0);
+        assert!(dark.sigma_a.x() > blonde.sigma_a.x());
+        // Melanin absorbs blue the most, giving hair its warm color
+        assert!(blonde.sigma_a.z() > blonde.sigma_a.x());
+
+        let reddish = HairMaterial::from_color(Color::new(0.6, 0.3, 0.1), 0.3);
+        assert!(reddish.sigma_a.x() < reddish.sigma_a.y());
+        assert!(reddish.sigma_a.y() < reddish.sigma_a.z());
+    }
+}
@@ -0,0 +1,350 @@
+use image::error::{ImageError, ParameterError, ParameterErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::aabb::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::mesh::intersect_triangle;
+use crate::ray::*;
+use crate::vec3::*;
+
+/// Terrain given by a regular grid of heights over the xz plane.
+///
+/// Each grid cell is split into two triangles, shaded with interpolated
+/// vertex normals. Rays walk the cells in order with a 2D DDA and skip cells
+/// whose height range they pass above or below.
+pub struct Heightfield {
+    nx: usize,
+    nz: usize,
+    heights: Vec<f64>,
+    normals: Vec<Vec3>,
+    // Min and max height of each cell
+    cell_range: Vec<(f64, f64)>,
+    corner: Point3,
+    cell_size: (f64, f64),
+    bbox: Aabb,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+impl Heightfield {
+    /// `heights` holds `nx * nz` samples in [0, 1], row by row along x. The
+    /// field spans `size.x()` by `size.z()` from `corner`, and a sample of 1
+    /// is `size.y()` above it. Panics with fewer than 2x2 samples.
+    pub fn new(
+        nx: usize,
+        nz: usize,
+        heights: &[f64],
+        corner: Point3,
+        size: Vec3,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> Heightfield {
+        assert!(
+            nx >= 2 && nz >= 2,
+            "a heightfield needs at least 2x2 samples"
+        );
+        assert_eq!(heights.len(), nx * nz);
+
+        let heights: Vec<f64> = heights.iter().map(|h| corner.y() + h * size.y()).collect();
+        let cell_size = (size.x() / (nx - 1) as f64, size.z() / (nz - 1) as f64);
+
+        // Vertex normals from central differences
+        let at = |i: usize, j: usize| heights[i + nx * j];
+        let mut normals = Vec::with_capacity(nx * nz);
+        for j in 0..nz {
+            for i in 0..nx {
+                let (i0, i1) = (i.saturating_sub(1), (i + 1).min(nx - 1));
+                let (j0, j1) = (j.saturating_sub(1), (j + 1).min(nz - 1));
+                let dx = (at(i1, j) - at(i0, j)) / ((i1 - i0) as f64 * cell_size.0);
+                let dz = (at(i, j1) - at(i, j0)) / ((j1 - j0) as f64 * cell_size.1);
+                normals.push(unit_vector(Vec3::new(-dx, 1.0, -dz)));
+            }
+        }
+
+        let mut cell_range = Vec::with_capacity((nx - 1) * (nz - 1));
+        for j in 0..nz - 1 {
+            for i in 0..nx - 1 {
+                let h = [at(i, j), at(i + 1, j), at(i, j + 1), at(i + 1, j + 1)];
+                let lo = h.iter().copied().fold(f64::INFINITY, f64::min);
+                let hi = h.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                cell_range.push((lo, hi));
+            }
+        }
+
+        let lo = heights.iter().copied().fold(f64::INFINITY, f64::min);
+        let hi = heights.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let bbox = Aabb::new(
+            Point3::new(corner.x(), lo, corner.z()),
+            Point3::new(corner.x() + size.x(), hi, corner.z() + size.z()),
+        )
+        .pad(0.0001);
+
+        Heightfield {
+            nx,
+            nz,
+            heights,
+            normals,
+            cell_range,
+            corner,
+            cell_size,
+            bbox,
+            material,
+        }
+    }
+
+    /// Loads heights from a grayscale image, 8 or 16 bits per channel. Image
+    /// columns run along x and rows along z. Images smaller than 2x2 are an
+    /// error.
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        corner: Point3,
+        size: Vec3,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> image::ImageResult<Heightfield> {
+        let img = image::open(path)?.into_luma16();
+        if img.width() < 2 || img.height() < 2 {
+            return Err(ImageError::Parameter(ParameterError::from_kind(
+                ParameterErrorKind::Generic(format!(
+                    "a heightfield needs at least 2x2 samples, got {}x{}",
+                    img.width(),
+                    img.height()
+                )),
+            )));
+        }
+        let heights: Vec<f64> = img.pixels().map(|p| p.0[0] as f64 / 65535.0).collect();
+
+        Ok(Heightfield::new(
+            img.width() as usize,
+            img.height() as usize,
+            &heights,
+            corner,
+            size,
+            material,
+        ))
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> Point3 {
+        Point3::new(
+            self.corner.x() + i as f64 * self.cell_size.0,
+            self.heights[i + self.nx * j],
+            self.corner.z() + j as f64 * self.cell_size.1,
+        )
+    }
+
+    fn hit_cell(&self, r: &Ray, i: usize, j: usize, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+        let triangles = [[0, 1, 2], [0, 2, 3]];
+
+        let mut closest: Option<(f64, [usize; 3], f64, f64)> = None;
+        for tri in triangles {
+            let [a, b, c] = tri.map(|k| self.vertex(corners[k].0, corners[k].1));
+            let limit = closest.map_or(t_max, |hit| hit.0);
+            if let Some((t, b1, b2)) = intersect_triangle(r, &a, &b, &c, t_min, limit) {
+                closest = Some((t, tri, b1, b2));
+            }
+        }
+
+        let (t, tri, b1, b2) = closest?;
+        let [na, nb, nc] = tri.map(|k| self.normals[corners[k].0 + self.nx * corners[k].1]);
+        let normal = unit_vector((1.0 - b1 - b2) * na + b1 * nb + b2 * nc);
+
+        let p = r.at(t);
+        let extent = self.bbox.maximum - self.bbox.minimum;
+        let mut rec = HitRecord {
+            t,
+            p,
+            u: ((p.x() - self.corner.x()) / extent.x()).clamp(0.0, 1.0),
+            v: ((p.z() - self.corner.z()) / extent.z()).clamp(0.0, 1.0),
+            material: self.material.clone(),
+            ..Default::default()
+        };
+        rec.set_face_normal(r, &normal);
+        Some(rec)
+    }
+}
+
+impl Hittable for Heightfield {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (t_enter, t_exit) = self.bbox.intersect(r, t_min, t_max)?;
+        let (o, d) = (r.origin(), r.direction());
+        let cells = (self.nx - 1, self.nz - 1);
+
+        // Cell containing the entry point
+        let p = r.at(t_enter);
+        let cell = |x: f64, corner: f64, size: f64, n: usize| {
+            (((x - corner) / size).floor().max(0.0) as usize).min(n - 1)
+        };
+        let mut i = cell(p.x(), self.corner.x(), self.cell_size.0, cells.0);
+        let mut j = cell(p.z(), self.corner.z(), self.cell_size.1, cells.1);
+
+        // Parametric distance to the next cell boundary and between
+        // boundaries, along each axis
+        let setup = |index: usize, origin: f64, dir: f64, corner: f64, size: f64| {
+            if dir == 0.0 {
+                return (f64::INFINITY, f64::INFINITY);
+            }
+            let next = corner + (index as f64 + if dir > 0.0 { 1.0 } else { 0.0 }) * size;
+            ((next - origin) / dir, size / dir.abs())
+        };
+        let (mut t_next_x, dt_x) = setup(i, o.x(), d.x(), self.corner.x(), self.cell_size.0);
+        let (mut t_next_z, dt_z) = setup(j, o.z(), d.z(), self.corner.z(), self.cell_size.1);
+
+        let mut t_cell = t_enter;
+        loop {
+            let t_leave = t_next_x.min(t_next_z).min(t_exit);
+
+            // Skip cells whose heights the ray segment cannot reach
+            let (y0, y1) = (o.y() + t_cell * d.y(), o.y() + t_leave * d.y());
+            let (lo, hi) = self.cell_range[i + cells.0 * j];
+            if y0.min(y1) <= hi && y0.max(y1) >= lo {
+                if let Some(rec) = self.hit_cell(r, i, j, t_min, t_max) {
+                    return Some(rec);
+                }
+            }
+
+            if t_leave >= t_exit {
+                return None;
+            }
+            if t_next_x < t_next_z {
+                if d.x() > 0.0 && i + 1 < cells.0 {
+                    i += 1;
+                } else if d.x() < 0.0 && i > 0 {
+                    i -= 1;
+                } else {
+                    return None;
+                }
+                t_next_x += dt_x;
+            } else {
+                if d.z() > 0.0 && j + 1 < cells.1 {
+                    j += 1;
+                } else if d.z() < 0.0 && j > 0 {
+                    j -= 1;
+                } else {
+                    return None;
+                }
+                t_next_z += dt_z;
+            }
+            t_cell = t_leave;
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bbox)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn material() -> Arc<dyn Material + Send + Sync> {
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    fn bumps() -> Heightfield {
+        let (nx, nz) = (17, 13);
+        let heights: Vec<f64> = (0..nx * nz)
+            .map(|n| {
+                let (i, j) = ((n % nx) as f64, (n / nx) as f64);
+                0.5 + 0.5 * (0.7 * i).sin() * (0.9 * j).cos()
+            })
+            .collect();
+        Heightfield::new(
+            nx,
+            nz,
+            &heights,
+            Point3::new(-4.0, 0.0, -3.0),
+            Vec3::new(8.0, 1.0, 6.0),
+            material(),
+        )
+    }
+
+    // Reference result testing every triangle
+    fn brute_force(field: &Heightfield, r: &Ray) -> Option<f64> {
+        let mut closest: Option<f64> = None;
+        for j in 0..field.nz - 1 {
+            for i in 0..field.nx - 1 {
+                let limit = closest.unwrap_or(f64::INFINITY);
+                if let Some(rec) = field.hit_cell(r, i, j, 0.001, limit) {
+                    closest = Some(rec.t);
+                }
+            }
+        }
+        closest
+    }
+
+    #[test]
+    fn flat_test() {
+        let field = Heightfield::new(
+            2,
+            2,
+            &[0.5; 4],
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 2.0, 1.0),
+            material(),
+        );
+        let r = Ray::new(Point3::new(0.25, 5.0, 0.75), Vec3::new(0.0, -1.0, 0.0));
+        let rec = field.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 4.0);
+        assert_eq!(rec.normal, Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!((rec.u, rec.v), (0.25, 0.75));
+    }
+
+    #[test]
+    fn dda_matches_brute_force_test() {
+        let field = bumps();
+        let mut hits = 0;
+        for n in 0..200 {
+            let a = n as f64 * 0.37;
+            let r = Ray::new(
+                Point3::new(6.0 * a.cos(), 1.5, 5.0 * a.sin()),
+                Vec3::new(-a.cos() + 0.1 * a.sin(), -0.2 - 0.001 * n as f64, -a.sin()),
+            );
+            let expected = brute_force(&field, &r);
+            let actual = field.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t);
+            assert_eq!(expected, actual, "ray {}", n);
+            hits += actual.is_some() as usize;
+        }
+        assert!(hits > 100, "{}", hits);
+    }
+
+    #[test]
+    fn load_16_bit_test() {
+        let path = std::env::temp_dir().join("heightfield_load_16_bit_test.png");
+        let img = image::ImageBuffer::<image::Luma<u16>, Vec<u16>>::from_raw(
+            3,
+            2,
+            vec![0, 32768, 65535, 0, 1, 2],
+        )
+        .unwrap();
+        img.save(&path).unwrap();
+
+        let field = Heightfield::load(
+            &path,
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(2.0, 1.0, 1.0),
+            material(),
+        )
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((field.nx, field.nz), (3, 2));
+        assert_eq!(field.vertex(2, 0), Point3::new(2.0, 1.0, 0.0));
+        assert!((field.vertex(1, 0).y() - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn load_too_small_test() {
+        let path = std::env::temp_dir().join("heightfield_load_too_small_test.png");
+        image::GrayImage::new(4, 1).save(&path).unwrap();
+
+        let field = Heightfield::load(
+            &path,
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 1.0, 1.0),
+            material(),
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert!(field.is_err());
+    }
+}
@@ -1,5 +1,6 @@
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
+use crate::aabb::*;
 use crate::material::*;
 use crate::vec3::*;
 use crate::ray::Ray;
@@ -10,22 +11,38 @@ pub struct HitRecord {
     pub normal: Vec3,
     pub material: Arc<dyn Material>,
     pub t: f64,
+    pub u: f64,
+    pub v: f64,
+    /// Direction of increasing u, or zero where the surface does not provide
+    /// one
+    pub tangent: Vec3,
+    /// Color given by the surface itself, such as interpolated vertex
+    /// colors, for materials like `VertexColor`. White where it has none.
+    pub color: Color,
     pub front_face: bool,
 }
 
-impl HitRecord {
-    pub fn default() -> HitRecord {
+impl Default for HitRecord {
+    fn default() -> HitRecord {
+        // Shared so that records filled in per hit do not allocate
+        static MATERIAL: OnceLock<Arc<Lambertian>> = OnceLock::new();
         HitRecord {
             p: Point3::default(),
             normal: Vec3::default(),
-            material: Arc::new(Lambertian {
-                albedo: Color::default(),
-            }),
+            material: MATERIAL
+                .get_or_init(|| Arc::new(Lambertian::new(Color::default())))
+                .clone(),
             t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            tangent: Vec3::default(),
+            color: Color::new(1.0, 1.0, 1.0),
             front_face: false,
         }
     }
+}
 
+impl HitRecord {
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: &Vec3) {
         self.front_face = dot(&r.direction(), outward_normal) < 0.0;
         self.normal = if self.front_face {
@@ -36,8 +53,62 @@ impl HitRecord {
     }
 }
 
+/// Stretch of a ray inside a closed object, between the surface hits where
+/// the ray enters and exits it
+#[derive(Clone)]
+pub struct HitInterval {
+    pub enter: HitRecord,
+    pub exit: HitRecord,
+}
+
+/// Union of the intervals of several objects, in order. Overlapping
+/// intervals are joined, so a ray entering a second object while inside the
+/// first stays inside until it has left both.
+pub fn merge_intervals(mut intervals: Vec<HitInterval>) -> Vec<HitInterval> {
+    intervals.sort_by(|a, b| a.enter.t.total_cmp(&b.enter.t));
+    let mut merged: Vec<HitInterval> = Vec::new();
+    for interval in intervals {
+        match merged.last_mut() {
+            Some(last) if interval.enter.t <= last.exit.t => {
+                if interval.exit.t > last.exit.t {
+                    last.exit = interval.exit;
+                }
+            }
+            _ => merged.push(interval),
+        }
+    }
+    merged
+}
+
 pub trait Hittable {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+
+    /// `None` for unbounded objects
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    /// Every interval along the whole line of the ray (negative t included)
+    /// where it is inside the object, in order. Only meaningful for closed
+    /// objects.
+    ///
+    /// By default the hits are collected one by one with `hit` and paired
+    /// up by `front_face`.
+    fn intervals(&self, r: &Ray) -> Vec<HitInterval> {
+        let mut intervals = Vec::new();
+        let mut enter: Option<HitRecord> = None;
+        let mut t = f64::NEG_INFINITY;
+
+        while let Some(rec) = self.hit(r, t, f64::INFINITY) {
+            t = rec.t + 1e-9 * rec.t.abs().max(1.0);
+            if rec.front_face {
+                // Keep the outermost entry if surfaces are nested
+                enter.get_or_insert(rec);
+            } else if let Some(enter) = enter.take() {
+                intervals.push(HitInterval { enter, exit: rec });
+            }
+        }
+
+        intervals
+    }
 }
 
 #[derive(Default)]
@@ -65,4 +136,19 @@ impl Hittable for HittableList {
 
         temp_record
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut boxes = self.objects.iter().map(|object| object.bounding_box());
+        let first = boxes.next()??;
+        boxes.try_fold(first, |acc, b| Some(surrounding_box(&acc, &b?)))
+    }
+
+    fn intervals(&self, r: &Ray) -> Vec<HitInterval> {
+        merge_intervals(
+            self.objects
+                .iter()
+                .flat_map(|object| object.intervals(r))
+                .collect(),
+        )
+    }
 }
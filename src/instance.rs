@@ -0,0 +1,142 @@
+use std::sync::Arc;
+
+use crate::aabb::*;
+use crate::hittable::*;
+use crate::ray::*;
+use crate::vec3::*;
+
+/// Places a shared object in the world through an affine transform.
+///
+/// Rays are moved into the object's space, intersected there and the hit is
+/// moved back, so the same object can be referenced by many instances.
+pub struct Instance {
+    object: Arc<dyn Hittable + Send + Sync>,
+    transform: Transform,
+}
+
+impl Instance {
+    /// Panics if `transform` is not invertible
+    pub fn new(object: Arc<dyn Hittable + Send + Sync>, matrix: Mat4) -> Instance {
+        let transform = Transform::new(matrix).expect("instance transform must be invertible");
+        Instance::with_transform(object, transform)
+    }
+
+    pub fn with_transform(
+        object: Arc<dyn Hittable + Send + Sync>,
+        transform: Transform,
+    ) -> Instance {
+        Instance { object, transform }
+    }
+
+    pub fn translate(object: Arc<dyn Hittable + Send + Sync>, offset: Vec3) -> Instance {
+        Instance::new(object, Mat4::translation(offset))
+    }
+
+    pub fn rotate(object: Arc<dyn Hittable + Send + Sync>, axis: Vec3, degrees: f64) -> Instance {
+        Instance::new(object, Mat4::rotation(axis, degrees))
+    }
+
+    pub fn scale(object: Arc<dyn Hittable + Send + Sync>, factors: Vec3) -> Instance {
+        Instance::new(object, Mat4::scaling(factors))
+    }
+
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+}
+
+// The direction is not normalized, so t is the same in both spaces
+pub(crate) fn object_ray(transform: &Transform, r: &Ray) -> Ray {
+    let to_object = transform.inverted();
+    Ray::new(
+        to_object.point(&r.origin()),
+        to_object.vector(&r.direction()),
+    )
+}
+
+pub(crate) fn to_world(transform: &Transform, mut rec: HitRecord) -> HitRecord {
+    rec.p = transform.point(&rec.p);
+    // The sign of dot(normal, direction) survives the transform, so
+    // front_face stays valid
+    rec.normal = unit_vector(transform.normal(&rec.normal));
+    if !rec.tangent.near_zero() {
+        rec.tangent = unit_vector(transform.vector(&rec.tangent));
+    }
+    rec
+}
+
+/// World space box around an object space box
+pub(crate) fn transform_bounds(transform: &Transform, bbox: &Aabb) -> Aabb {
+    let corners = bbox.corners();
+    let first = transform.point(&corners[0]);
+    corners
+        .iter()
+        .skip(1)
+        .fold(Aabb::new(first, first), |acc, c| {
+            let p = transform.point(c);
+            surrounding_box(&acc, &Aabb::new(p, p))
+        })
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let rec = self
+            .object
+            .hit(&object_ray(&self.transform, r), t_min, t_max)?;
+        Some(to_world(&self.transform, rec))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let bbox = self.object.bounding_box()?;
+        Some(transform_bounds(&self.transform, &bbox))
+    }
+
+    fn intervals(&self, r: &Ray) -> Vec<HitInterval> {
+        self.object
+            .intervals(&object_ray(&self.transform, r))
+            .into_iter()
+            .map(|interval| HitInterval {
+                enter: to_world(&self.transform, interval.enter),
+                exit: to_world(&self.transform, interval.exit),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+
+    fn unit_sphere() -> Arc<dyn Hittable + Send + Sync> {
+        Arc::new(Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        ))
+    }
+
+    #[test]
+    fn translate_test() {
+        let instance = Instance::translate(unit_sphere(), Vec3::new(0.0, 0.0, -5.0));
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let rec = instance.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 4.0);
+        assert_eq!(rec.p, Point3::new(0.0, 0.0, -4.0));
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn scale_normal_test() {
+        // An ellipsoid stretched along x keeps normals perpendicular to it
+        let instance = Instance::scale(unit_sphere(), Vec3::new(2.0, 1.0, 1.0));
+        let r = Ray::new(Point3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+
+        let rec = instance.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 3.0);
+        assert_eq!(rec.normal, Vec3::new(1.0, 0.0, 0.0));
+        assert!(rec.front_face);
+    }
+}
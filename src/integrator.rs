@@ -0,0 +1,516 @@
+//! Ways of turning a camera ray into a color.
+//!
+//! `PathTracer` is the book's renderer. The others are for look-dev and
+//! debugging and can be picked by name at runtime with `by_name`.
+
+use crate::hittable::*;
+use crate::ray::Ray;
+use crate::render::SplatBuffer;
+use crate::vec3::*;
+
+pub trait Integrator: Send + Sync {
+    /// Light arriving along `r`, or a false color for debug views
+    fn li(&self, r: &Ray, world: &dyn Hittable) -> Color;
+
+    /// `li` for integrators that also carry light to other pixels, which
+    /// they add to `splats` multiplied by `mask`. Callers keeping only some
+    /// color channels of the result mask out the others.
+    fn li_splat(
+        &self,
+        r: &Ray,
+        world: &dyn Hittable,
+        _splats: &SplatBuffer,
+        _mask: &Color,
+    ) -> Color {
+        self.li(r, world)
+    }
+}
+
+/// Names accepted by `by_name`
+pub const NAMES: [&str; 7] = ["path", "ao", "whitted", "normals", "uv", "depth", "bounces"];
+
+/// An integrator with default settings, `None` for unknown names
+pub fn by_name(name: &str, max_depth: usize) -> Option<Box<dyn Integrator>> {
+    let integrator: Box<dyn Integrator> = match name {
+        "path" => Box::new(PathTracer::new(max_depth)),
+        "ao" => Box::new(AmbientOcclusion::new(1.0)),
+        "whitted" => Box::new(Whitted::new(max_depth).with_light(Light::Directional {
+            direction: Vec3::new(-1.0, -2.0, -1.0),
+            irradiance: Color::new(3.0, 3.0, 3.0),
+        })),
+        "normals" => Box::new(Normals),
+        "uv" => Box::new(Uv),
+        "depth" => Box::new(Depth::new(20.0)),
+        "bounces" => Box::new(BounceHeatmap::new(max_depth)),
+        _ => return None,
+    };
+    Some(integrator)
+}
+
+/// The sky gradient seen by rays that hit nothing
+pub fn background(r: &Ray) -> Color {
+    let unit_direction = unit_vector(r.direction());
+    let t = 0.5 * (unit_direction.y() + 1.0);
+    (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
+}
+
+/// Light arriving along rays that leave the scene
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Background {
+    /// The sky gradient of `background`
+    #[default]
+    Sky,
+    /// The same radiance from every direction, like a constant infinite
+    /// light
+    Constant(Color),
+}
+
+impl Background {
+    pub fn radiance(&self, r: &Ray) -> Color {
+        match self {
+            Background::Sky => background(r),
+            Background::Constant(l) => *l,
+        }
+    }
+}
+
+// Pixels are gamma corrected with a square root, squaring first keeps
+// false colors as they are
+fn false_color(c: Color) -> Color {
+    c * c
+}
+
+/// Follows rays through random scattering until they leave the scene.
+///
+/// Paths are traced in a loop carrying their throughput. After
+/// `roulette_depth` bounces Russian roulette ends dim paths early and
+/// brightens the survivors to compensate, so the image stays unbiased while
+/// `max_depth` only caps the rare long path.
+pub struct PathTracer {
+    pub max_depth: usize,
+    pub roulette_depth: usize,
+    pub background: Background,
+}
+
+impl PathTracer {
+    pub fn new(max_depth: usize) -> PathTracer {
+        PathTracer {
+            max_depth,
+            roulette_depth: 3,
+            background: Background::Sky,
+        }
+    }
+
+    pub fn with_background(mut self, background: Background) -> PathTracer {
+        self.background = background;
+        self
+    }
+
+    pub fn with_roulette_depth(mut self, roulette_depth: usize) -> PathTracer {
+        self.roulette_depth = roulette_depth;
+        self
+    }
+
+    /// Light arriving along `r` and the number of times the path scattered
+    pub fn trace(&self, r: &Ray, world: &dyn Hittable) -> (Color, usize) {
+        let mut radiance = Color::new(0.0, 0.0, 0.0);
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = Ray::new(r.origin(), r.direction());
+
+        for bounce in 0..self.max_depth {
+            let rec = match world.hit(&ray, 0.001, f64::INFINITY) {
+                Some(rec) => rec,
+                None => {
+                    return (
+                        radiance + throughput * self.background.radiance(&ray),
+                        bounce,
+                    )
+                }
+            };
+            radiance += throughput * rec.material.emitted_at(&rec);
+            let (attenuation, scattered) = match rec.material.scatter(&ray, &rec) {
+                Some(scatter) => scatter,
+                None => return (radiance, bounce),
+            };
+            throughput = throughput * attenuation;
+
+            // Survive with a probability following the throughput, capped
+            // so that even bright paths end eventually
+            if bounce + 1 >= self.roulette_depth {
+                let survival = throughput
+                    .x()
+                    .max(throughput.y())
+                    .max(throughput.z())
+                    .min(0.95);
+                if random_double(0.0..1.0) >= survival {
+                    return (radiance, bounce + 1);
+                }
+                throughput /= survival;
+            }
+            ray = scattered;
+        }
+
+        // If we've exceeded the ray bounce limit, no more light is gathered
+        (radiance, self.max_depth)
+    }
+}
+
+impl Integrator for PathTracer {
+    fn li(&self, r: &Ray, world: &dyn Hittable) -> Color {
+        self.trace(r, world).0
+    }
+}
+
+/// White where the hemisphere around the first hit is open, darker where
+/// geometry within `max_distance` blocks it
+pub struct AmbientOcclusion {
+    pub max_distance: f64,
+    /// Occlusion rays per camera ray
+    pub samples: usize,
+}
+
+impl AmbientOcclusion {
+    pub fn new(max_distance: f64) -> AmbientOcclusion {
+        AmbientOcclusion {
+            max_distance,
+            samples: 1,
+        }
+    }
+
+    pub fn with_samples(mut self, samples: usize) -> AmbientOcclusion {
+        self.samples = samples.max(1);
+        self
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn li(&self, r: &Ray, world: &dyn Hittable) -> Color {
+        let rec = match world.hit(r, 0.001, f64::INFINITY) {
+            Some(rec) => rec,
+            None => return Color::new(1.0, 1.0, 1.0),
+        };
+
+        // Cosine weighted directions, as Lambertian scatters
+        let open = (0..self.samples)
+            .filter(|_| {
+                let mut direction = rec.normal + Vec3::random_unit_vector();
+                if direction.near_zero() {
+                    direction = rec.normal;
+                }
+                let probe = Ray::new(rec.p, unit_vector(direction));
+                world.hit(&probe, 0.001, self.max_distance).is_none()
+            })
+            .count();
+        let visibility = open as f64 / self.samples as f64;
+        Color::new(visibility, visibility, visibility)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Light {
+    /// Intensity falls off with the squared distance
+    Point { position: Point3, intensity: Color },
+    /// Parallel light travelling along `direction`, like the sun
+    Directional { direction: Vec3, irradiance: Color },
+}
+
+/// Direct light from point and directional lights with hard shadows on
+/// diffuse surfaces, and recursion through specular ones
+pub struct Whitted {
+    pub max_depth: usize,
+    pub lights: Vec<Light>,
+    /// Light reaching every diffuse surface regardless of shadows
+    pub ambient: Color,
+    pub background: Background,
+}
+
+impl Whitted {
+    pub fn new(max_depth: usize) -> Whitted {
+        Whitted {
+            max_depth,
+            lights: Vec::new(),
+            ambient: Color::new(0.1, 0.1, 0.1),
+            background: Background::Sky,
+        }
+    }
+
+    pub fn with_light(mut self, light: Light) -> Whitted {
+        self.lights.push(light);
+        self
+    }
+
+    pub fn with_ambient(mut self, ambient: Color) -> Whitted {
+        self.ambient = ambient;
+        self
+    }
+
+    pub fn with_background(mut self, background: Background) -> Whitted {
+        self.background = background;
+        self
+    }
+
+    // Irradiance at `rec` from every light it can see
+    fn direct(&self, rec: &HitRecord, world: &dyn Hittable) -> Color {
+        let mut irradiance = Color::default();
+        for light in &self.lights {
+            let (to_light, distance, arriving) = match *light {
+                Light::Point {
+                    position,
+                    intensity,
+                } => {
+                    let d = position - rec.p;
+                    let distance = d.length();
+                    (d / distance, distance, intensity / (distance * distance))
+                }
+                Light::Directional {
+                    direction,
+                    irradiance,
+                } => (-unit_vector(direction), f64::INFINITY, irradiance),
+            };
+            let cos = dot(&rec.normal, &to_light);
+            if cos <= 0.0 {
+                continue;
+            }
+            let shadow = Ray::new(rec.p, to_light);
+            if world.hit(&shadow, 0.001, distance).is_none() {
+                irradiance += cos * arriving;
+            }
+        }
+        irradiance
+    }
+
+    fn trace(&self, r: &Ray, world: &dyn Hittable, depth: usize) -> Color {
+        if depth == 0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let rec = match world.hit(r, 0.001, f64::INFINITY) {
+            Some(rec) => rec,
+            None => return self.background.radiance(r),
+        };
+
+        let emitted = rec.material.emitted_at(&rec);
+        let (attenuation, scattered) = match rec.material.scatter(r, &rec) {
+            Some(scatter) => scatter,
+            None => return emitted,
+        };
+        if rec.material.is_specular() {
+            return emitted + attenuation * self.trace(&scattered, world, depth - 1);
+        }
+
+        // The attenuation of a diffuse bounce is its albedo
+        let radiance = self.direct(&rec, world) / std::f64::consts::PI + self.ambient;
+        emitted + attenuation * radiance
+    }
+}
+
+impl Integrator for Whitted {
+    fn li(&self, r: &Ray, world: &dyn Hittable) -> Color {
+        self.trace(r, world, self.max_depth)
+    }
+}
+
+/// Shading normals of the first hit mapped from [-1, 1] to [0, 1]
+pub struct Normals;
+
+impl Integrator for Normals {
+    fn li(&self, r: &Ray, world: &dyn Hittable) -> Color {
+        match world.hit(r, 0.001, f64::INFINITY) {
+            Some(rec) => false_color(0.5 * (rec.normal + Vec3::new(1.0, 1.0, 1.0))),
+            None => Color::default(),
+        }
+    }
+}
+
+/// Texture coordinates of the first hit in red and green
+pub struct Uv;
+
+impl Integrator for Uv {
+    fn li(&self, r: &Ray, world: &dyn Hittable) -> Color {
+        match world.hit(r, 0.001, f64::INFINITY) {
+            Some(rec) => false_color(Color::new(
+                rec.u.clamp(0.0, 1.0),
+                rec.v.clamp(0.0, 1.0),
+                0.0,
+            )),
+            None => Color::default(),
+        }
+    }
+}
+
+/// Distance to the first hit along the ray, black up close to white at
+/// `max_distance` and beyond
+pub struct Depth {
+    pub max_distance: f64,
+}
+
+impl Depth {
+    pub fn new(max_distance: f64) -> Depth {
+        Depth { max_distance }
+    }
+}
+
+impl Integrator for Depth {
+    fn li(&self, r: &Ray, world: &dyn Hittable) -> Color {
+        let depth = world.hit(r, 0.001, f64::INFINITY).map_or(1.0, |rec| {
+            (rec.t * r.direction().length() / self.max_distance).min(1.0)
+        });
+        false_color(Color::new(depth, depth, depth))
+    }
+}
+
+/// Number of times a path tracer path scatters before it ends, from blue
+/// for none over green to red for `max_depth`
+pub struct BounceHeatmap {
+    pub path_tracer: PathTracer,
+}
+
+impl BounceHeatmap {
+    pub fn new(max_depth: usize) -> BounceHeatmap {
+        BounceHeatmap {
+            path_tracer: PathTracer::new(max_depth),
+        }
+    }
+
+    pub fn bounces(&self, r: &Ray, world: &dyn Hittable) -> usize {
+        self.path_tracer.trace(r, world).1
+    }
+}
+
+impl Integrator for BounceHeatmap {
+    fn li(&self, r: &Ray, world: &dyn Hittable) -> Color {
+        let max_depth = self.path_tracer.max_depth.max(1);
+        let heat = self.bounces(r, world) as f64 / max_depth as f64;
+        let color = if heat < 0.5 {
+            Color::new(0.0, 2.0 * heat, 1.0 - 2.0 * heat)
+        } else {
+            Color::new(2.0 * heat - 1.0, 2.0 - 2.0 * heat, 0.0)
+        };
+        false_color(color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::{Lambertian, Material, Metal};
+    use crate::plane::Plane;
+    use crate::sphere::Sphere;
+    use std::sync::Arc;
+
+    fn floor(material: Arc<dyn Material + Send + Sync>) -> HittableList {
+        let mut world = HittableList::default();
+        world.add(Arc::new(Plane::new(
+            Point3::default(),
+            Vec3::new(0.0, 1.0, 0.0),
+            material,
+        )));
+        world
+    }
+
+    fn down() -> Ray {
+        Ray::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0))
+    }
+
+    #[test]
+    fn by_name_test() {
+        for name in NAMES {
+            assert!(by_name(name, 10).is_some(), "{}", name);
+        }
+        assert!(by_name("nope", 10).is_none());
+    }
+
+    #[test]
+    fn whitted_test() {
+        let world = floor(Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        let whitted = Whitted::new(5)
+            .with_ambient(Color::default())
+            .with_light(Light::Point {
+                position: Point3::new(0.0, 2.0, 0.0),
+                intensity: Color::new(4.0, 4.0, 4.0),
+            });
+        // albedo / pi * I / d^2
+        let expected = 0.5 / std::f64::consts::PI;
+        assert!((whitted.li(&down(), &world).x() - expected).abs() < 1e-12);
+
+        // A blocker between the floor and the light casts a shadow
+        let mut world = world;
+        world.add(Arc::new(Sphere::new(
+            Point3::new(0.0, 1.5, 0.0),
+            0.1,
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        )));
+        let r = Ray::new(Point3::new(0.0, 1.0, 0.5), Vec3::new(0.0, -1.0, -0.5));
+        assert_eq!(whitted.li(&r, &world), Color::default());
+
+        // Mirrors reflect the sky
+        let mirror = floor(Arc::new(Metal::new(Color::new(1.0, 1.0, 1.0), 0.0)));
+        let up = Ray::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(whitted.li(&down(), &mirror), background(&up));
+    }
+
+    #[test]
+    fn debug_views_test() {
+        let world = floor(Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))));
+        assert_eq!(Normals.li(&down(), &world), Color::new(0.25, 1.0, 0.25));
+        assert_eq!(
+            Depth::new(4.0).li(&down(), &world),
+            Color::new(0.0625, 0.0625, 0.0625)
+        );
+        assert_eq!(
+            AmbientOcclusion::new(1.0).li(&down(), &world),
+            Color::new(1.0, 1.0, 1.0)
+        );
+        // The floor is hit once, the scattered ray escapes
+        assert_eq!(BounceHeatmap::new(4).bounces(&down(), &world), 1);
+    }
+
+    // Glows and reflects half the light it receives
+    struct Glow;
+
+    impl Material for Glow {
+        fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+            let (_, scattered) = Lambertian::new(Color::default()).scatter(r_in, rec)?;
+            Some((Color::new(0.5, 0.5, 0.5), scattered))
+        }
+
+        fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+            Color::new(1.0, 1.0, 1.0)
+        }
+    }
+
+    #[test]
+    fn russian_roulette_test() {
+        // Inside a closed glowing sphere every bounce adds half of the
+        // previous one, 1 + 1/2 + 1/4 + ... = 2
+        let mut world = HittableList::default();
+        world.add(Arc::new(Sphere::new(
+            Point3::default(),
+            1.0,
+            Arc::new(Glow),
+        )));
+        let tracer = PathTracer::new(1000);
+        let r = Ray::new(Point3::default(), Vec3::new(0.0, 0.0, -1.0));
+
+        let n = 20000;
+        let (mut sum, mut bounces) = (0.0, 0);
+        for _ in 0..n {
+            let (color, b) = tracer.trace(&r, &world);
+            sum += color.x();
+            bounces += b;
+        }
+        assert!((sum / n as f64 - 2.0).abs() < 0.05);
+        // Without roulette every path would run into the depth limit
+        assert!(bounces / n < 10);
+    }
+
+    #[test]
+    fn background_test() {
+        let l = Color::new(0.1, 0.2, 0.3);
+        let mirror = floor(Arc::new(Metal::new(Color::new(1.0, 1.0, 1.0), 0.0)));
+        let tracer = PathTracer::new(10).with_background(Background::Constant(l));
+        assert_eq!(tracer.li(&down(), &mirror), l);
+        let whitted = Whitted::new(10).with_background(Background::Constant(l));
+        assert_eq!(whitted.li(&down(), &mirror), l);
+        assert_eq!(Background::Sky.radiance(&down()), background(&down()));
+    }
+}
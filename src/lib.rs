@@ -0,0 +1,42 @@
+pub mod aabb;
+pub mod aarect;
+pub mod animation;
+pub mod blackbody;
+pub mod bdpt;
+pub mod bvh;
+pub mod camera;
+pub mod constant_medium;
+pub mod csg;
+pub mod curve;
+pub mod gltf_import;
+pub mod hair;
+pub mod heightfield;
+pub mod hittable;
+pub mod instance;
+pub mod integrator;
+pub mod light;
+pub mod material;
+pub mod mesh;
+pub mod mlt;
+pub mod pbr;
+pub mod pbrt;
+pub mod photon;
+pub mod plane;
+pub mod ply;
+pub mod point_cloud;
+pub mod polynomial;
+pub mod quad;
+pub mod quadrics;
+pub mod ray;
+pub mod render;
+pub mod sampler;
+pub mod scene_graph;
+pub mod sdf;
+pub mod sphere;
+#[cfg(test)]
+mod test_scenes;
+pub mod texture;
+pub mod tlas;
+pub mod vec3;
+pub mod volume;
+pub mod y4m;
@@ -0,0 +1,208 @@
+//! Emitters that integrators can sample directly.
+//!
+//! An `AreaLight` knows its shape, so points and emission directions can be
+//! drawn from it. `hittable` gives the matching object for the world, which
+//! shares the light's material so that hits on it can be recognised.
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use crate::hittable::*;
+use crate::material::DiffuseLight;
+use crate::quad::Quad;
+use crate::ray::Ray;
+use crate::sphere::Sphere;
+use crate::vec3::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightShape {
+    /// Emits outwards
+    Sphere { center: Point3, radius: f64 },
+    /// Emits from both faces, like a `Quad` with a `DiffuseLight`
+    Quad { q: Point3, u: Vec3, v: Vec3 },
+}
+
+/// Uniformly emitting surface
+pub struct AreaLight {
+    shape: LightShape,
+    material: Arc<DiffuseLight>,
+}
+
+impl AreaLight {
+    pub fn new(shape: LightShape, emit: Color) -> AreaLight {
+        AreaLight {
+            shape,
+            material: Arc::new(DiffuseLight::new(emit)),
+        }
+    }
+
+    pub fn sphere(center: Point3, radius: f64, emit: Color) -> AreaLight {
+        AreaLight::new(LightShape::Sphere { center, radius }, emit)
+    }
+
+    pub fn quad(q: Point3, u: Vec3, v: Vec3, emit: Color) -> AreaLight {
+        AreaLight::new(LightShape::Quad { q, u, v }, emit)
+    }
+
+    pub fn shape(&self) -> &LightShape {
+        &self.shape
+    }
+
+    pub fn emit(&self) -> Color {
+        self.material.emit
+    }
+
+    /// The light's shape to add to the world
+    pub fn hittable(&self) -> Arc<dyn Hittable + Send + Sync> {
+        match self.shape {
+            LightShape::Sphere { center, radius } => {
+                Arc::new(Sphere::new(center, radius, self.material.clone()))
+            }
+            LightShape::Quad { q, u, v } => Arc::new(Quad::new(q, u, v, self.material.clone())),
+        }
+    }
+
+    /// Whether `rec` is a hit on this light's `hittable`
+    pub fn is_hit(&self, rec: &HitRecord) -> bool {
+        Arc::as_ptr(&rec.material) as *const () == Arc::as_ptr(&self.material) as *const ()
+    }
+
+    pub fn area(&self) -> f64 {
+        match self.shape {
+            LightShape::Sphere { radius, .. } => 4.0 * PI * radius * radius,
+            LightShape::Quad { u, v, .. } => cross(&u, &v).length(),
+        }
+    }
+
+    fn two_sided(&self) -> bool {
+        matches!(self.shape, LightShape::Quad { .. })
+    }
+
+    /// A uniformly distributed point on the surface and the normal there
+    pub fn sample_point(&self) -> (Point3, Vec3) {
+        match self.shape {
+            LightShape::Sphere { center, radius } => {
+                let n = Vec3::random_unit_vector();
+                (center + radius * n, n)
+            }
+            LightShape::Quad { q, u, v } => {
+                let p = q + random_double(0.0..1.0) * u + random_double(0.0..1.0) * v;
+                (p, unit_vector(cross(&u, &v)))
+            }
+        }
+    }
+
+    /// Radiance leaving a point with normal `n` in the unit direction `w`
+    pub fn radiance(&self, n: &Vec3, w: &Vec3) -> Color {
+        if self.two_sided() || dot(n, w) > 0.0 {
+            self.emit()
+        } else {
+            Color::default()
+        }
+    }
+
+    /// A cosine weighted emission direction from a point with normal `n`
+    /// and its density over solid angle
+    pub fn sample_direction(&self, n: &Vec3) -> (Vec3, f64) {
+        let side = if self.two_sided() && random_double(0.0..1.0) < 0.5 {
+            -*n
+        } else {
+            *n
+        };
+        let mut direction = side + Vec3::random_unit_vector();
+        if direction.near_zero() {
+            direction = side;
+        }
+        let direction = unit_vector(direction);
+        (direction, self.pdf_direction(n, &direction))
+    }
+
+    pub fn pdf_direction(&self, n: &Vec3, w: &Vec3) -> f64 {
+        let cos = dot(n, w);
+        if self.two_sided() {
+            cos.abs() / (2.0 * PI)
+        } else {
+            cos.max(0.0) / PI
+        }
+    }
+}
+
+/// A uniformly picked light with its index and the probability of picking
+/// it, `None` if there are no lights
+pub fn pick_light(lights: &[AreaLight]) -> Option<(usize, &AreaLight, f64)> {
+    if lights.is_empty() {
+        return None;
+    }
+    let index = ((random_double(0.0..1.0) * lights.len() as f64) as usize).min(lights.len() - 1);
+    Some((index, &lights[index], 1.0 / lights.len() as f64))
+}
+
+/// Light from one point on a randomly picked light reflected at `rec`
+/// towards `wo`, divided by the probability of sampling it. Black for
+/// materials without a BSDF to `eval`.
+pub fn direct_lighting(
+    lights: &[AreaLight],
+    world: &dyn Hittable,
+    rec: &HitRecord,
+    wo: &Vec3,
+) -> Color {
+    let (_, light, pick_pdf) = match pick_light(lights) {
+        Some(pick) => pick,
+        None => return Color::default(),
+    };
+    let (p, n) = light.sample_point();
+    let d = p - rec.p;
+    let dist = d.length();
+    let wi = d / dist;
+    let f = match rec.material.eval(rec, wo, &wi) {
+        Some((f, _)) => f,
+        None => return Color::default(),
+    };
+    let emit = light.radiance(&n, &-wi);
+    if f.near_zero() || emit.near_zero() {
+        return Color::default();
+    }
+    let shadow = Ray::new(rec.p, wi);
+    if world.hit(&shadow, 0.001, dist - 0.001).is_some() {
+        return Color::default();
+    }
+    let g = dot(&wi, &rec.normal).abs() * dot(&wi, &n).abs() / (dist * dist);
+    f * emit * (g * light.area() / pick_pdf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn light_test() {
+        let light = AreaLight::quad(
+            Point3::new(0.0, 1.0, 0.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 3.0),
+            Color::new(4.0, 4.0, 4.0),
+        );
+        assert_eq!(light.area(), 6.0);
+
+        let r = Ray::new(Point3::new(0.5, 0.0, 0.5), Vec3::new(0.0, 1.0, 0.0));
+        let rec = light.hittable().hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!(light.is_hit(&rec));
+        let other = AreaLight::sphere(Point3::default(), 1.0, Color::new(4.0, 4.0, 4.0));
+        assert!(!other.is_hit(&rec));
+
+        for _ in 0..100 {
+            let (p, n) = light.sample_point();
+            assert!(p.y() == 1.0 && (0.0..=2.0).contains(&p.x()) && (0.0..=3.0).contains(&p.z()));
+            let (w, pdf) = light.sample_direction(&n);
+            assert!((pdf - dot(&n, &w).abs() / (2.0 * PI)).abs() < 1e-12);
+        }
+
+        assert!(pick_light(&[]).is_none());
+        let lights = [light, other];
+        for _ in 0..10 {
+            let (index, picked, pdf) = pick_light(&lights).unwrap();
+            assert!(std::ptr::eq(picked, &lights[index]));
+            assert_eq!(pdf, 0.5);
+        }
+    }
+}
@@ -1,10 +1,36 @@
-use rand::Rng;
+use std::f64::consts::PI;
 
 use crate::hittable::*;
 use crate::ray::*;
 use crate::vec3::*;
 pub trait Material {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>;
+
+    /// Light given off at the hit point, black for non-emitting materials
+    fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    /// `emitted` for a ray hitting `rec`, for materials whose light depends
+    /// on the side that was hit
+    fn emitted_at(&self, rec: &HitRecord) -> Color {
+        self.emitted(rec.u, rec.v, &rec.p)
+    }
+
+    /// Mirror-like materials, which Whitted-style integrators follow instead
+    /// of shading them with direct light
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    /// BSDF value f(wo, wi) and the density over solid angle with which
+    /// `scatter` picks `wi` for light leaving towards `wo`, for integrators
+    /// that connect paths. Both directions are unit vectors pointing away
+    /// from the surface. `None` for materials that can only be sampled, such
+    /// as mirrors, which are then reached by scattering alone.
+    fn eval(&self, _rec: &HitRecord, _wo: &Vec3, _wi: &Vec3) -> Option<(Color, f64)> {
+        None
+    }
 }
 
 pub struct Lambertian {
@@ -29,6 +55,34 @@ impl Material for Lambertian {
         let attenuation = self.albedo;
         Some((attenuation, scattered))
     }
+
+    fn eval(&self, rec: &HitRecord, wo: &Vec3, wi: &Vec3) -> Option<(Color, f64)> {
+        // Reflection only, on the side the light leaves towards
+        let n = if dot(wo, &rec.normal) < 0.0 {
+            -rec.normal
+        } else {
+            rec.normal
+        };
+        let cos = dot(wi, &n);
+        if cos <= 0.0 || dot(wo, &n) <= 0.0 {
+            return Some((Color::default(), 0.0));
+        }
+        Some((self.albedo / PI, cos / PI))
+    }
+}
+
+/// Lambertian reflection of the color the surface puts in the hit record,
+/// such as the interpolated vertex colors of a mesh
+pub struct VertexColor;
+
+impl Material for VertexColor {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        Lambertian::new(rec.color).scatter(r_in, rec)
+    }
+
+    fn eval(&self, rec: &HitRecord, wo: &Vec3, wi: &Vec3) -> Option<(Color, f64)> {
+        Lambertian::new(rec.color).eval(rec, wo, wi)
+    }
 }
 
 pub struct Metal {
@@ -59,6 +113,10 @@ impl Material for Metal {
             None
         }
     }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
 }
 
 pub struct Dielectric {
@@ -93,8 +151,7 @@ impl Material for Dielectric {
         let cos_theta = 1.0_f64.min(dot(&(-1.0 * unit_direction), &rec.normal));
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
         
-        let mut rng = rand::thread_rng();
-        let rand_f64 = rng.gen_range(0.0..1.0);
+        let rand_f64 = random_double(0.0..1.0);
 
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
         let direction = if cannot_refract || Self::reflectance(cos_theta, refraction_ratio) > rand_f64 {
@@ -106,4 +163,117 @@ impl Material for Dielectric {
         let scattered = Ray::new(rec.p, direction);
         Some((attenuation, scattered))
     }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+}
+
+/// Emits a constant color and scatters nothing
+pub struct DiffuseLight {
+    pub emit: Color,
+    /// Emit only from the side the surface normal points to
+    pub one_sided: bool,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> DiffuseLight {
+        DiffuseLight {
+            emit,
+            one_sided: false,
+        }
+    }
+
+    pub fn one_sided(emit: Color) -> DiffuseLight {
+        DiffuseLight {
+            emit,
+            one_sided: true,
+        }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<(Color, Ray)> {
+        None
+    }
+
+    fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
+        self.emit
+    }
+
+    fn emitted_at(&self, rec: &HitRecord) -> Color {
+        if self.one_sided && !rec.front_face {
+            Color::default()
+        } else {
+            self.emit
+        }
+    }
+}
+
+/// Phase function scattering light equally in all directions, used inside
+/// participating media
+pub struct Isotropic {
+    pub albedo: Color,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Color) -> Isotropic {
+        Isotropic { albedo }
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        let scattered = Ray::new(rec.p, Vec3::random_unit_vector());
+        Some((self.albedo, scattered))
+    }
+}
+
+/// Henyey-Greenstein phase function. `g` in (-1, 1) is the mean cosine of
+/// the scattering angle: positive values scatter forward, negative backward
+/// and zero is isotropic.
+pub struct HenyeyGreenstein {
+    pub albedo: Color,
+    pub g: f64,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(albedo: Color, g: f64) -> HenyeyGreenstein {
+        HenyeyGreenstein {
+            albedo,
+            g: g.clamp(-0.999, 0.999),
+        }
+    }
+
+    /// Samples the cosine of the angle between the incoming propagation
+    /// direction and the scattered direction
+    pub fn sample_cos_theta(g: f64, xi: f64) -> f64 {
+        if g.abs() < 1e-3 {
+            return 1.0 - 2.0 * xi;
+        }
+
+        let sqr_term = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+        ((1.0 + g * g - sqr_term * sqr_term) / (2.0 * g)).clamp(-1.0, 1.0)
+    }
+}
+
+impl Material for HenyeyGreenstein {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        let cos_theta = Self::sample_cos_theta(self.g, random_double(0.0..1.0));
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = random_double(0.0..(2.0 * std::f64::consts::PI));
+
+        let w = unit_vector(r_in.direction());
+        let a = if w.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = unit_vector(cross(&w, &a));
+        let u = cross(&w, &v);
+
+        let direction = sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * w;
+        let scattered = Ray::new(rec.p, direction);
+        Some((self.albedo, scattered))
+    }
 }
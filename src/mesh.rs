@@ -0,0 +1,534 @@
+//! Indexed triangle meshes and loaders for Stanford PLY and binary STL.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::aabb::*;
+use crate::bvh::FlatBvh;
+use crate::hittable::*;
+use crate::material::*;
+use crate::ply;
+use crate::ray::*;
+use crate::vec3::*;
+
+/// Triangles indexing into shared vertex arrays. `normals`, `uvs` and
+/// `colors` are either empty or hold one entry per position.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TriangleMesh {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub colors: Vec<Color>,
+    pub indices: Vec<[u32; 3]>,
+}
+
+impl TriangleMesh {
+    pub fn new(positions: Vec<Point3>, indices: Vec<[u32; 3]>) -> TriangleMesh {
+        TriangleMesh {
+            positions,
+            indices,
+            ..Default::default()
+        }
+    }
+
+    pub fn vertices(&self, triangle: &[u32; 3]) -> [Point3; 3] {
+        triangle.map(|i| self.positions[i as usize])
+    }
+
+    /// Unnormalized normal of a triangle, with length twice its area
+    pub fn face_normal(&self, triangle: &[u32; 3]) -> Vec3 {
+        let [a, b, c] = self.vertices(triangle);
+        cross(&(b - a), &(c - a))
+    }
+
+    /// Replaces the vertex normals with area weighted averages of the face
+    /// normals around each vertex. Faces meeting at more than
+    /// `angle_degrees` keep a crease, which splits the vertices along it;
+    /// 0 gives a faceted mesh.
+    pub fn smooth_normals(&mut self, angle_degrees: f64) {
+        let cos_limit = angle_degrees.to_radians().cos() - 1e-9;
+        let face_normals: Vec<Vec3> = self.indices.iter().map(|t| self.face_normal(t)).collect();
+        let directions: Vec<Vec3> = face_normals
+            .iter()
+            .map(|n| if n.near_zero() { *n } else { unit_vector(*n) })
+            .collect();
+
+        let mut incident = vec![Vec::new(); self.positions.len()];
+        for (face, triangle) in self.indices.iter().enumerate() {
+            for &v in triangle {
+                incident[v as usize].push(face);
+            }
+        }
+
+        let mut smoothed = TriangleMesh::default();
+        let mut remap: HashMap<(u32, [u64; 3]), u32> = HashMap::new();
+        for (face, triangle) in self.indices.iter().enumerate() {
+            let corners = triangle.map(|v| {
+                let sum = incident[v as usize]
+                    .iter()
+                    .filter(|&&g| dot(&directions[face], &directions[g]) >= cos_limit)
+                    .fold(Vec3::default(), |acc, &g| acc + face_normals[g]);
+                let normal = if sum.near_zero() {
+                    directions[face]
+                } else {
+                    unit_vector(sum)
+                };
+
+                let key = (v, [normal.x(), normal.y(), normal.z()].map(f64::to_bits));
+                *remap.entry(key).or_insert_with(|| {
+                    let i = v as usize;
+                    smoothed.positions.push(self.positions[i]);
+                    smoothed.normals.push(normal);
+                    if !self.uvs.is_empty() {
+                        smoothed.uvs.push(self.uvs[i]);
+                    }
+                    if !self.colors.is_empty() {
+                        smoothed.colors.push(self.colors[i]);
+                    }
+                    smoothed.positions.len() as u32 - 1
+                })
+            });
+            smoothed.indices.push(corners);
+        }
+
+        *self = smoothed;
+    }
+}
+
+/// A triangle mesh as a hittable, with its own BVH over the triangles
+pub struct Mesh {
+    mesh: TriangleMesh,
+    bvh: FlatBvh,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+impl Mesh {
+    pub fn new(mut mesh: TriangleMesh, material: Arc<dyn Material + Send + Sync>) -> Mesh {
+        let positions = &mesh.positions;
+        let bvh = FlatBvh::build(&mut mesh.indices, |triangle| {
+            triangle_bounds(positions, triangle)
+        });
+        Mesh {
+            mesh,
+            bvh,
+            material,
+        }
+    }
+
+    /// Shades with a `VertexColor` material of the interpolated vertex
+    /// colors instead of the mesh material, where the mesh has colors
+    pub fn with_vertex_colors(mut self) -> Mesh {
+        if !self.mesh.colors.is_empty() {
+            self.material = Arc::new(VertexColor);
+        }
+        self
+    }
+
+    pub fn mesh(&self) -> &TriangleMesh {
+        &self.mesh
+    }
+
+    /// Moves the vertices and refits the BVH instead of rebuilding it, for
+    /// animated meshes that keep their topology. Vertex normals are left as
+    /// they are.
+    ///
+    /// Panics if the vertex count changes.
+    pub fn set_positions(&mut self, positions: Vec<Point3>) {
+        assert_eq!(
+            positions.len(),
+            self.mesh.positions.len(),
+            "vertex count must not change"
+        );
+        self.mesh.positions = positions;
+        let positions = &self.mesh.positions;
+        self.bvh.refit(&self.mesh.indices, |triangle| {
+            triangle_bounds(positions, triangle)
+        });
+    }
+
+    fn hit_triangles(
+        &self,
+        triangles: &[[u32; 3]],
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<HitRecord> {
+        let mut closest: Option<(f64, &[u32; 3], f64, f64)> = None;
+        for triangle in triangles {
+            let [a, b, c] = self.mesh.vertices(triangle);
+            let limit = closest.map_or(t_max, |hit| hit.0);
+            if let Some((t, b1, b2)) = intersect_triangle(r, &a, &b, &c, t_min, limit) {
+                closest = Some((t, triangle, b1, b2));
+            }
+        }
+
+        let (t, triangle, b1, b2) = closest?;
+        let [i, j, k] = triangle.map(|i| i as usize);
+        let b0 = 1.0 - b1 - b2;
+        let normal = if self.mesh.normals.is_empty() {
+            unit_vector(self.mesh.face_normal(triangle))
+        } else {
+            let n = &self.mesh.normals;
+            unit_vector(b0 * n[i] + b1 * n[j] + b2 * n[k])
+        };
+        let (u, v, tangent) = if self.mesh.uvs.is_empty() {
+            (b1, b2, Vec3::default())
+        } else {
+            let uv = &self.mesh.uvs;
+            (
+                b0 * uv[i].0 + b1 * uv[j].0 + b2 * uv[k].0,
+                b0 * uv[i].1 + b1 * uv[j].1 + b2 * uv[k].1,
+                self.tangent(triangle),
+            )
+        };
+        let color = if self.mesh.colors.is_empty() {
+            Color::new(1.0, 1.0, 1.0)
+        } else {
+            let c = &self.mesh.colors;
+            b0 * c[i] + b1 * c[j] + b2 * c[k]
+        };
+
+        let mut rec = HitRecord {
+            t,
+            p: r.at(t),
+            u,
+            v,
+            tangent,
+            color,
+            material: self.material.clone(),
+            ..Default::default()
+        };
+        rec.set_face_normal(r, &normal);
+        Some(rec)
+    }
+
+    // Direction of increasing u over a triangle, zero for degenerate UVs
+    fn tangent(&self, triangle: &[u32; 3]) -> Vec3 {
+        let [a, b, c] = self.mesh.vertices(triangle);
+        let [ta, tb, tc] = triangle.map(|i| self.mesh.uvs[i as usize]);
+        let (du1, dv1) = (tb.0 - ta.0, tb.1 - ta.1);
+        let (du2, dv2) = (tc.0 - ta.0, tc.1 - ta.1);
+        let det = du1 * dv2 - du2 * dv1;
+        let dpdu = (dv2 * (b - a) - dv1 * (c - a)) / det;
+        if det.abs() < 1e-12 || dpdu.near_zero() {
+            Vec3::default()
+        } else {
+            unit_vector(dpdu)
+        }
+    }
+}
+
+impl Hittable for Mesh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.bvh.hit(r, t_min, t_max, |range, t_max| {
+            self.hit_triangles(&self.mesh.indices[range], r, t_min, t_max)
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bvh.bounds()
+    }
+}
+
+/// Möller-Trumbore ray/triangle intersection, returning `t` and the
+/// barycentric coordinates of `b` and `c`
+pub fn intersect_triangle(
+    r: &Ray,
+    a: &Point3,
+    b: &Point3,
+    c: &Point3,
+    t_min: f64,
+    t_max: f64,
+) -> Option<(f64, f64, f64)> {
+    let edge1 = *b - *a;
+    let edge2 = *c - *a;
+    let pvec = cross(&r.direction(), &edge2);
+    let det = dot(&edge1, &pvec);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let tvec = r.origin() - *a;
+    let u = dot(&tvec, &pvec) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let qvec = cross(&tvec, &edge1);
+    let v = dot(&r.direction(), &qvec) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = dot(&edge2, &qvec) * inv_det;
+    (t_min <= t && t <= t_max).then_some((t, u, v))
+}
+
+fn triangle_bounds(positions: &[Point3], triangle: &[u32; 3]) -> Aabb {
+    let [a, b, c] = triangle.map(|i| positions[i as usize]);
+    Aabb::new(a.min(&b).min(&c), a.max(&b).max(&c)).pad(0.0001)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+pub fn load_ply<P: AsRef<Path>>(path: P) -> io::Result<TriangleMesh> {
+    mesh_from_ply(&ply::load(path)?)
+}
+
+/// Builds a mesh from the `vertex` and `face` elements, with optional
+/// normals, UVs and colors. Polygons are split into triangle fans.
+pub fn mesh_from_ply(ply: &ply::PlyFile) -> io::Result<TriangleMesh> {
+    let vertex = ply
+        .element("vertex")
+        .ok_or_else(|| invalid("PLY has no vertex element"))?;
+    let face = ply
+        .element("face")
+        .ok_or_else(|| invalid("PLY has no face element"))?;
+    let n = vertex.count;
+
+    let triple = |names: [&str; 3]| {
+        let [x, y, z] = names.map(|name| vertex.scalar(name));
+        let (x, y, z) = (x?, y?, z?);
+        Some(
+            (0..n)
+                .map(|i| Vec3::new(x[i], y[i], z[i]))
+                .collect::<Vec<_>>(),
+        )
+    };
+    let positions = triple(["x", "y", "z"]).ok_or_else(|| invalid("PLY vertex has no x, y, z"))?;
+    let normals = triple(["nx", "ny", "nz"]).unwrap_or_default();
+
+    let u = vertex.any_scalar(&["u", "s", "texture_u", "texture_s"]);
+    let v = vertex.any_scalar(&["v", "t", "texture_v", "texture_t"]);
+    let uvs = match (u, v) {
+        (Some(u), Some(v)) => (0..n).map(|i| (u[i], v[i])).collect(),
+        _ => Vec::new(),
+    };
+
+    let colors = match triple(["red", "green", "blue"]) {
+        Some(colors) => {
+            let scale = vertex.property("red").unwrap().kind.color_scale();
+            colors.into_iter().map(|c| c / scale).collect()
+        }
+        None => Vec::new(),
+    };
+
+    let polygons = face
+        .list("vertex_indices")
+        .or_else(|| face.list("vertex_index"))
+        .ok_or_else(|| invalid("PLY face has no vertex_indices"))?;
+    let mut indices = Vec::with_capacity(polygons.len());
+    for polygon in polygons {
+        if polygon.iter().any(|&i| i < 0.0 || i as usize >= n) {
+            return Err(invalid("PLY face index out of range"));
+        }
+        for k in 2..polygon.len() {
+            indices.push([polygon[0], polygon[k - 1], polygon[k]].map(|i| i as u32));
+        }
+    }
+
+    Ok(TriangleMesh {
+        positions,
+        normals,
+        uvs,
+        colors,
+        indices,
+    })
+}
+
+/// Loads a binary STL file, see `read_stl`
+pub fn load_stl<P: AsRef<Path>>(path: P, smoothing_angle: f64) -> io::Result<TriangleMesh> {
+    read_stl(BufReader::new(File::open(path)?), smoothing_angle)
+}
+
+/// Reads a binary STL file. Coincident corners are welded and the stored
+/// normals are ignored in favour of ones recomputed from the faces, smoothed
+/// across edges sharper than `smoothing_angle` degrees.
+pub fn read_stl<R: Read>(mut reader: R, smoothing_angle: f64) -> io::Result<TriangleMesh> {
+    let mut header = [0u8; 84];
+    reader.read_exact(&mut header)?;
+    // Binary headers may start with "solid" as well, but their triangle
+    // count is hardly ever made of printable characters
+    let text = |b: &u8| b.is_ascii_graphic() || b.is_ascii_whitespace();
+    if header.starts_with(b"solid") && header.iter().all(text) {
+        return Err(invalid("ASCII STL is not supported"));
+    }
+    let count = u32::from_le_bytes(header[80..84].try_into().unwrap()) as usize;
+
+    let mut mesh = TriangleMesh::default();
+    let mut welded: HashMap<[u32; 3], u32> = HashMap::new();
+    let mut record = [0u8; 50];
+    for _ in 0..count {
+        reader.read_exact(&mut record)?;
+        let float = |k: usize| f32::from_le_bytes(record[4 * k..4 * k + 4].try_into().unwrap());
+        // Skip the stored normal in floats 0..3
+        let triangle = [3, 6, 9].map(|k| {
+            let p = [float(k), float(k + 1), float(k + 2)];
+            *welded.entry(p.map(f32::to_bits)).or_insert_with(|| {
+                mesh.positions
+                    .push(Point3::new(p[0] as f64, p[1] as f64, p[2] as f64));
+                mesh.positions.len() as u32 - 1
+            })
+        });
+        mesh.indices.push(triangle);
+    }
+
+    mesh.smooth_normals(smoothing_angle);
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube_stl() -> Vec<u8> {
+        let corner = |i: usize| [(i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32];
+        let faces = [
+            [0, 2, 3, 1],
+            [4, 5, 7, 6],
+            [0, 1, 5, 4],
+            [2, 6, 7, 3],
+            [0, 4, 6, 2],
+            [1, 3, 7, 5],
+        ];
+        let mut data = vec![0u8; 80];
+        data.extend(12u32.to_le_bytes());
+        for f in faces {
+            for tri in [[f[0], f[1], f[2]], [f[0], f[2], f[3]]] {
+                data.extend([0u8; 12]);
+                for v in tri {
+                    for x in corner(v) {
+                        data.extend(x.to_le_bytes());
+                    }
+                }
+                data.extend([0u8; 2]);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn stl_normals_test() {
+        let faceted = read_stl(cube_stl().as_slice(), 30.0).unwrap();
+        assert_eq!(faceted.indices.len(), 12);
+        // Each corner splits into one vertex per adjacent side
+        assert_eq!(faceted.positions.len(), 24);
+        for normal in &faceted.normals {
+            assert_eq!(normal.length_squared(), 1.0);
+        }
+
+        let smooth = read_stl(cube_stl().as_slice(), 100.0).unwrap();
+        assert_eq!(smooth.positions.len(), 8);
+        let corner = smooth
+            .positions
+            .iter()
+            .position(|p| *p == Point3::new(1.0, 1.0, 1.0))
+            .unwrap();
+        let n = smooth.normals[corner];
+        assert!((n - unit_vector(Vec3::new(1.0, 1.0, 1.0))).length() < 1e-9);
+    }
+
+    #[test]
+    fn ascii_stl_test() {
+        let text = "solid cube\n  facet normal 0 0 1\n    outer loop\n      vertex 0 0 0\n";
+        assert!(read_stl(text.as_bytes(), 0.0).is_err());
+    }
+
+    #[test]
+    fn stl_hit_test() {
+        let mesh = read_stl(cube_stl().as_slice(), 0.0).unwrap();
+        let material = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let cube = Mesh::new(mesh, material);
+
+        let r = Ray::new(Point3::new(0.3, 0.6, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = cube.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 4.0).abs() < 1e-9);
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert!(rec.front_face);
+    }
+
+    #[test]
+    fn ply_test() {
+        let text = "ply\nformat ascii 1.0\nelement vertex 4\n\
+            property float x\nproperty float y\nproperty float z\n\
+            property float nx\nproperty float ny\nproperty float nz\n\
+            property float s\nproperty float t\n\
+            property uchar red\nproperty uchar green\nproperty uchar blue\n\
+            element face 1\nproperty list uchar uint vertex_indices\nend_header\n\
+            0 0 0 0 0 1 0 0 255 0 0\n\
+            1 0 0 0 0 1 1 0 255 0 0\n\
+            1 1 0 0 0 1 1 1 0 0 255\n\
+            0 1 0 0 0 1 0 1 0 0 255\n\
+            4 0 1 2 3\n";
+        let mesh = mesh_from_ply(&ply::read(text.as_bytes()).unwrap()).unwrap();
+        assert_eq!(mesh.indices, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(mesh.colors[0], Color::new(1.0, 0.0, 0.0));
+
+        let material = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let quad = Mesh::new(mesh, material).with_vertex_colors();
+        let r = Ray::new(Point3::new(0.25, 0.75, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = quad.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 1.0);
+        assert!((rec.u - 0.25).abs() < 1e-9 && (rec.v - 0.75).abs() < 1e-9);
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(rec.tangent, Vec3::new(1.0, 0.0, 0.0));
+        assert!((rec.color - Color::new(0.25, 0.0, 0.75)).near_zero());
+        let (attenuation, _) = rec.material.scatter(&r, &rec).unwrap();
+        assert_eq!(attenuation, rec.color);
+    }
+
+    #[test]
+    fn bvh_matches_brute_force_test() {
+        let positions: Vec<Point3> = (0..300).map(|_| Vec3::random(-3.0..3.0)).collect();
+        let indices: Vec<[u32; 3]> = (0..100).map(|i| [3 * i, 3 * i + 1, 3 * i + 2]).collect();
+        let material = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let mesh = Mesh::new(TriangleMesh::new(positions, indices), material);
+
+        for _ in 0..200 {
+            let r = Ray::new(Vec3::random(-5.0..5.0), Vec3::random(-1.0..1.0));
+            let expected = mesh
+                .hit_triangles(&mesh.mesh().indices, &r, 0.001, f64::INFINITY)
+                .map(|rec| rec.t);
+            assert_eq!(
+                mesh.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn refit_test() {
+        let positions: Vec<Point3> = (0..300).map(|_| Vec3::random(-3.0..3.0)).collect();
+        let indices: Vec<[u32; 3]> = (0..100).map(|i| [3 * i, 3 * i + 1, 3 * i + 2]).collect();
+        let material = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let mut mesh = Mesh::new(TriangleMesh::new(positions, indices), material);
+
+        // Squash and shift every vertex, the old tree no longer fits
+        let moved = mesh
+            .mesh()
+            .positions
+            .iter()
+            .map(|&p| Point3::new(p.x() * 0.5 + 4.0, p.y() * 2.0, p.z()))
+            .collect();
+        mesh.set_positions(moved);
+        let bounds = mesh.bounding_box().unwrap();
+        assert!(bounds.minimum.x() > 2.0 && bounds.maximum.y() > 3.0);
+
+        for _ in 0..200 {
+            let r = Ray::new(Vec3::random(-5.0..8.0), Vec3::random(-1.0..1.0));
+            let expected = mesh
+                .hit_triangles(&mesh.mesh().indices, &r, 0.001, f64::INFINITY)
+                .map(|rec| rec.t);
+            assert_eq!(
+                mesh.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t),
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,431 @@
+//! Primary sample space Metropolis light transport.
+//!
+//! A path traced by `PathTracer` is a function of the random numbers it
+//! draws. PSSMLT runs Markov chains over these numbers: each step either
+//! nudges all of them a little or, as a large step, draws them afresh, and
+//! keeps the proposal with a probability following how much brighter it is.
+//! Chains thus linger on the rare paths that carry most of the light, such
+//! as light squeezing through a gap, and every visited path is splatted to
+//! the pixel it lands on. A bootstrap pass of independent paths first
+//! measures the image's brightness, which the chains alone cannot know, and
+//! picks their starting points.
+
+use image::RgbImage;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+use crate::camera::Camera;
+use crate::hittable::*;
+use crate::integrator::PathTracer;
+use crate::render::{radiance_image, RenderSettings, SplatBuffer};
+use crate::sampler::{with_sampler, Sampler};
+use crate::vec3::*;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct PrimarySample {
+    value: f64,
+    /// Iteration that last changed `value`
+    modified: u64,
+    /// State before the current iteration, restored on rejection
+    value_backup: f64,
+    modified_backup: u64,
+}
+
+/// A `Sampler` whose numbers mutate from one iteration to the next.
+///
+/// Numbers are mutated lazily when a path asks for them, catching up on the
+/// small steps they missed, so paths of any length cost only the numbers
+/// they use.
+pub struct MltSampler {
+    rng: StdRng,
+    /// Standard deviation of small steps
+    sigma: f64,
+    large_step_probability: f64,
+    x: Vec<PrimarySample>,
+    index: usize,
+    iteration: u64,
+    last_large_step: u64,
+    large_step: bool,
+}
+
+impl MltSampler {
+    /// Samplers with the same `seed` and settings hand out the same numbers
+    pub fn new(seed: u64, sigma: f64, large_step_probability: f64) -> MltSampler {
+        MltSampler {
+            rng: StdRng::seed_from_u64(seed),
+            sigma,
+            large_step_probability,
+            x: Vec::new(),
+            index: 0,
+            iteration: 0,
+            last_large_step: 0,
+            large_step: true,
+        }
+    }
+
+    /// Proposes a mutation of every number, to be drawn from the start
+    pub fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.gen_range(0.0..1.0) < self.large_step_probability;
+        self.index = 0;
+    }
+
+    pub fn is_large_step(&self) -> bool {
+        self.large_step
+    }
+
+    /// A uniform number outside the primary samples, e.g. to decide on
+    /// acceptance, so a chain is repeatable from its seed alone
+    pub fn uniform(&mut self) -> f64 {
+        self.rng.gen_range(0.0..1.0)
+    }
+
+    /// Keeps the proposal
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    /// Returns to the numbers before the proposal
+    pub fn reject(&mut self) {
+        for sample in &mut self.x {
+            if sample.modified == self.iteration {
+                sample.value = sample.value_backup;
+                sample.modified = sample.modified_backup;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    // Brings a number up to date with the current iteration
+    fn ensure_ready(&mut self, i: usize) {
+        // Numbers a path asks for the first time start out uniform, as
+        // rejection sampling loops would never end on small steps from zero
+        while i >= self.x.len() {
+            let value = self.rng.gen_range(0.0..1.0);
+            self.x.push(PrimarySample {
+                value,
+                modified: self.iteration,
+                ..PrimarySample::default()
+            });
+        }
+        let sample = &mut self.x[i];
+        // Numbers unused since the last accepted large step missed it
+        if sample.modified < self.last_large_step {
+            sample.value = self.rng.gen_range(0.0..1.0);
+            sample.modified = self.last_large_step;
+        }
+        sample.value_backup = sample.value;
+        sample.modified_backup = sample.modified;
+
+        if self.large_step {
+            sample.value = self.rng.gen_range(0.0..1.0);
+        } else {
+            // Small steps add up to one with the summed variance
+            let steps = (self.iteration - sample.modified) as f64;
+            let sigma = self.sigma * steps.sqrt();
+            sample.value += sigma * normal(&mut self.rng);
+            sample.value -= sample.value.floor();
+        }
+        sample.modified = self.iteration;
+    }
+}
+
+impl Sampler for MltSampler {
+    fn next(&mut self) -> f64 {
+        let i = self.index;
+        self.ensure_ready(i);
+        self.index += 1;
+        self.x[i].value
+    }
+}
+
+// Standard normal number by the Box-Muller transform
+fn normal(rng: &mut StdRng) -> f64 {
+    let u1 = 1.0 - rng.gen_range(0.0..1.0);
+    let u2 = rng.gen_range(0.0..1.0);
+    (-2.0 * f64::ln(u1)).sqrt() * (2.0 * PI * u2).cos()
+}
+
+fn luminance(c: &Color) -> f64 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
+/// Metropolis light transport over the paths of `path_tracer`.
+///
+/// `render` takes the samples per pixel as the average number of mutations
+/// per pixel. Chromatic aberration is ignored.
+pub struct Pssmlt {
+    pub path_tracer: PathTracer,
+    /// Independent paths measuring the image's brightness
+    pub bootstrap_samples: usize,
+    /// Markov chains, which run in parallel
+    pub chains: usize,
+    pub sigma: f64,
+    pub large_step_probability: f64,
+}
+
+impl Pssmlt {
+    pub fn new(max_depth: usize) -> Pssmlt {
+        Pssmlt {
+            path_tracer: PathTracer::new(max_depth),
+            bootstrap_samples: 100_000,
+            chains: 1000,
+            sigma: 0.01,
+            large_step_probability: 0.3,
+        }
+    }
+
+    pub fn with_bootstrap_samples(mut self, bootstrap_samples: usize) -> Pssmlt {
+        self.bootstrap_samples = bootstrap_samples;
+        self
+    }
+
+    pub fn with_chains(mut self, chains: usize) -> Pssmlt {
+        self.chains = chains;
+        self
+    }
+
+    pub fn with_sigma(mut self, sigma: f64) -> Pssmlt {
+        self.sigma = sigma;
+        self
+    }
+
+    pub fn with_large_step_probability(mut self, large_step_probability: f64) -> Pssmlt {
+        self.large_step_probability = large_step_probability;
+        self
+    }
+
+    fn sampler(&self, seed: u64) -> Rc<RefCell<MltSampler>> {
+        Rc::new(RefCell::new(MltSampler::new(
+            seed,
+            self.sigma,
+            self.large_step_probability,
+        )))
+    }
+
+    // Traces the path of the sampler's numbers, returning its light and the
+    // film coordinates it starts from. The first two numbers pick a film
+    // position anywhere on the image.
+    fn l(
+        &self,
+        sampler: &Rc<RefCell<MltSampler>>,
+        world: &dyn Hittable,
+        cam: &Camera,
+        settings: &RenderSettings,
+    ) -> (Color, (f64, f64)) {
+        let (width, height) = (settings.image_width as f64, settings.image_height as f64);
+        with_sampler(sampler.clone(), || {
+            let s = random_double(0.0..1.0) * width / (width - 1.0);
+            let t = (1.0 + random_double(0.0..1.0) * height) / (height - 1.0);
+            let r = cam.get_ray(s, t);
+            (self.path_tracer.trace(&r, world).0, (s, t))
+        })
+    }
+
+    /// Radiance of every pixel, row by row from the top
+    pub fn radiance(
+        &self,
+        world: &(dyn Hittable + Send + Sync),
+        cam: &Camera,
+        settings: &RenderSettings,
+    ) -> Vec<Color> {
+        let threads = num_cpus::get();
+        let pixels = settings.image_width as usize * settings.image_height as usize;
+
+        // Bootstrap paths, each replayable from its index as the seed
+        let mut weights = vec![0.0; self.bootstrap_samples];
+        crossbeam::scope(|spawner| {
+            let per_thread = self.bootstrap_samples / threads + 1;
+            for (chunk, chunk_weights) in weights.chunks_mut(per_thread).enumerate() {
+                spawner.spawn(move |_| {
+                    for (k, weight) in chunk_weights.iter_mut().enumerate() {
+                        let seed = (chunk * per_thread + k) as u64;
+                        let sampler = self.sampler(seed);
+                        *weight = luminance(&self.l(&sampler, world, cam, settings).0);
+                    }
+                });
+            }
+        })
+        .expect("failed to spawn threads");
+
+        let total: f64 = weights.iter().sum();
+        if total == 0.0 {
+            return vec![Color::default(); pixels];
+        }
+        let brightness = total / self.bootstrap_samples as f64;
+        let cdf: Vec<f64> = weights
+            .iter()
+            .scan(0.0, |sum, weight| {
+                *sum += weight / total;
+                Some(*sum)
+            })
+            .collect();
+
+        // Chains start from bootstrap paths picked in proportion to their
+        // weight, drawn here so only this thread's sampler decides them
+        let starts: Vec<u64> = (0..self.chains)
+            .map(|_| {
+                let u = random_double(0.0..1.0);
+                cdf.partition_point(|&c| c < u).min(cdf.len() - 1) as u64
+            })
+            .collect();
+
+        let mutations = settings.samples_per_pixel * pixels;
+        let splats = SplatBuffer::new(settings);
+        crossbeam::scope(|spawner| {
+            for thread in 0..threads {
+                let (starts, splats) = (&starts, &splats);
+                spawner.spawn(move |_| {
+                    for chain in (thread..self.chains).step_by(threads) {
+                        let chain_mutations =
+                            mutations / self.chains + usize::from(chain < mutations % self.chains);
+                        self.run_chain(
+                            starts[chain],
+                            chain_mutations,
+                            world,
+                            cam,
+                            settings,
+                            splats,
+                        );
+                    }
+                });
+            }
+        })
+        .expect("failed to spawn threads");
+
+        let scale = brightness / settings.samples_per_pixel as f64;
+        (0..settings.image_height)
+            .flat_map(|j| (0..settings.image_width).map(move |i| (i, j)))
+            .map(|(i, j)| splats.get(i, j) * scale)
+            .collect()
+    }
+
+    // Mutates the path of bootstrap sample `seed`, splatting each state with
+    // its expected share of the chain's time there
+    fn run_chain(
+        &self,
+        seed: u64,
+        mutations: usize,
+        world: &dyn Hittable,
+        cam: &Camera,
+        settings: &RenderSettings,
+        splats: &SplatBuffer,
+    ) {
+        let sampler = self.sampler(seed);
+        let (mut current, mut current_film) = self.l(&sampler, world, cam, settings);
+        for _ in 0..mutations {
+            sampler.borrow_mut().start_iteration();
+            let (proposed, proposed_film) = self.l(&sampler, world, cam, settings);
+            let (y_current, y_proposed) = (luminance(&current), luminance(&proposed));
+            let accept = if y_current > 0.0 {
+                (y_proposed / y_current).min(1.0)
+            } else {
+                1.0
+            };
+
+            if accept > 0.0 && y_proposed > 0.0 {
+                let (s, t) = proposed_film;
+                splats.add(s, t, proposed * (accept / y_proposed));
+            }
+            if accept < 1.0 {
+                let (s, t) = current_film;
+                splats.add(s, t, current * ((1.0 - accept) / y_current));
+            }
+
+            let u = sampler.borrow_mut().uniform();
+            if u < accept {
+                current = proposed;
+                current_film = proposed_film;
+                sampler.borrow_mut().accept();
+            } else {
+                sampler.borrow_mut().reject();
+            }
+        }
+    }
+
+    pub fn render(
+        &self,
+        world: &(dyn Hittable + Send + Sync),
+        cam: &Camera,
+        settings: &RenderSettings,
+    ) -> RgbImage {
+        radiance_image(&self.radiance(world, cam, settings), settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrator::Integrator;
+    use crate::test_scenes::{box_camera, box_scene, pixel_means, seeded};
+
+    fn draw(sampler: &mut MltSampler) -> [f64; 3] {
+        [0; 3].map(|_| sampler.next())
+    }
+
+    fn values(sampler: &MltSampler) -> Vec<f64> {
+        sampler.x.iter().map(|x| x.value).collect()
+    }
+
+    #[test]
+    fn mutation_test() {
+        let mut sampler = MltSampler::new(7, 0.01, 0.0);
+        let start = draw(&mut sampler);
+        assert_eq!(start, draw(&mut MltSampler::new(7, 0.01, 0.0)));
+
+        sampler.start_iteration();
+        assert!(!sampler.is_large_step());
+        let step = draw(&mut sampler);
+        for (a, b) in start.iter().zip(step) {
+            let d = (a - b).abs();
+            assert!(d > 0.0 && d.min(1.0 - d) < 0.1);
+        }
+        sampler.reject();
+        assert_eq!(values(&sampler), start);
+
+        sampler.start_iteration();
+        let step = draw(&mut sampler);
+        sampler.accept();
+        sampler.start_iteration();
+        sampler.reject();
+        assert_eq!(values(&sampler), step);
+    }
+
+    #[test]
+    fn matches_path_tracer_test() {
+        let (world, _) = box_scene();
+        let settings = RenderSettings {
+            image_width: 6,
+            image_height: 4,
+            // Chains spend most of their time on the light, so the dim
+            // pixels need many mutations to settle
+            samples_per_pixel: 5000,
+        };
+        let camera = box_camera(&settings);
+        let mlt = Pssmlt::new(50)
+            .with_bootstrap_samples(10_000)
+            .with_chains(64);
+        let radiance = seeded(1, || mlt.radiance(&world, &camera, &settings));
+
+        let path_tracer = PathTracer::new(50);
+        let sampled = RenderSettings {
+            samples_per_pixel: 2000,
+            ..settings
+        };
+        let expected = seeded(2, || {
+            pixel_means(&camera, &sampled, |r| path_tracer.li(r, &world))
+        });
+        let error: f64 = radiance
+            .iter()
+            .zip(&expected)
+            .map(|(l, e)| (l.x() - e.x()).abs())
+            .sum();
+        let total: f64 = expected.iter().map(|e| e.x()).sum();
+        assert!(error < 0.15 * total, "error {} of {}", error, total);
+    }
+}
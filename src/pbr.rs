@@ -0,0 +1,202 @@
+//! Metallic-roughness material in the style of glTF 2.0.
+//!
+//! Scattering picks one lobe at random: metals and the Fresnel reflection of
+//! dielectrics reflect into a cone widened by the roughness like `Metal`,
+//! everything else scatters diffusely like `Lambertian`.
+
+use std::sync::Arc;
+
+use crate::hittable::*;
+use crate::material::*;
+use crate::ray::*;
+use crate::texture::ImageTexture;
+use crate::vec3::*;
+
+pub struct PbrMaterial {
+    pub base_color: Color,
+    pub base_color_texture: Option<Arc<ImageTexture>>,
+    pub metallic: f64,
+    pub roughness: f64,
+    /// Roughness in the green and metalness in the blue channel
+    pub metallic_roughness_texture: Option<Arc<ImageTexture>>,
+    /// Tangent space normals, applied along the hit's tangent
+    pub normal_texture: Option<Arc<ImageTexture>>,
+    pub normal_scale: f64,
+    pub emissive: Color,
+    pub emissive_texture: Option<Arc<ImageTexture>>,
+}
+
+impl PbrMaterial {
+    pub fn new(base_color: Color, metallic: f64, roughness: f64) -> PbrMaterial {
+        PbrMaterial {
+            base_color,
+            base_color_texture: None,
+            metallic: metallic.clamp(0.0, 1.0),
+            roughness: roughness.clamp(0.0, 1.0),
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            emissive: Color::default(),
+            emissive_texture: None,
+        }
+    }
+
+    /// Multiplies the base color, expects linear values
+    pub fn with_base_color_texture(mut self, texture: Arc<ImageTexture>) -> PbrMaterial {
+        self.base_color_texture = Some(texture);
+        self
+    }
+
+    pub fn with_metallic_roughness_texture(mut self, texture: Arc<ImageTexture>) -> PbrMaterial {
+        self.metallic_roughness_texture = Some(texture);
+        self
+    }
+
+    pub fn with_normal_texture(mut self, texture: Arc<ImageTexture>, scale: f64) -> PbrMaterial {
+        self.normal_texture = Some(texture);
+        self.normal_scale = scale;
+        self
+    }
+
+    /// `texture`, if any, multiplies `emissive` and expects linear values
+    pub fn with_emissive(
+        mut self,
+        emissive: Color,
+        texture: Option<Arc<ImageTexture>>,
+    ) -> PbrMaterial {
+        self.emissive = emissive;
+        self.emissive_texture = texture;
+        self
+    }
+
+    pub fn base_color_at(&self, u: f64, v: f64) -> Color {
+        match &self.base_color_texture {
+            Some(texture) => self.base_color * texture.color(u, v),
+            None => self.base_color,
+        }
+    }
+
+    /// Metalness and roughness at a texture coordinate
+    pub fn metallic_roughness_at(&self, u: f64, v: f64) -> (f64, f64) {
+        match &self.metallic_roughness_texture {
+            Some(texture) => {
+                let [_, g, b, _] = texture.sample(u, v);
+                (self.metallic * b, self.roughness * g)
+            }
+            None => (self.metallic, self.roughness),
+        }
+    }
+
+    fn shading_normal(&self, rec: &HitRecord) -> Vec3 {
+        let texture = match &self.normal_texture {
+            Some(texture) if !rec.tangent.near_zero() => texture,
+            _ => return rec.normal,
+        };
+        let n = rec.normal;
+        let t = rec.tangent - dot(&rec.tangent, &n) * n;
+        if t.near_zero() {
+            return n;
+        }
+        let t = unit_vector(t);
+        let b = cross(&n, &t);
+
+        let [x, y, z, _] = texture.sample(rec.u, rec.v);
+        let x = (2.0 * x - 1.0) * self.normal_scale;
+        let y = (2.0 * y - 1.0) * self.normal_scale;
+        let z = 2.0 * z - 1.0;
+        let mapped = x * t + y * b + z * n;
+        if mapped.near_zero() {
+            n
+        } else {
+            unit_vector(mapped)
+        }
+    }
+}
+
+impl Material for PbrMaterial {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        let base_color = self.base_color_at(rec.u, rec.v);
+        let (metallic, roughness) = self.metallic_roughness_at(rec.u, rec.v);
+        let normal = self.shading_normal(rec);
+
+        let unit_direction = unit_vector(r_in.direction());
+        let cos_theta = (-dot(&unit_direction, &normal)).clamp(0.0, 1.0);
+
+        // Metals tint their reflection, dielectrics reflect uncolored light
+        // with the Fresnel reflectance of an index of refraction of 1.5
+        let reflection = if random_double(0.0..1.0) < metallic {
+            Some(base_color)
+        } else if random_double(0.0..1.0) < Dielectric::reflectance(cos_theta, 1.5) {
+            Some(Color::new(1.0, 1.0, 1.0))
+        } else {
+            None
+        };
+
+        if let Some(attenuation) = reflection {
+            let fuzz = roughness * roughness;
+            let reflected = Vec3::reflect(&unit_direction, &normal);
+            let scattered = Ray::new(rec.p, reflected + fuzz * Vec3::random_in_unit_sphere());
+            if dot(&scattered.direction(), &rec.normal) <= 0.0 {
+                return None;
+            }
+            return Some((attenuation, scattered));
+        }
+
+        let mut direction = normal + Vec3::random_unit_vector();
+        if direction.near_zero() {
+            direction = normal;
+        }
+        Some((base_color, Ray::new(rec.p, direction)))
+    }
+
+    fn emitted(&self, u: f64, v: f64, _p: &Point3) -> Color {
+        match &self.emissive_texture {
+            Some(texture) => self.emissive * texture.color(u, v),
+            None => self.emissive,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn textures_test() {
+        let texture = Arc::new(ImageTexture::new(1, 1, vec![[0.5, 0.25, 1.0, 1.0]]));
+        let material = PbrMaterial::new(Color::new(1.0, 0.5, 1.0), 1.0, 0.8)
+            .with_base_color_texture(texture.clone())
+            .with_metallic_roughness_texture(texture.clone())
+            .with_emissive(Color::new(2.0, 2.0, 2.0), Some(texture));
+
+        assert_eq!(
+            material.base_color_at(0.3, 0.3),
+            Color::new(0.5, 0.125, 1.0)
+        );
+        assert_eq!(material.metallic_roughness_at(0.3, 0.3), (1.0, 0.2));
+        assert_eq!(
+            material.emitted(0.3, 0.3, &Point3::default()),
+            Color::new(1.0, 0.5, 2.0)
+        );
+    }
+
+    #[test]
+    fn normal_map_test() {
+        // A texel tilting the normal fully towards +tangent
+        let texture = Arc::new(ImageTexture::new(1, 1, vec![[1.0, 0.5, 0.5, 1.0]]));
+        let material =
+            PbrMaterial::new(Color::new(1.0, 1.0, 1.0), 0.0, 1.0).with_normal_texture(texture, 1.0);
+        let rec = HitRecord {
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            tangent: Vec3::new(1.0, 0.0, 0.0),
+            ..Default::default()
+        };
+        assert_eq!(material.shading_normal(&rec), Vec3::new(1.0, 0.0, 0.0));
+
+        let flat = HitRecord {
+            tangent: Vec3::default(),
+            ..rec
+        };
+        assert_eq!(material.shading_normal(&flat), Vec3::new(0.0, 0.0, 1.0));
+    }
+}
@@ -0,0 +1,923 @@
+//! Importer for a subset of the pbrt-v4 scene format.
+//!
+//! Supported directives are `LookAt`, `Transform`, `ConcatTransform`,
+//! `Translate`, `Scale`, `Rotate`, `Identity`, `Camera "perspective"`,
+//! `Film`, `Sampler`, `WorldBegin`, `AttributeBegin`/`AttributeEnd`,
+//! `ReverseOrientation`, `Include`, `Material`, `MakeNamedMaterial`,
+//! `NamedMaterial`, `AreaLightSource "diffuse"`, `LightSource "infinite"`
+//! with a constant radiance and `Shape` for spheres, triangle meshes and PLY
+//! meshes. Anything else is skipped with a message in
+//! `PbrtScene::warnings`, while malformed input is an error.
+//!
+//! pbrt uses a left-handed coordinate system, so the scene is mirrored along
+//! x to render the same image with this renderer's camera. The infinite
+//! light becomes `PbrtScene::background`, to be handed to the integrator's
+//! `with_background`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::blackbody::blackbody_color;
+use crate::camera::Camera;
+use crate::hittable::*;
+use crate::instance::Instance;
+use crate::integrator::Background;
+use crate::material::*;
+use crate::mesh::{self, Mesh, TriangleMesh};
+use crate::sphere::Sphere;
+use crate::vec3::*;
+
+pub struct PbrtScene {
+    pub world: HittableList,
+    pub camera: Camera,
+    pub image_width: usize,
+    pub image_height: usize,
+    pub samples_per_pixel: usize,
+    /// Output file name requested by the film
+    pub filename: Option<String>,
+    /// Radiance of the infinite light, black without one
+    pub background: Background,
+    pub warnings: Vec<String>,
+}
+
+pub fn load_pbrt<P: AsRef<Path>>(path: P) -> io::Result<PbrtScene> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)?;
+    let base = path.parent().unwrap_or(Path::new("."));
+    read_pbrt(&text, base)
+}
+
+/// Parses scene text, resolving `Include` and PLY files against `base`
+pub fn read_pbrt(text: &str, base: &Path) -> io::Result<PbrtScene> {
+    let mut parser = Parser::new(base);
+    parser.parse(text, "<scene>")?;
+    Ok(parser.finish())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Number(f64),
+    Open,
+    Close,
+}
+
+fn tokenize(text: &str, file: &str) -> io::Result<Vec<(Token, usize)>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            '\n' => {
+                line += 1;
+                chars.next();
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '#' => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            '[' | ']' => {
+                chars.next();
+                tokens.push((if c == '[' { Token::Open } else { Token::Close }, line));
+            }
+            '"' => {
+                chars.next();
+                let start = line;
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\n') | None => {
+                            return Err(syntax(file, start, "unterminated string"));
+                        }
+                        Some(c) => s.push(c),
+                    }
+                }
+                tokens.push((Token::Str(s), start));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '"' | '[' | ']' | '#') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                let token = match word.parse::<f64>() {
+                    Ok(x) if !word.starts_with(char::is_alphabetic) => Token::Number(x),
+                    _ => Token::Word(word),
+                };
+                tokens.push((token, line));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn syntax(file: &str, line: usize, message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}:{}: {}", file, line, message),
+    )
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Number(f64),
+    Str(String),
+}
+
+#[derive(Debug, Clone)]
+struct Param {
+    kind: String,
+    name: String,
+    values: Vec<Value>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Params(Vec<Param>);
+
+impl Params {
+    fn find(&self, name: &str) -> Option<&Param> {
+        self.0.iter().find(|p| p.name == name)
+    }
+
+    fn numbers(&self, name: &str) -> Option<Vec<f64>> {
+        let values = &self.find(name)?.values;
+        values
+            .iter()
+            .map(|v| match v {
+                Value::Number(x) => Some(*x),
+                Value::Str(_) => None,
+            })
+            .collect()
+    }
+
+    fn float(&self, name: &str, default: f64) -> f64 {
+        self.numbers(name)
+            .and_then(|v| v.first().copied())
+            .unwrap_or(default)
+    }
+
+    fn string(&self, name: &str) -> Option<&str> {
+        match self.find(name)?.values.first()? {
+            Value::Str(s) => Some(s),
+            Value::Number(_) => None,
+        }
+    }
+
+    fn bool(&self, name: &str, default: bool) -> bool {
+        match self.string(name) {
+            Some("true") => true,
+            Some("false") => false,
+            _ => default,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Attributes {
+    ctm: Mat4,
+    material: Arc<dyn Material + Send + Sync>,
+    /// Radiance and whether it leaves both sides of the surface
+    area_light: Option<(Color, bool)>,
+    reverse_orientation: bool,
+}
+
+struct CameraSettings {
+    camera_to_world: Mat4,
+    fov: f64,
+    lens_radius: f64,
+    focal_distance: f64,
+}
+
+/// Deepest nesting of `Include` and `Import` files
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+struct Parser {
+    base: PathBuf,
+    /// Files being included, innermost last
+    includes: Vec<PathBuf>,
+    attributes: Attributes,
+    stack: Vec<Attributes>,
+    named_materials: HashMap<String, Arc<dyn Material + Send + Sync>>,
+    camera: Option<CameraSettings>,
+    image_width: usize,
+    image_height: usize,
+    samples_per_pixel: usize,
+    filename: Option<String>,
+    background: Color,
+    world: HittableList,
+    warnings: Vec<String>,
+}
+
+impl Parser {
+    fn new(base: &Path) -> Parser {
+        Parser {
+            base: base.to_path_buf(),
+            includes: Vec::new(),
+            attributes: Attributes {
+                ctm: Mat4::identity(),
+                material: Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+                area_light: None,
+                reverse_orientation: false,
+            },
+            stack: Vec::new(),
+            named_materials: HashMap::new(),
+            camera: None,
+            image_width: 1280,
+            image_height: 720,
+            samples_per_pixel: 16,
+            filename: None,
+            background: Color::default(),
+            world: HittableList::default(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn parse(&mut self, text: &str, file: &str) -> io::Result<()> {
+        let tokens = tokenize(text, file)?;
+        let mut pos = 0;
+
+        while pos < tokens.len() {
+            let (token, line) = &tokens[pos];
+            let line = *line;
+            pos += 1;
+            let directive = match token {
+                Token::Word(word) => word.as_str(),
+                _ => return Err(syntax(file, line, "expected a directive")),
+            };
+            let err = |message: &str| syntax(file, line, message);
+            let warn = |message: String| format!("{}:{}: {}", file, line, message);
+
+            match directive {
+                "LookAt" => {
+                    let v = numbers(&tokens, &mut pos, 9)
+                        .ok_or_else(|| err("LookAt needs 9 numbers"))?;
+                    let eye = Point3::new(v[0], v[1], v[2]);
+                    let look = Point3::new(v[3], v[4], v[5]);
+                    let up = Vec3::new(v[6], v[7], v[8]);
+                    let look_at = look_at(eye, look, up).ok_or_else(|| err("degenerate LookAt"))?;
+                    self.attributes.ctm = self.attributes.ctm * look_at;
+                }
+                "Transform" | "ConcatTransform" => {
+                    let v = bracketed_numbers(&tokens, &mut pos)
+                        .filter(|v| v.len() == 16)
+                        .ok_or_else(|| err("expected a matrix of 16 numbers"))?;
+                    // Matrices are given column by column
+                    let mut m = [[0.0; 4]; 4];
+                    for (i, x) in v.iter().enumerate() {
+                        m[i % 4][i / 4] = *x;
+                    }
+                    self.attributes.ctm = if directive == "Transform" {
+                        Mat4::new(m)
+                    } else {
+                        self.attributes.ctm * Mat4::new(m)
+                    };
+                }
+                "Translate" | "Scale" => {
+                    let v =
+                        numbers(&tokens, &mut pos, 3).ok_or_else(|| err("expected 3 numbers"))?;
+                    let v = Vec3::new(v[0], v[1], v[2]);
+                    let m = if directive == "Translate" {
+                        Mat4::translation(v)
+                    } else {
+                        Mat4::scaling(v)
+                    };
+                    self.attributes.ctm = self.attributes.ctm * m;
+                }
+                "Rotate" => {
+                    let v = numbers(&tokens, &mut pos, 4)
+                        .ok_or_else(|| err("Rotate needs 4 numbers"))?;
+                    let rotation = Mat4::rotation(Vec3::new(v[1], v[2], v[3]), v[0]);
+                    self.attributes.ctm = self.attributes.ctm * rotation;
+                }
+                "Identity" => self.attributes.ctm = Mat4::identity(),
+                "ReverseOrientation" => {
+                    self.attributes.reverse_orientation = !self.attributes.reverse_orientation
+                }
+                "WorldBegin" => self.attributes.ctm = Mat4::identity(),
+                "AttributeBegin" | "TransformBegin" => self.stack.push(self.attributes.clone()),
+                "AttributeEnd" | "TransformEnd" => {
+                    let saved = self
+                        .stack
+                        .pop()
+                        .ok_or_else(|| err("unmatched AttributeEnd"))?;
+                    if directive == "AttributeEnd" {
+                        self.attributes = saved;
+                    } else {
+                        self.attributes.ctm = saved.ctm;
+                    }
+                }
+                "Include" | "Import" => {
+                    let name =
+                        string(&tokens, &mut pos).ok_or_else(|| err("expected a file name"))?;
+                    let path = self.base.join(&name);
+                    let text = fs::read_to_string(&path)
+                        .map_err(|e| err(&format!("cannot read {}: {}", path.display(), e)))?;
+                    let canonical = fs::canonicalize(&path).unwrap_or(path);
+                    if self.includes.contains(&canonical) {
+                        return Err(err(&format!("'{}' includes itself", name)));
+                    }
+                    if self.includes.len() >= MAX_INCLUDE_DEPTH {
+                        return Err(err("includes are nested too deeply"));
+                    }
+                    self.includes.push(canonical);
+                    let result = self.parse(&text, &name);
+                    self.includes.pop();
+                    result?;
+                }
+                "Camera" => {
+                    let (kind, params) = typed(&tokens, &mut pos, file)?;
+                    if kind != "perspective" {
+                        self.warnings.push(warn(format!(
+                            "unsupported camera '{}', using perspective",
+                            kind
+                        )));
+                    }
+                    let camera_to_world = self
+                        .attributes
+                        .ctm
+                        .inverse()
+                        .ok_or_else(|| err("camera transform is not invertible"))?;
+                    self.camera = Some(CameraSettings {
+                        camera_to_world,
+                        fov: params.float("fov", 90.0),
+                        lens_radius: params.float("lensradius", 0.0),
+                        focal_distance: params.float("focaldistance", 1e6),
+                    });
+                }
+                "Film" => {
+                    let (_, params) = typed(&tokens, &mut pos, file)?;
+                    self.image_width = params.float("xresolution", 1280.0) as usize;
+                    self.image_height = params.float("yresolution", 720.0) as usize;
+                    self.filename = params.string("filename").map(String::from);
+                }
+                "Sampler" => {
+                    let (_, params) = typed(&tokens, &mut pos, file)?;
+                    self.samples_per_pixel = params.float("pixelsamples", 16.0) as usize;
+                }
+                "Material" => {
+                    let (kind, params) = typed(&tokens, &mut pos, file)?;
+                    let (material, warning) = make_material(&kind, &params);
+                    self.warnings.extend(warning.map(warn));
+                    self.attributes.material = material;
+                }
+                "MakeNamedMaterial" => {
+                    let name = string(&tokens, &mut pos).ok_or_else(|| err("expected a name"))?;
+                    let params = parameters(&tokens, &mut pos, file)?;
+                    let kind = params.string("type").unwrap_or("diffuse").to_string();
+                    let (material, warning) = make_material(&kind, &params);
+                    self.warnings.extend(warning.map(warn));
+                    self.named_materials.insert(name, material);
+                }
+                "NamedMaterial" => {
+                    let name = string(&tokens, &mut pos).ok_or_else(|| err("expected a name"))?;
+                    self.attributes.material = self
+                        .named_materials
+                        .get(&name)
+                        .cloned()
+                        .ok_or_else(|| err(&format!("unknown material '{}'", name)))?;
+                }
+                "AreaLightSource" => {
+                    let (kind, params) = typed(&tokens, &mut pos, file)?;
+                    if kind != "diffuse" {
+                        self.warnings
+                            .push(warn(format!("unsupported area light '{}'", kind)));
+                        continue;
+                    }
+                    let l = self.light_color(&params, &warn);
+                    self.attributes.area_light = Some((
+                        params.float("scale", 1.0) * l,
+                        params.bool("twosided", false),
+                    ));
+                }
+                "LightSource" => {
+                    let (kind, params) = typed(&tokens, &mut pos, file)?;
+                    if kind != "infinite" {
+                        self.warnings
+                            .push(warn(format!("unsupported light source '{}'", kind)));
+                    } else if params.find("filename").is_some() {
+                        self.warnings
+                            .push(warn("image based infinite lights are not supported".into()));
+                    } else {
+                        let l = self.light_color(&params, &warn);
+                        self.background += params.float("scale", 1.0) * l;
+                    }
+                }
+                "Shape" => {
+                    let (kind, params) = typed(&tokens, &mut pos, file)?;
+                    self.shape(&kind, &params).map_err(|e| err(&e))?;
+                    if !matches!(kind.as_str(), "sphere" | "trianglemesh" | "plymesh") {
+                        self.warnings
+                            .push(warn(format!("unsupported shape '{}'", kind)));
+                    }
+                    if kind == "sphere"
+                        && self.attributes.reverse_orientation
+                        && matches!(self.attributes.area_light, Some((_, false)))
+                    {
+                        self.warnings.push(warn(
+                            "one-sided light on an inward facing sphere emits from both sides"
+                                .into(),
+                        ));
+                    }
+                }
+                _ => {
+                    // Skip the arguments of directives this importer ignores
+                    while pos < tokens.len() && !matches!(tokens[pos].0, Token::Word(_)) {
+                        pos += 1;
+                    }
+                    self.warnings.push(warn(format!(
+                        "unsupported directive '{}' ignored",
+                        directive
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The `L` parameter of a light, white if missing or unsupported
+    fn light_color(&mut self, params: &Params, warn: &dyn Fn(String) -> String) -> Color {
+        let l = color(params, "L");
+        if l.is_none() && params.find("L").is_some() {
+            self.warnings.push(warn(
+                "only rgb and blackbody light colors are supported".into(),
+            ));
+        }
+        l.unwrap_or(Color::new(1.0, 1.0, 1.0))
+    }
+
+    fn shape(&mut self, kind: &str, params: &Params) -> Result<(), String> {
+        let object: Arc<dyn Hittable + Send + Sync> = match kind {
+            "sphere" => Arc::new(Sphere::new(
+                Point3::default(),
+                params.float("radius", 1.0),
+                self.shape_material(true),
+            )),
+            "trianglemesh" => {
+                let p = params
+                    .numbers("P")
+                    .ok_or("trianglemesh needs \"point3 P\"")?;
+                let mut data = TriangleMesh::new(
+                    p.chunks_exact(3)
+                        .map(|c| Point3::new(c[0], c[1], c[2]))
+                        .collect(),
+                    Vec::new(),
+                );
+                let indices = match params.numbers("indices") {
+                    Some(indices) => indices,
+                    None if data.positions.len() == 3 => vec![0.0, 1.0, 2.0],
+                    None => return Err("trianglemesh needs \"integer indices\"".into()),
+                };
+                if indices.len() % 3 != 0
+                    || indices
+                        .iter()
+                        .any(|&i| i < 0.0 || i as usize >= data.positions.len())
+                {
+                    return Err("bad trianglemesh indices".into());
+                }
+                data.indices = indices
+                    .chunks_exact(3)
+                    .map(|c| [c[0] as u32, c[1] as u32, c[2] as u32])
+                    .collect();
+                if let Some(n) = params.numbers("N") {
+                    data.normals = n
+                        .chunks_exact(3)
+                        .map(|c| Vec3::new(c[0], c[1], c[2]))
+                        .collect();
+                }
+                if let Some(uv) = params.numbers("uv") {
+                    data.uvs = uv.chunks_exact(2).map(|c| (c[0], c[1])).collect();
+                }
+                let n = data.positions.len();
+                if data.normals.len() != n && !data.normals.is_empty()
+                    || data.uvs.len() != n && !data.uvs.is_empty()
+                {
+                    return Err("trianglemesh attributes do not match \"P\"".into());
+                }
+                self.mesh(data)
+            }
+            "plymesh" => {
+                let name = params
+                    .string("filename")
+                    .ok_or("plymesh needs \"string filename\"")?;
+                let path = self.base.join(name);
+                let data = mesh::load_ply(&path)
+                    .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+                self.mesh(data)
+            }
+            _ => return Ok(()),
+        };
+
+        // Mirror into the right-handed world
+        let world = Mat4::scaling(Vec3::new(-1.0, 1.0, 1.0)) * self.attributes.ctm;
+        let transform = Transform::new(world).ok_or("shape transform is not invertible")?;
+        self.world
+            .add(Arc::new(Instance::with_transform(object, transform)));
+        Ok(())
+    }
+
+    fn mesh(&self, mut data: TriangleMesh) -> Arc<dyn Hittable + Send + Sync> {
+        if self.attributes.reverse_orientation {
+            for triangle in &mut data.indices {
+                triangle.swap(1, 2);
+            }
+        }
+        Arc::new(Mesh::new(data, self.shape_material(false)))
+    }
+
+    fn shape_material(&self, sphere: bool) -> Arc<dyn Material + Send + Sync> {
+        match self.attributes.area_light {
+            // Spheres always face outwards, so reversed ones light both ways
+            Some((l, false)) if !self.attributes.reverse_orientation || !sphere => {
+                Arc::new(DiffuseLight::one_sided(l))
+            }
+            Some((l, _)) => Arc::new(DiffuseLight::new(l)),
+            None => self.attributes.material.clone(),
+        }
+    }
+
+    fn finish(self) -> PbrtScene {
+        let settings = self.camera.unwrap_or(CameraSettings {
+            camera_to_world: Mat4::identity(),
+            fov: 90.0,
+            lens_radius: 0.0,
+            focal_distance: 1e6,
+        });
+        let aspect_ratio = self.image_width as f64 / self.image_height.max(1) as f64;
+        // The field of view spans the shorter image axis
+        let vfov = if aspect_ratio >= 1.0 {
+            settings.fov
+        } else {
+            2.0 * ((settings.fov.to_radians() / 2.0).tan() / aspect_ratio)
+                .atan()
+                .to_degrees()
+        };
+
+        let to_world = Mat4::scaling(Vec3::new(-1.0, 1.0, 1.0)) * settings.camera_to_world;
+        let eye = to_world.transform_point(&Point3::default());
+        let camera = Camera::new(
+            eye,
+            to_world.transform_point(&Point3::new(0.0, 0.0, 1.0)),
+            to_world.transform_vector(&Vec3::new(0.0, 1.0, 0.0)),
+            vfov,
+            aspect_ratio,
+            2.0 * settings.lens_radius,
+            // Only matters for depth of field
+            if settings.lens_radius > 0.0 {
+                settings.focal_distance
+            } else {
+                1.0
+            },
+        );
+
+        let mut warnings = self.warnings;
+        if !self.stack.is_empty() {
+            warnings.push("unterminated AttributeBegin".into());
+        }
+        PbrtScene {
+            world: self.world,
+            camera,
+            image_width: self.image_width,
+            image_height: self.image_height,
+            samples_per_pixel: self.samples_per_pixel,
+            filename: self.filename,
+            background: Background::Constant(self.background),
+            warnings,
+        }
+    }
+}
+
+/// pbrt's world-to-camera transform for LookAt, with the camera looking
+/// down +z
+fn look_at(eye: Point3, look: Point3, up: Vec3) -> Option<Mat4> {
+    let dir = look - eye;
+    let right = cross(&unit_vector(up), &dir);
+    if dir.near_zero() || right.near_zero() {
+        return None;
+    }
+    let dir = unit_vector(dir);
+    let right = unit_vector(right);
+    let new_up = cross(&dir, &right);
+    let camera_to_world = Mat4::new([
+        [right.x(), new_up.x(), dir.x(), eye.x()],
+        [right.y(), new_up.y(), dir.y(), eye.y()],
+        [right.z(), new_up.z(), dir.z(), eye.z()],
+        [0.0, 0.0, 0.0, 1.0],
+    ]);
+    camera_to_world.inverse()
+}
+
+fn numbers(tokens: &[(Token, usize)], pos: &mut usize, n: usize) -> Option<Vec<f64>> {
+    let values: Vec<f64> = tokens[*pos..]
+        .iter()
+        .take(n)
+        .map_while(|(t, _)| match t {
+            Token::Number(x) => Some(*x),
+            _ => None,
+        })
+        .collect();
+    (values.len() == n).then(|| {
+        *pos += n;
+        values
+    })
+}
+
+fn bracketed_numbers(tokens: &[(Token, usize)], pos: &mut usize) -> Option<Vec<f64>> {
+    if tokens.get(*pos)?.0 != Token::Open {
+        return numbers(tokens, pos, 16);
+    }
+    let mut values = Vec::new();
+    let mut i = *pos + 1;
+    loop {
+        match &tokens.get(i)?.0 {
+            Token::Number(x) => values.push(*x),
+            Token::Close => break,
+            _ => return None,
+        }
+        i += 1;
+    }
+    *pos = i + 1;
+    Some(values)
+}
+
+fn string(tokens: &[(Token, usize)], pos: &mut usize) -> Option<String> {
+    match &tokens.get(*pos)?.0 {
+        Token::Str(s) => {
+            *pos += 1;
+            Some(s.clone())
+        }
+        _ => None,
+    }
+}
+
+/// A quoted type name followed by a parameter list
+fn typed(tokens: &[(Token, usize)], pos: &mut usize, file: &str) -> io::Result<(String, Params)> {
+    let line = tokens.get(*pos - 1).map_or(0, |t| t.1);
+    let kind = string(tokens, pos).ok_or_else(|| syntax(file, line, "expected a type name"))?;
+    Ok((kind, parameters(tokens, pos, file)?))
+}
+
+/// `"type name" value` or `"type name" [values]` pairs
+fn parameters(tokens: &[(Token, usize)], pos: &mut usize, file: &str) -> io::Result<Params> {
+    let mut params = Vec::new();
+    while let Some((Token::Str(declaration), line)) = tokens.get(*pos) {
+        let words: Vec<&str> = declaration.split_whitespace().collect();
+        let (kind, name) = match words.as_slice() {
+            [kind, name] => (kind.to_string(), name.to_string()),
+            _ => {
+                return Err(syntax(
+                    file,
+                    *line,
+                    &format!("bad parameter '{}'", declaration),
+                ))
+            }
+        };
+        *pos += 1;
+
+        let value = |token: &Token| match token {
+            Token::Number(x) => Some(Value::Number(*x)),
+            Token::Str(s) => Some(Value::Str(s.clone())),
+            Token::Word(w) if w == "true" || w == "false" => Some(Value::Str(w.clone())),
+            _ => None,
+        };
+        let mut values = Vec::new();
+        match tokens.get(*pos) {
+            Some((Token::Open, _)) => {
+                *pos += 1;
+                loop {
+                    match tokens.get(*pos) {
+                        Some((Token::Close, _)) => break,
+                        Some((token, _)) => values.push(value(token).ok_or_else(|| {
+                            syntax(file, *line, &format!("bad value for '{}'", name))
+                        })?),
+                        None => return Err(syntax(file, *line, "unterminated '['")),
+                    }
+                    *pos += 1;
+                }
+                *pos += 1;
+            }
+            Some((token, _)) => {
+                values.push(value(token).ok_or_else(|| {
+                    syntax(file, *line, &format!("missing value for '{}'", name))
+                })?);
+                *pos += 1;
+            }
+            None => {
+                return Err(syntax(
+                    file,
+                    *line,
+                    &format!("missing value for '{}'", name),
+                ))
+            }
+        }
+        params.push(Param { kind, name, values });
+    }
+    Ok(Params(params))
+}
+
+/// An RGB or blackbody color parameter. Spectra are not supported.
+fn color(params: &Params, name: &str) -> Option<Color> {
+    let param = params.find(name)?;
+    let v = params.numbers(name)?;
+    match (param.kind.as_str(), v.as_slice()) {
+        ("rgb", [r, g, b]) => Some(Color::new(*r, *g, *b)),
+        ("blackbody", [kelvin]) => Some(blackbody_color(*kelvin)),
+        _ => None,
+    }
+}
+
+/// Approximate reflectance of pbrt's named metal spectra
+fn metal_color(spectrum: &str) -> Option<Color> {
+    let name = spectrum.strip_prefix("metal-")?.split('-').next()?;
+    Some(match name {
+        "Ag" => Color::new(0.972, 0.960, 0.915),
+        "Al" => Color::new(0.913, 0.922, 0.924),
+        "Au" => Color::new(1.0, 0.766, 0.336),
+        "Cu" => Color::new(0.955, 0.638, 0.538),
+        "CuZn" => Color::new(0.910, 0.778, 0.423),
+        _ => return None,
+    })
+}
+
+/// The material and, if it could only be approximated, why
+fn make_material(kind: &str, params: &Params) -> (Arc<dyn Material + Send + Sync>, Option<String>) {
+    let mut warning = None;
+    let mut unsupported_color = |name: &str| {
+        if params.find(name).is_some() {
+            warning = Some(format!(
+                "unsupported {} '{}' on {}",
+                params.find(name).unwrap().kind,
+                name,
+                kind
+            ));
+        }
+    };
+
+    let material: Arc<dyn Material + Send + Sync> = match kind {
+        "diffuse" => {
+            let reflectance = color(params, "reflectance").unwrap_or_else(|| {
+                unsupported_color("reflectance");
+                Color::new(0.5, 0.5, 0.5)
+            });
+            Arc::new(Lambertian::new(reflectance))
+        }
+        "conductor" => {
+            let albedo = color(params, "reflectance")
+                .or_else(|| params.string("eta").and_then(metal_color))
+                .unwrap_or_else(|| {
+                    unsupported_color("reflectance");
+                    unsupported_color("eta");
+                    metal_color("metal-Cu").unwrap()
+                });
+            let roughness = params.float("roughness", params.float("uroughness", 0.0));
+            Arc::new(Metal::new(albedo, roughness))
+        }
+        "dielectric" => {
+            if params.string("eta").is_some() {
+                unsupported_color("eta");
+            }
+            Arc::new(Dielectric::new(params.float("eta", 1.5)))
+        }
+        _ => {
+            warning = Some(format!("unsupported material '{}', using diffuse", kind));
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+        }
+    };
+    (material, warning)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+
+    const SCENE: &str = r#"
+        # A small test scene
+        LookAt 0 0 5  0 0 0  0 1 0
+        Camera "perspective" "float fov" [ 40 ]
+        Film "rgb" "integer xresolution" 200 "integer yresolution" [100]
+            "string filename" "test.exr"
+        Sampler "zsobol" "integer pixelsamples" 64
+        Integrator "volpath"
+        WorldBegin
+        LightSource "infinite" "rgb L" [0.1 0.2 0.3]
+        AttributeBegin
+            Material "conductor" "spectrum eta" "metal-Au-eta" "float roughness" 0.1
+            Translate 2 0 0
+            Shape "sphere" "float radius" 0.5
+        AttributeEnd
+        AttributeBegin
+            AreaLightSource "diffuse" "blackbody L" [6500] "float scale" 2
+            Shape "trianglemesh" "point3 P" [-1 -1 -2  1 -1 -2  0 1 -2]
+                "integer indices" [0 1 2]
+        AttributeEnd
+        Shape "disk"
+    "#;
+
+    #[test]
+    fn scene_test() {
+        let scene = read_pbrt(SCENE, Path::new(".")).unwrap();
+        assert_eq!((scene.image_width, scene.image_height), (200, 100));
+        assert_eq!(scene.samples_per_pixel, 64);
+        assert_eq!(scene.filename.as_deref(), Some("test.exr"));
+        assert_eq!(
+            scene.background,
+            Background::Constant(Color::new(0.1, 0.2, 0.3))
+        );
+        assert_eq!(scene.world.objects.len(), 2);
+        assert_eq!(scene.warnings.len(), 2);
+        assert!(scene.warnings[0].contains("Integrator"));
+        assert!(scene.warnings[1].contains("disk"));
+
+        // The center ray hits the emitter
+        let r = scene.camera.get_ray(0.5, 0.5);
+        let rec = scene.world.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 7.0).abs() < 1e-6);
+        assert!(rec.material.emitted(rec.u, rec.v, &rec.p).y() > 1.0);
+    }
+
+    #[test]
+    fn handedness_test() {
+        // In pbrt, +x appears on the left for a camera looking down -z
+        let scene = read_pbrt(SCENE, Path::new(".")).unwrap();
+        let left = scene.camera.get_ray(0.2, 0.5);
+        let right = scene.camera.get_ray(0.8, 0.5);
+        let sphere = |r: &Ray| {
+            scene
+                .world
+                .hit(r, 0.001, f64::INFINITY)
+                .is_some_and(|rec| rec.material.emitted(rec.u, rec.v, &rec.p).y() == 0.0)
+        };
+        assert!(sphere(&left));
+        assert!(!sphere(&right));
+    }
+
+    #[test]
+    fn errors_test() {
+        let error = read_pbrt("LookAt 0 0 1\nWorldBegin", Path::new("."))
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("<scene>:1"));
+        assert!(read_pbrt("Shape \"sphere\" \"float radius\"", Path::new(".")).is_err());
+        assert!(read_pbrt("AttributeEnd", Path::new(".")).is_err());
+        assert!(read_pbrt(
+            "Material \"diffuse\" \"rgb reflectance [1 0 0]",
+            Path::new(".")
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn one_sided_light_test() {
+        // The triangle's normal points towards +z in pbrt
+        let triangle = "Shape \"trianglemesh\" \"point3 P\" [-1 -1 0  1 -1 0  0 1 0]";
+        // Emission seen by a ray from z along -z, or along +z from inside
+        let emitted = |text: &str, z: f64| {
+            let scene = read_pbrt(text, Path::new(".")).unwrap();
+            let direction = if z > 0.0 { -1.0 } else { 1.0 };
+            let r = Ray::new(Point3::new(0.0, 0.0, z), Vec3::new(0.0, 0.0, direction));
+            let rec = scene.world.hit(&r, 0.001, f64::INFINITY).unwrap();
+            rec.material.emitted_at(&rec).y()
+        };
+
+        let one_sided = format!("AreaLightSource \"diffuse\"\n{}", triangle);
+        assert!(emitted(&one_sided, 1.0) > 0.0);
+        assert_eq!(emitted(&one_sided, -1.0), 0.0);
+        let reversed = format!("ReverseOrientation\n{}", one_sided);
+        assert_eq!(emitted(&reversed, 1.0), 0.0);
+        assert!(emitted(&reversed, -1.0) > 0.0);
+        let two_sided = format!(
+            "AreaLightSource \"diffuse\" \"bool twosided\" true\n{}",
+            triangle
+        );
+        assert!(emitted(&two_sided, -1.0) > 0.0);
+
+        let sphere = "AreaLightSource \"diffuse\"\nShape \"sphere\"";
+        assert!(emitted(sphere, 2.0) > 0.0);
+        assert_eq!(emitted(sphere, 0.0), 0.0);
+    }
+
+    #[test]
+    fn include_cycle_test() {
+        let dir = std::env::temp_dir().join("pbrt_include_cycle_test");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.pbrt"), "Include \"b.pbrt\"\n").unwrap();
+        fs::write(dir.join("b.pbrt"), "Include \"a.pbrt\"\n").unwrap();
+
+        let error = load_pbrt(dir.join("a.pbrt")).err().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(error.to_string().contains("includes itself"));
+    }
+}
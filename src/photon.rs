@@ -0,0 +1,580 @@
+//! Photon mapping.
+//!
+//! Photons are shot from area lights, followed through the scene by
+//! scattering and stored where they land on surfaces with a BSDF. Radiance
+//! at a point is then estimated from the density of nearby photons. This
+//! resolves caustics, light focused by mirrors and glass onto diffuse
+//! surfaces, which a path tracer only finds by chance.
+//!
+//! `PhotonMapper` path traces everything but caustics, which it looks up in
+//! a photon map built once. `Sppm` is progressive: it shoots new photons
+//! every pass and shrinks the lookup radius of every pixel, so the blur of
+//! the density estimate vanishes and the image converges.
+
+use image::RgbImage;
+use std::f64::consts::PI;
+use std::ops::Range;
+
+use crate::camera::Camera;
+use crate::hittable::*;
+use crate::integrator::{Background, Integrator};
+use crate::light::{direct_lighting, pick_light, AreaLight};
+use crate::ray::Ray;
+use crate::render::{for_each_row, radiance_image, RenderSettings};
+use crate::vec3::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Photon {
+    pub p: Point3,
+    /// Unit direction the photon travelled in
+    pub direction: Vec3,
+    pub power: Color,
+}
+
+/// Photons in a balanced kd-tree. The median of every range is its node, so
+/// the tree needs no storage beyond the photons and their split axes.
+#[derive(Default)]
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<u8>,
+}
+
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>) -> PhotonMap {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        PhotonMap { photons, axes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// Calls `f` with every photon within `radius` of `p`
+    pub fn within(&self, p: &Point3, radius: f64, mut f: impl FnMut(&Photon)) {
+        self.visit(0..self.photons.len(), p, radius, &mut f);
+    }
+
+    fn visit(&self, range: Range<usize>, p: &Point3, radius: f64, f: &mut impl FnMut(&Photon)) {
+        if range.is_empty() {
+            return;
+        }
+        let mid = (range.start + range.end) / 2;
+        let photon = &self.photons[mid];
+        if (photon.p - *p).length_squared() <= radius * radius {
+            f(photon);
+        }
+        let axis = self.axes[mid] as usize;
+        let d = p[axis] - photon.p[axis];
+        if d <= radius {
+            self.visit(range.start..mid, p, radius, f);
+        }
+        if d >= -radius {
+            self.visit(mid + 1..range.end, p, radius, f);
+        }
+    }
+
+    /// Power of the photons within `radius` of `rec` reflected towards `wo`,
+    /// and how many there were
+    pub fn flux(&self, rec: &HitRecord, wo: &Vec3, radius: f64) -> (Color, usize) {
+        let mut flux = Color::default();
+        let mut count = 0;
+        self.within(&rec.p, radius, |photon| {
+            if let Some((f, _)) = rec.material.eval(rec, wo, &-photon.direction) {
+                flux += f * photon.power;
+            }
+            count += 1;
+        });
+        (flux, count)
+    }
+
+    /// Radiance leaving `rec` towards `wo` from the photon density within
+    /// `radius`
+    pub fn radiance(&self, rec: &HitRecord, wo: &Vec3, radius: f64) -> Color {
+        self.flux(rec, wo, radius).0 / (PI * radius * radius)
+    }
+}
+
+fn build(photons: &mut [Photon], axes: &mut [u8]) {
+    if photons.len() <= 1 {
+        return;
+    }
+    let (lo, hi) = photons.iter().fold(
+        (
+            Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        ),
+        |(lo, hi), photon| (lo.min(&photon.p), hi.max(&photon.p)),
+    );
+    let extent = hi - lo;
+    let axis = (0..3)
+        .max_by(|&a, &b| extent[a].total_cmp(&extent[b]))
+        .unwrap();
+
+    let mid = photons.len() / 2;
+    photons.select_nth_unstable_by(mid, |a, b| a.p[axis].total_cmp(&b.p[axis]));
+    axes[mid] = axis as u8;
+    let (left, right) = photons.split_at_mut(mid);
+    let (left_axes, right_axes) = axes.split_at_mut(mid);
+    build(left, left_axes);
+    build(&mut right[1..], &mut right_axes[1..]);
+}
+
+/// Which photons `trace_photons` stores
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhotonKind {
+    /// Photons that reached a surface with a BSDF only through materials
+    /// without one, such as glass. The photon ends there.
+    Caustic,
+    /// Photons at every surface with a BSDF but the first they hit, which
+    /// direct lighting covers
+    Indirect,
+}
+
+// Materials without a BSDF to evaluate are only passed by scattering
+fn is_delta(rec: &HitRecord, wo: &Vec3) -> bool {
+    rec.material.eval(rec, wo, wo).is_none()
+}
+
+// Russian roulette from the third bounce on. Ends the path with a
+// probability following `reflectance`, or returns `weight` brightened to
+// make up for the ended ones.
+fn roulette(bounce: usize, reflectance: &Color, weight: Color) -> Option<Color> {
+    if bounce + 1 < 3 {
+        return Some(weight);
+    }
+    let survival = reflectance.max_component().min(0.95);
+    if random_double(0.0..1.0) >= survival {
+        None
+    } else {
+        Some(weight / survival)
+    }
+}
+
+/// Shoots `count` photons from `lights`, picked uniformly, into `world`.
+/// Their power is divided by `count`, so a photon map of them estimates
+/// radiance directly.
+pub fn trace_photons(
+    world: &dyn Hittable,
+    lights: &[AreaLight],
+    count: usize,
+    max_depth: usize,
+    kind: PhotonKind,
+) -> Vec<Photon> {
+    let mut photons = Vec::new();
+    for _ in 0..count {
+        let (_, light, pick_pdf) = match pick_light(lights) {
+            Some(pick) => pick,
+            None => break,
+        };
+        let (p, n) = light.sample_point();
+        let (direction, pdf_dir) = light.sample_direction(&n);
+        if pdf_dir == 0.0 {
+            continue;
+        }
+        let pdf = pick_pdf * pdf_dir / light.area();
+        let mut power =
+            light.radiance(&n, &direction) * dot(&n, &direction).abs() / (pdf * count as f64);
+        let mut ray = Ray::new(p, direction);
+
+        for bounce in 0..max_depth {
+            let rec = match world.hit(&ray, 0.001, f64::INFINITY) {
+                Some(rec) => rec,
+                None => break,
+            };
+            let direction = unit_vector(ray.direction());
+            let delta = is_delta(&rec, &-direction);
+            if !delta {
+                if bounce > 0 {
+                    photons.push(Photon {
+                        p: rec.p,
+                        direction,
+                        power,
+                    });
+                }
+                // A caustic photon's first surface with a BSDF is its last
+                if kind == PhotonKind::Caustic {
+                    break;
+                }
+            }
+            let (attenuation, scattered) = match rec.material.scatter(&ray, &rec) {
+                Some(scatter) => scatter,
+                None => break,
+            };
+            // Keep photon powers alike by letting the material's
+            // reflectance decide survival
+            power = match roulette(bounce, &attenuation, power * attenuation) {
+                Some(power) => power,
+                None => break,
+            };
+            ray = scattered;
+        }
+    }
+    photons
+}
+
+/// Path tracing with caustics from a photon map.
+///
+/// Wherever a path meets a surface with a BSDF, the caustic photons around
+/// it are added. Paths that then reach one of the lights through glass or
+/// mirrors alone carry the same light, so their emission is dropped. Only
+/// `lights` cast photons; caustics of other emitters and the sky are still
+/// path traced.
+pub struct PhotonMapper {
+    lights: Vec<AreaLight>,
+    caustics: PhotonMap,
+    /// Photon lookup radius, which trades noise for blur
+    pub radius: f64,
+    pub max_depth: usize,
+    pub background: Background,
+}
+
+impl PhotonMapper {
+    /// Shoots `photons` photons from `lights`, whose hittables must be in
+    /// `world`
+    pub fn new(
+        world: &dyn Hittable,
+        lights: Vec<AreaLight>,
+        photons: usize,
+        radius: f64,
+        max_depth: usize,
+    ) -> PhotonMapper {
+        let caustics = trace_photons(world, &lights, photons, max_depth, PhotonKind::Caustic);
+        PhotonMapper {
+            lights,
+            caustics: PhotonMap::new(caustics),
+            radius,
+            max_depth,
+            background: Background::Sky,
+        }
+    }
+
+    pub fn with_background(mut self, background: Background) -> PhotonMapper {
+        self.background = background;
+        self
+    }
+
+    pub fn caustics(&self) -> &PhotonMap {
+        &self.caustics
+    }
+}
+
+impl Integrator for PhotonMapper {
+    fn li(&self, r: &Ray, world: &dyn Hittable) -> Color {
+        let mut radiance = Color::default();
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = Ray::new(r.origin(), r.direction());
+        // Whether the path left a surface with a BSDF and has only passed
+        // delta materials since, and whether it has passed any
+        let (mut after_bsdf, mut through_delta) = (false, false);
+
+        for bounce in 0..self.max_depth {
+            let rec = match world.hit(&ray, 0.001, f64::INFINITY) {
+                Some(rec) => rec,
+                None => return radiance + throughput * self.background.radiance(&ray),
+            };
+            let caustic = after_bsdf && through_delta;
+            if !(caustic && self.lights.iter().any(|light| light.is_hit(&rec))) {
+                radiance += throughput * rec.material.emitted_at(&rec);
+            }
+
+            let wo = -unit_vector(ray.direction());
+            if is_delta(&rec, &wo) {
+                through_delta = after_bsdf;
+            } else {
+                radiance += throughput * self.caustics.radiance(&rec, &wo, self.radius);
+                after_bsdf = true;
+                through_delta = false;
+            }
+
+            let (attenuation, scattered) = match rec.material.scatter(&ray, &rec) {
+                Some(scatter) => scatter,
+                None => return radiance,
+            };
+            throughput = throughput * attenuation;
+            throughput = match roulette(bounce, &throughput, throughput) {
+                Some(throughput) => throughput,
+                None => return radiance,
+            };
+            ray = scattered;
+        }
+        radiance
+    }
+}
+
+// What a pixel has gathered over the passes of `Sppm`
+#[derive(Clone, Copy)]
+struct SppmPixel {
+    /// Sum of the light reaching the camera directly or through direct
+    /// lighting, over all passes
+    direct: Color,
+    /// Photon power gathered within `radius`, scaled as the radius shrinks
+    tau: Color,
+    /// Photons the pixel is deemed to have gathered
+    count: f64,
+    radius: f64,
+}
+
+/// Stochastic progressive photon mapping.
+///
+/// Each of `samples_per_pixel` passes follows one camera ray per pixel
+/// through glass and mirrors to the first surface with a BSDF. The light
+/// there comes from direct lighting plus the photons of that pass within
+/// the pixel's radius. The radius then shrinks so that a fraction `alpha` of
+/// the new photons counts, which makes the estimate converge. Only `lights`
+/// cast photons, so other emitters and the sky light the scene only where
+/// the camera sees them through glass or mirrors.
+pub struct Sppm {
+    lights: Vec<AreaLight>,
+    pub initial_radius: f64,
+    pub photons_per_pass: usize,
+    /// Between 0 and 1, lower shrinks the radius faster
+    pub alpha: f64,
+    pub max_depth: usize,
+    pub background: Background,
+}
+
+impl Sppm {
+    /// Lights whose hittables must be in the rendered world
+    pub fn new(lights: Vec<AreaLight>, initial_radius: f64, max_depth: usize) -> Sppm {
+        Sppm {
+            lights,
+            initial_radius,
+            photons_per_pass: 100_000,
+            alpha: 2.0 / 3.0,
+            max_depth,
+            background: Background::Sky,
+        }
+    }
+
+    pub fn with_photons_per_pass(mut self, photons_per_pass: usize) -> Sppm {
+        self.photons_per_pass = photons_per_pass;
+        self
+    }
+
+    pub fn with_alpha(mut self, alpha: f64) -> Sppm {
+        self.alpha = alpha;
+        self
+    }
+
+    pub fn with_background(mut self, background: Background) -> Sppm {
+        self.background = background;
+        self
+    }
+
+    /// Radiance of every pixel, row by row from the top
+    pub fn radiance(
+        &self,
+        world: &(dyn Hittable + Send + Sync),
+        cam: &Camera,
+        settings: &RenderSettings,
+    ) -> Vec<Color> {
+        let RenderSettings {
+            image_width,
+            image_height,
+            samples_per_pixel,
+        } = *settings;
+        let mut pixels = vec![
+            SppmPixel {
+                direct: Color::default(),
+                tau: Color::default(),
+                count: 0.0,
+                radius: self.initial_radius,
+            };
+            (image_width * image_height) as usize
+        ];
+
+        for _ in 0..samples_per_pixel {
+            let photons = trace_photons(
+                world,
+                &self.lights,
+                self.photons_per_pass,
+                self.max_depth,
+                PhotonKind::Indirect,
+            );
+            let photons = PhotonMap::new(photons);
+
+            for_each_row(&mut pixels, image_width, |j, row| {
+                for (i, pixel) in row.iter_mut().enumerate() {
+                    let u = (i as f64 + random_double(0.0..1.0)) / (image_width - 1) as f64;
+                    let v = (image_height as f64 - j as f64 + random_double(0.0..1.0))
+                        / (image_height - 1) as f64;
+                    self.update(pixel, &cam.get_ray(u, v), world, &photons);
+                }
+            });
+        }
+
+        let passes = samples_per_pixel as f64;
+        pixels
+            .iter()
+            .map(|pixel| {
+                let area = PI * pixel.radius * pixel.radius;
+                (pixel.direct + pixel.tau / area) / passes
+            })
+            .collect()
+    }
+
+    /// Renders one image, with one pass per sample per pixel
+    pub fn render(
+        &self,
+        world: &(dyn Hittable + Send + Sync),
+        cam: &Camera,
+        settings: &RenderSettings,
+    ) -> RgbImage {
+        radiance_image(&self.radiance(world, cam, settings), settings)
+    }
+
+    // Follows `r` to its visible point and gathers this pass's light there
+    fn update(&self, pixel: &mut SppmPixel, r: &Ray, world: &dyn Hittable, photons: &PhotonMap) {
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        let mut ray = Ray::new(r.origin(), r.direction());
+
+        for _ in 0..self.max_depth {
+            let rec = match world.hit(&ray, 0.001, f64::INFINITY) {
+                Some(rec) => rec,
+                None => {
+                    pixel.direct += throughput * self.background.radiance(&ray);
+                    return;
+                }
+            };
+            pixel.direct += throughput * rec.material.emitted_at(&rec);
+
+            let wo = -unit_vector(ray.direction());
+            if !is_delta(&rec, &wo) {
+                pixel.direct += throughput * direct_lighting(&self.lights, world, &rec, &wo);
+
+                let (flux, count) = photons.flux(&rec, &wo, pixel.radius);
+                if count > 0 {
+                    let count = count as f64;
+                    let new_count = pixel.count + self.alpha * count;
+                    let shrink = new_count / (pixel.count + count);
+                    pixel.tau = (pixel.tau + throughput * flux) * shrink;
+                    pixel.radius *= shrink.sqrt();
+                    pixel.count = new_count;
+                }
+                return;
+            }
+
+            let (attenuation, scattered) = match rec.material.scatter(&ray, &rec) {
+                Some(scatter) => scatter,
+                None => return,
+            };
+            throughput = throughput * attenuation;
+            ray = scattered;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::integrator::PathTracer;
+    use crate::test_scenes::{box_scene, pixel_means, seeded};
+
+    #[test]
+    fn within_test() {
+        let photons: Vec<Photon> = (0..500)
+            .map(|_| Photon {
+                p: Vec3::random(-1.0..1.0),
+                direction: Vec3::new(0.0, -1.0, 0.0),
+                power: Color::new(1.0, 1.0, 1.0),
+            })
+            .collect();
+        let map = PhotonMap::new(photons.clone());
+        assert_eq!(map.len(), 500);
+
+        for _ in 0..20 {
+            let p = Vec3::random(-1.0..1.0);
+            let mut found = Vec::new();
+            map.within(&p, 0.3, |photon| found.push(photon.p));
+            let expected = photons
+                .iter()
+                .filter(|photon| (photon.p - p).length() <= 0.3)
+                .count();
+            assert_eq!(found.len(), expected);
+            assert!(found.iter().all(|q| (*q - p).length() <= 0.3));
+        }
+    }
+
+    // Looks down from under the light at the floor and the glass ball's
+    // caustic, so that pixel noise from seeing the light doesn't swamp the
+    // comparisons
+    fn floor_camera(settings: &RenderSettings) -> Camera {
+        Camera::new(
+            Point3::new(0.0, 1.5, 0.0),
+            Point3::new(0.0, -2.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            70.0,
+            settings.aspect_ratio(),
+            0.0,
+            1.0,
+        )
+    }
+
+    // Mean pixel radiance over camera rays like `render` shoots
+    fn mean(integrator: &dyn Integrator, world: &dyn Hittable, settings: &RenderSettings) -> f64 {
+        let means = pixel_means(&floor_camera(settings), settings, |r| {
+            integrator.li(r, world)
+        });
+        means.iter().map(|c| c.x()).sum::<f64>() / means.len() as f64
+    }
+
+    #[test]
+    fn photon_mapper_test() {
+        let (world, light) = box_scene();
+        let settings = RenderSettings {
+            image_width: 6,
+            image_height: 4,
+            samples_per_pixel: 2000,
+        };
+        let mapper = seeded(1, || {
+            PhotonMapper::new(&world, vec![light], 50_000, 0.1, 50)
+        });
+        assert!(!mapper.caustics().is_empty());
+        let path_tracer = PathTracer::new(50);
+
+        let expected = seeded(1, || mean(&path_tracer, &world, &settings));
+        let mapped = seeded(3, || mean(&mapper, &world, &settings));
+        assert!(
+            (mapped - expected).abs() < 0.1 * expected,
+            "photon mapper {} path tracer {}",
+            mapped,
+            expected
+        );
+    }
+
+    #[test]
+    fn sppm_test() {
+        let (world, light) = box_scene();
+        let settings = RenderSettings {
+            image_width: 6,
+            image_height: 4,
+            samples_per_pixel: 2000,
+        };
+        let path_tracer = PathTracer::new(50);
+        let expected = seeded(1, || mean(&path_tracer, &world, &settings));
+
+        let settings = RenderSettings {
+            samples_per_pixel: 32,
+            ..settings
+        };
+        // Few passes leave the radii large, which blurs away some light near
+        // the walls
+        let sppm = Sppm::new(vec![light], 0.3, 50).with_photons_per_pass(4000);
+        let radiance = seeded(2, || {
+            sppm.radiance(&world, &floor_camera(&settings), &settings)
+        });
+        let progressive = radiance.iter().map(|c| c.x()).sum::<f64>() / radiance.len() as f64;
+        assert!(
+            (progressive - expected).abs() < 0.15 * expected,
+            "sppm {} path tracer {}",
+            progressive,
+            expected
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::Arc;
+
+use crate::aabb::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::ray::*;
+use crate::vec3::*;
+
+/// Infinite plane through `point`, facing `normal`.
+///
+/// UVs are the in-plane coordinates of the hit point relative to `point`, in
+/// world units. Being unbounded, planes cannot go into a `BvhNode`.
+pub struct Plane {
+    point: Point3,
+    normal: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+impl Plane {
+    pub fn new(point: Point3, normal: Vec3, material: Arc<dyn Material + Send + Sync>) -> Plane {
+        let normal = unit_vector(normal);
+        let a = if normal.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let tangent = unit_vector(cross(&a, &normal));
+        let bitangent = cross(&normal, &tangent);
+
+        Plane {
+            point,
+            normal,
+            tangent,
+            bitangent,
+            material,
+        }
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let denom = dot(&self.normal, &r.direction());
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+
+        let t = dot(&self.normal, &(self.point - r.origin())) / denom;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        let p = r.at(t);
+        let offset = p - self.point;
+        let mut rec = HitRecord {
+            t,
+            p,
+            u: dot(&offset, &self.tangent),
+            v: dot(&offset, &self.bitangent),
+            material: self.material.clone(),
+            ..Default::default()
+        };
+        rec.set_face_normal(r, &self.normal);
+
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plane_hit_test() {
+        let plane = Plane::new(
+            Point3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        );
+        let r = Ray::new(Point3::new(100.0, 1.0, -50.0), Vec3::new(0.0, -1.0, 0.0));
+        let rec = plane.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 2.0);
+        assert_eq!(rec.normal, Vec3::new(0.0, 1.0, 0.0));
+        assert!(rec.front_face);
+
+        let parallel = Ray::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert!(plane.hit(&parallel, 0.001, f64::INFINITY).is_none());
+        assert!(plane.bounding_box().is_none());
+    }
+}
@@ -0,0 +1,392 @@
+//! Reader for Stanford PLY files in ASCII and binary (either byte order)
+//! encodings.
+//!
+//! Every element is read column-wise: scalar properties become one `f64` per
+//! row and list properties one `Vec<f64>` per row. Interpreting the
+//! elements, e.g. `vertex` or `face`, is left to the caller.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlyType {
+    Char,
+    UChar,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    Float,
+    Double,
+}
+
+impl PlyType {
+    fn parse(name: &str) -> Option<PlyType> {
+        Some(match name {
+            "char" | "int8" => PlyType::Char,
+            "uchar" | "uint8" => PlyType::UChar,
+            "short" | "int16" => PlyType::Short,
+            "ushort" | "uint16" => PlyType::UShort,
+            "int" | "int32" => PlyType::Int,
+            "uint" | "uint32" => PlyType::UInt,
+            "float" | "float32" => PlyType::Float,
+            "double" | "float64" => PlyType::Double,
+            _ => return None,
+        })
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            PlyType::Char | PlyType::UChar => 1,
+            PlyType::Short | PlyType::UShort => 2,
+            PlyType::Int | PlyType::UInt | PlyType::Float => 4,
+            PlyType::Double => 8,
+        }
+    }
+
+    /// The value that stands for full intensity in color properties
+    pub fn color_scale(&self) -> f64 {
+        match self {
+            PlyType::UChar => 255.0,
+            PlyType::UShort => 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlyProperty {
+    pub name: String,
+    pub kind: PlyType,
+    /// Type of the length prefix for list properties
+    pub list: Option<PlyType>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlyColumn {
+    Scalar(Vec<f64>),
+    List(Vec<Vec<f64>>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlyElement {
+    pub name: String,
+    pub count: usize,
+    pub properties: Vec<PlyProperty>,
+    pub columns: Vec<PlyColumn>,
+}
+
+impl PlyElement {
+    pub fn property(&self, name: &str) -> Option<&PlyProperty> {
+        self.properties.iter().find(|p| p.name == name)
+    }
+
+    pub fn scalar(&self, name: &str) -> Option<&[f64]> {
+        let index = self.properties.iter().position(|p| p.name == name)?;
+        match &self.columns[index] {
+            PlyColumn::Scalar(values) => Some(values),
+            PlyColumn::List(_) => None,
+        }
+    }
+
+    pub fn list(&self, name: &str) -> Option<&[Vec<f64>]> {
+        let index = self.properties.iter().position(|p| p.name == name)?;
+        match &self.columns[index] {
+            PlyColumn::List(values) => Some(values),
+            PlyColumn::Scalar(_) => None,
+        }
+    }
+
+    /// The first of `names` that is a scalar property
+    pub fn any_scalar(&self, names: &[&str]) -> Option<&[f64]> {
+        names.iter().find_map(|name| self.scalar(name))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlyFile {
+    pub format: PlyFormat,
+    pub elements: Vec<PlyElement>,
+}
+
+impl PlyFile {
+    pub fn element(&self, name: &str) -> Option<&PlyElement> {
+        self.elements.iter().find(|e| e.name == name)
+    }
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<PlyFile> {
+    read(BufReader::new(File::open(path)?))
+}
+
+pub fn read<R: BufRead>(mut reader: R) -> io::Result<PlyFile> {
+    let (format, mut elements) = read_header(&mut reader)?;
+
+    match format {
+        PlyFormat::Ascii => {
+            let mut body = String::new();
+            reader.read_to_string(&mut body)?;
+            let mut tokens = body.split_whitespace();
+            let mut next = |kind: PlyType| -> io::Result<f64> {
+                let token = tokens
+                    .next()
+                    .ok_or_else(|| invalid("unexpected end of data"))?;
+                let value = token
+                    .parse::<f64>()
+                    .map_err(|_| invalid(&format!("bad number '{}'", token)))?;
+                Ok(match kind {
+                    PlyType::Float | PlyType::Double => value,
+                    _ => value.trunc(),
+                })
+            };
+            for element in &mut elements {
+                read_rows(element, &mut next)?;
+            }
+        }
+        PlyFormat::BinaryLittleEndian | PlyFormat::BinaryBigEndian => {
+            let little = format == PlyFormat::BinaryLittleEndian;
+            let mut next = |kind: PlyType| read_binary(&mut reader, kind, little);
+            for element in &mut elements {
+                read_rows(element, &mut next)?;
+            }
+        }
+    }
+
+    Ok(PlyFile { format, elements })
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("PLY: {}", message))
+}
+
+fn read_header<R: BufRead>(reader: &mut R) -> io::Result<(PlyFormat, Vec<PlyElement>)> {
+    let mut line = String::new();
+    let mut next_line = |line: &mut String| -> io::Result<()> {
+        line.clear();
+        if reader.read_line(line)? == 0 {
+            return Err(invalid("unexpected end of header"));
+        }
+        Ok(())
+    };
+
+    next_line(&mut line)?;
+    if line.trim() != "ply" {
+        return Err(invalid("missing 'ply' magic"));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = Vec::new();
+    loop {
+        next_line(&mut line)?;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["end_header"] => break,
+            [] | ["comment", ..] | ["obj_info", ..] => {}
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                    "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                    _ => return Err(invalid(&format!("unknown format '{}'", name))),
+                });
+            }
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| invalid(&format!("bad element count '{}'", count)))?,
+                properties: Vec::new(),
+                columns: Vec::new(),
+            }),
+            ["property", rest @ ..] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| invalid("property before any element"))?;
+                let kind = |name: &str| {
+                    PlyType::parse(name).ok_or_else(|| invalid(&format!("unknown type '{}'", name)))
+                };
+                let (property, column) = match rest {
+                    ["list", count, item, name] => (
+                        PlyProperty {
+                            name: name.to_string(),
+                            kind: kind(item)?,
+                            list: Some(kind(count)?),
+                        },
+                        PlyColumn::List(Vec::new()),
+                    ),
+                    [item, name] => (
+                        PlyProperty {
+                            name: name.to_string(),
+                            kind: kind(item)?,
+                            list: None,
+                        },
+                        PlyColumn::Scalar(Vec::new()),
+                    ),
+                    _ => return Err(invalid(&format!("bad property '{}'", line.trim()))),
+                };
+                element.properties.push(property);
+                element.columns.push(column);
+            }
+            _ => {
+                return Err(invalid(&format!(
+                    "unexpected header line '{}'",
+                    line.trim()
+                )))
+            }
+        }
+    }
+
+    let format = format.ok_or_else(|| invalid("missing format line"))?;
+    // Rows without properties take no data, so nothing bounds their count
+    if let Some(element) = elements
+        .iter()
+        .find(|e| e.properties.is_empty() && e.count > 0)
+    {
+        return Err(invalid(&format!(
+            "element '{}' has rows but no properties",
+            element.name
+        )));
+    }
+    Ok((format, elements))
+}
+
+fn read_rows<F>(element: &mut PlyElement, next: &mut F) -> io::Result<()>
+where
+    F: FnMut(PlyType) -> io::Result<f64>,
+{
+    for _ in 0..element.count {
+        for (property, column) in element.properties.iter().zip(&mut element.columns) {
+            match column {
+                PlyColumn::Scalar(values) => values.push(next(property.kind)?),
+                PlyColumn::List(lists) => {
+                    let len = next(property.list.unwrap())? as usize;
+                    let list = (0..len)
+                        .map(|_| next(property.kind))
+                        .collect::<io::Result<Vec<_>>>()?;
+                    lists.push(list);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_binary<R: Read>(reader: &mut R, kind: PlyType, little: bool) -> io::Result<f64> {
+    let mut buf = [0u8; 8];
+    let bytes = &mut buf[..kind.size()];
+    reader.read_exact(bytes).map_err(|e| {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            invalid("unexpected end of data")
+        } else {
+            e
+        }
+    })?;
+    if !little {
+        bytes.reverse();
+    }
+
+    let b = buf;
+    Ok(match kind {
+        PlyType::Char => b[0] as i8 as f64,
+        PlyType::UChar => b[0] as f64,
+        PlyType::Short => i16::from_le_bytes([b[0], b[1]]) as f64,
+        PlyType::UShort => u16::from_le_bytes([b[0], b[1]]) as f64,
+        PlyType::Int => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+        PlyType::UInt => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+        PlyType::Float => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+        PlyType::Double => f64::from_le_bytes(b),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "element vertex 2\n\
+        property float x\n\
+        property float y\n\
+        property float z\n\
+        property uchar red\n\
+        element face 1\n\
+        property list uchar int vertex_indices\n\
+        end_header\n";
+
+    #[test]
+    fn ascii_test() {
+        let text = format!(
+            "ply\nformat ascii 1.0\ncomment test\n{}1 2 3 255\n-1 0.5 0 0\n3 0 1 1\n",
+            HEADER
+        );
+        let ply = read(text.as_bytes()).unwrap();
+
+        let vertex = ply.element("vertex").unwrap();
+        assert_eq!(vertex.scalar("x").unwrap(), &[1.0, -1.0]);
+        assert_eq!(vertex.scalar("y").unwrap(), &[2.0, 0.5]);
+        assert_eq!(vertex.scalar("red").unwrap(), &[255.0, 0.0]);
+        assert_eq!(vertex.property("red").unwrap().kind.color_scale(), 255.0);
+        let face = ply.element("face").unwrap();
+        assert_eq!(face.list("vertex_indices").unwrap(), &[vec![0.0, 1.0, 1.0]]);
+    }
+
+    #[test]
+    fn binary_matches_ascii_test() {
+        let ascii = format!(
+            "ply\nformat ascii 1.0\n{}1 2 3 255\n-1 0.5 0 0\n3 0 1 1\n",
+            HEADER
+        );
+        let expected = read(ascii.as_bytes()).unwrap();
+
+        for (name, little) in [("binary_little_endian", true), ("binary_big_endian", false)] {
+            let mut data = format!("ply\nformat {} 1.0\n{}", name, HEADER).into_bytes();
+            let f = |v: f32| {
+                if little {
+                    v.to_le_bytes()
+                } else {
+                    v.to_be_bytes()
+                }
+            };
+            let i = |v: i32| {
+                if little {
+                    v.to_le_bytes()
+                } else {
+                    v.to_be_bytes()
+                }
+            };
+            for (x, y, z, red) in [(1.0, 2.0, 3.0, 255u8), (-1.0, 0.5, 0.0, 0)] {
+                data.extend(f(x));
+                data.extend(f(y));
+                data.extend(f(z));
+                data.push(red);
+            }
+            data.push(3);
+            for index in [0, 1, 1] {
+                data.extend(i(index));
+            }
+
+            let ply = read(data.as_slice()).unwrap();
+            assert_eq!(ply.elements, expected.elements);
+        }
+    }
+
+    #[test]
+    fn truncated_test() {
+        let text = format!("ply\nformat ascii 1.0\n{}1 2 3", HEADER);
+        assert!(read(text.as_bytes()).is_err());
+        assert!(read("plx\n".as_bytes()).is_err());
+
+        // Counts in the header are not trusted with allocations
+        let huge = "ply\nformat binary_little_endian 1.0\n\
+            element vertex 1152921504606846976\nproperty float x\nend_header\n";
+        assert!(read(huge.as_bytes()).is_err());
+        let empty = "ply\nformat ascii 1.0\nelement vertex 1152921504606846976\nend_header\n";
+        assert!(read(empty.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,346 @@
+//! Point clouds rendered as camera-facing disks or as spheres.
+//!
+//! Points are stored in a flat array next to a flat BVH of their own, so a
+//! cloud of millions of points is a single hittable rather than millions of
+//! `Arc<dyn Hittable>`.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::aabb::*;
+use crate::bvh::FlatBvh;
+use crate::hittable::*;
+use crate::material::*;
+use crate::ply;
+use crate::ray::*;
+use crate::sphere::Sphere;
+use crate::vec3::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointShape {
+    /// A disk facing the incoming ray
+    Disk,
+    Sphere,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CloudPoint {
+    pub position: [f32; 3],
+    pub radius: f32,
+    pub color: [u8; 3],
+}
+
+impl CloudPoint {
+    pub fn new(position: Point3, radius: f64, color: Color) -> CloudPoint {
+        let byte = |c: f64| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+        CloudPoint {
+            position: [
+                position.x() as f32,
+                position.y() as f32,
+                position.z() as f32,
+            ],
+            radius: radius as f32,
+            color: [byte(color.x()), byte(color.y()), byte(color.z())],
+        }
+    }
+
+    pub fn center(&self) -> Point3 {
+        let [x, y, z] = self.position;
+        Point3::new(x as f64, y as f64, z as f64)
+    }
+
+    pub fn albedo(&self) -> Color {
+        let [r, g, b] = self.color;
+        Color::new(r as f64, g as f64, b as f64) / 255.0
+    }
+
+    fn bounds(&self) -> Aabb {
+        let c = self.center();
+        let r = Vec3::new(1.0, 1.0, 1.0) * self.radius as f64;
+        Aabb::new(c - r, c + r)
+    }
+}
+
+pub struct PointCloud {
+    points: Vec<CloudPoint>,
+    bvh: FlatBvh,
+    shape: PointShape,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+impl PointCloud {
+    /// Points are shaded as Lambertian surfaces of their own colors, with
+    /// a `VertexColor` material
+    pub fn new(mut points: Vec<CloudPoint>, shape: PointShape) -> PointCloud {
+        let bvh = FlatBvh::build(&mut points, CloudPoint::bounds);
+        PointCloud {
+            points,
+            bvh,
+            shape,
+            material: Arc::new(VertexColor),
+        }
+    }
+
+    /// Uses another material for all points. The point colors are still
+    /// passed on in the hit records.
+    pub fn with_material(mut self, material: Arc<dyn Material + Send + Sync>) -> PointCloud {
+        self.material = material;
+        self
+    }
+
+    pub fn points(&self) -> &[CloudPoint] {
+        &self.points
+    }
+
+    fn hit_point(&self, point: &CloudPoint, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let center = point.center();
+        let radius = point.radius as f64;
+        let oc = r.origin() - center;
+        let a = r.direction().length_squared();
+
+        let (t, outward_normal, (u, v)) = match self.shape {
+            PointShape::Disk => {
+                let t = -dot(&oc, &r.direction()) / a;
+                if t < t_min || t > t_max {
+                    return None;
+                }
+                let offset = r.at(t) - center;
+                if offset.length_squared() > radius * radius {
+                    return None;
+                }
+                (t, -unit_vector(r.direction()), (0.0, 0.0))
+            }
+            PointShape::Sphere => {
+                let half_b = dot(&oc, &r.direction());
+                let c = oc.length_squared() - radius * radius;
+                let discriminant = half_b * half_b - a * c;
+                if discriminant < 0.0 {
+                    return None;
+                }
+                let sqrtd = discriminant.sqrt();
+                let mut t = (-half_b - sqrtd) / a;
+                if t < t_min || t > t_max {
+                    t = (-half_b + sqrtd) / a;
+                    if t < t_min || t > t_max {
+                        return None;
+                    }
+                }
+                let outward_normal = (r.at(t) - center) / radius;
+                (t, outward_normal, Sphere::get_sphere_uv(&outward_normal))
+            }
+        };
+
+        let mut rec = HitRecord {
+            p: r.at(t),
+            t,
+            u,
+            v,
+            material: self.material.clone(),
+            color: point.albedo(),
+            ..Default::default()
+        };
+        rec.set_face_normal(r, &outward_normal);
+        Some(rec)
+    }
+}
+
+impl Hittable for PointCloud {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.bvh.hit(r, t_min, t_max, |range, t_max| {
+            let mut closest = None;
+            let mut closest_so_far = t_max;
+            for point in &self.points[range] {
+                if let Some(rec) = self.hit_point(point, r, t_min, closest_so_far) {
+                    closest_so_far = rec.t;
+                    closest = Some(rec);
+                }
+            }
+            closest
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bvh.bounds()
+    }
+}
+
+/// Loads points from a `.ply` or `.xyz` file, chosen by extension
+pub fn load_points<P: AsRef<Path>>(path: P, default_radius: f64) -> io::Result<Vec<CloudPoint>> {
+    let path = path.as_ref();
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("ply") => points_from_ply(&ply::load(path)?, default_radius),
+        Some("xyz") => read_xyz(BufReader::new(File::open(path)?), default_radius),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown point cloud format: {}", path.display()),
+        )),
+    }
+}
+
+/// Points from the `vertex` element, with optional `red`/`green`/`blue`
+/// colors and `radius`
+pub fn points_from_ply(ply: &ply::PlyFile, default_radius: f64) -> io::Result<Vec<CloudPoint>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let vertex = ply
+        .element("vertex")
+        .ok_or_else(|| invalid("PLY has no vertex element"))?;
+    let coordinate = |name: &str| {
+        vertex
+            .scalar(name)
+            .ok_or_else(|| invalid(&format!("PLY vertex has no '{}'", name)))
+    };
+    let (xs, ys, zs) = (coordinate("x")?, coordinate("y")?, coordinate("z")?);
+
+    let channel = |names: &[&str]| {
+        let scale = names
+            .iter()
+            .find_map(|name| vertex.property(name))
+            .map(|p| p.kind.color_scale());
+        vertex.any_scalar(names).zip(scale)
+    };
+    let colors = [
+        channel(&["red", "r", "diffuse_red"]),
+        channel(&["green", "g", "diffuse_green"]),
+        channel(&["blue", "b", "diffuse_blue"]),
+    ];
+    let radii = vertex.scalar("radius");
+
+    Ok((0..vertex.count)
+        .map(|i| {
+            let c = colors.map(|channel| channel.map_or(1.0, |(values, scale)| values[i] / scale));
+            CloudPoint::new(
+                Point3::new(xs[i], ys[i], zs[i]),
+                radii.map_or(default_radius, |r| r[i]),
+                Color::new(c[0], c[1], c[2]),
+            )
+        })
+        .collect())
+}
+
+/// Reads whitespace separated `x y z [r g b [radius]]` lines. Colors are
+/// either in 0..1 or, if any channel of a point is above 1, in 0..255.
+pub fn read_xyz<R: BufRead>(reader: R, default_radius: f64) -> io::Result<Vec<CloudPoint>> {
+    let mut points = Vec::new();
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let invalid = |message: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {}", number + 1, message),
+            )
+        };
+        let values = line
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|token| !token.is_empty())
+            .map(|token| token.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid("expected numbers"))?;
+        if !matches!(values.len(), 3 | 6 | 7) {
+            return Err(invalid("expected x y z [r g b [radius]]"));
+        }
+
+        let mut color = Color::new(1.0, 1.0, 1.0);
+        if values.len() >= 6 {
+            color = Color::new(values[3], values[4], values[5]);
+            if color.max_component() > 1.0 {
+                color /= 255.0;
+            }
+        }
+        let radius = values.get(6).copied().unwrap_or(default_radius);
+        points.push(CloudPoint::new(
+            Point3::new(values[0], values[1], values[2]),
+            radius,
+            color,
+        ));
+    }
+
+    Ok(points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_points(n: usize) -> Vec<CloudPoint> {
+        (0..n)
+            .map(|_| {
+                CloudPoint::new(
+                    Vec3::random(-5.0..5.0),
+                    random_double(0.05..0.3),
+                    Vec3::random(0.0..1.0),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bvh_matches_brute_force_test() {
+        for shape in [PointShape::Disk, PointShape::Sphere] {
+            let cloud = PointCloud::new(random_points(2000), shape);
+            for _ in 0..200 {
+                let r = Ray::new(Vec3::random(-8.0..8.0), Vec3::random(-1.0..1.0));
+                let expected = cloud
+                    .points()
+                    .iter()
+                    .filter_map(|p| cloud.hit_point(p, &r, 0.001, f64::INFINITY))
+                    .map(|rec| rec.t)
+                    .min_by(f64::total_cmp);
+                let found = cloud.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t);
+                assert_eq!(found, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn disk_faces_ray_test() {
+        let point = CloudPoint::new(Point3::new(0.0, 0.0, -2.0), 0.5, Color::new(1.0, 0.0, 0.0));
+        let cloud = PointCloud::new(vec![point], PointShape::Disk);
+
+        let r = Ray::new(Point3::new(0.4, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = cloud.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 2.0);
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+        assert!(rec.front_face);
+        assert_eq!(rec.color, Color::new(1.0, 0.0, 0.0));
+        let (attenuation, _) = rec.material.scatter(&r, &rec).unwrap();
+        assert_eq!(attenuation, Color::new(1.0, 0.0, 0.0));
+
+        let r = Ray::new(Point3::new(0.6, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(cloud.hit(&r, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn xyz_test() {
+        let text = "# scan\n0 0 0\n1 2 3 255 0 0\n1,2,3,0.5,0.5,0.5,0.2\n";
+        let points = read_xyz(text.as_bytes(), 0.1).unwrap();
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[0].color, [255, 255, 255]);
+        assert_eq!(points[1].color, [255, 0, 0]);
+        assert_eq!(points[1].center(), Point3::new(1.0, 2.0, 3.0));
+        assert_eq!(points[2].radius, 0.2);
+        assert!(read_xyz("1 2\n".as_bytes(), 0.1).is_err());
+    }
+
+    #[test]
+    fn ply_colors_test() {
+        let text = "ply\nformat ascii 1.0\nelement vertex 1\n\
+            property double x\nproperty double y\nproperty double z\n\
+            property uchar red\nproperty uchar green\nproperty uchar blue\n\
+            property float radius\nend_header\n1 2 3 255 0 51 0.25\n";
+        let points = points_from_ply(&ply::read(text.as_bytes()).unwrap(), 0.1).unwrap();
+        assert_eq!(points[0].color, [255, 0, 51]);
+        assert_eq!(points[0].radius, 0.25);
+    }
+}
@@ -0,0 +1,175 @@
+//! Closed-form real roots of low degree polynomials, after Jochen Schwarze's
+//! "Cubic and Quartic Roots" in Graphics Gems I. Coefficients are given from
+//! the highest degree down and roots are returned in ascending order.
+
+const EPSILON: f64 = 1e-9;
+
+fn is_zero(x: f64) -> bool {
+    x.abs() < EPSILON
+}
+
+fn sorted(mut roots: Vec<f64>) -> Vec<f64> {
+    roots.sort_by(|a, b| a.total_cmp(b));
+    roots
+}
+
+/// Roots of `a x^2 + b x + c`, also handling the linear case `a == 0`
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a == 0.0 {
+        return if b == 0.0 { vec![] } else { vec![-c / b] };
+    }
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return vec![];
+    }
+
+    // Avoid cancellation between -b and the square root
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    if q == 0.0 {
+        return vec![0.0];
+    }
+    sorted(vec![q / a, c / q])
+}
+
+/// Roots of `a x^3 + b x^2 + c x + d`
+pub fn solve_cubic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    if a == 0.0 {
+        return solve_quadratic(b, c, d);
+    }
+
+    // Normal form x^3 + A x^2 + B x + C, then substitute x = y - A/3 to
+    // eliminate the quadratic term: y^3 + 3p y + 2q = 0
+    let (a2, a1, a0) = (b / a, c / a, d / a);
+    let sq_a = a2 * a2;
+    let p = (-sq_a / 3.0 + a1) / 3.0;
+    let q = (2.0 / 27.0 * a2 * sq_a - a2 * a1 / 3.0 + a0) / 2.0;
+    let cb_p = p * p * p;
+    let discriminant = q * q + cb_p;
+
+    let roots = if is_zero(discriminant) {
+        if is_zero(q) {
+            vec![0.0]
+        } else {
+            let u = (-q).cbrt();
+            vec![2.0 * u, -u]
+        }
+    } else if discriminant < 0.0 {
+        // Casus irreducibilis: three real roots
+        let phi = (-q / (-cb_p).sqrt()).clamp(-1.0, 1.0).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        let third = std::f64::consts::PI / 3.0;
+        vec![
+            t * phi.cos(),
+            -t * (phi + third).cos(),
+            -t * (phi - third).cos(),
+        ]
+    } else {
+        let sqrt_d = discriminant.sqrt();
+        vec![(sqrt_d - q).cbrt() - (sqrt_d + q).cbrt()]
+    };
+
+    sorted(roots.into_iter().map(|y| y - a2 / 3.0).collect())
+}
+
+/// Roots of `a x^4 + b x^3 + c x^2 + d x + e` using Ferrari's method. The
+/// roots are refined with Newton steps on the original polynomial.
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> Vec<f64> {
+    if a == 0.0 {
+        return solve_cubic(b, c, d, e);
+    }
+
+    // Normal form, then substitute x = y - A/4: y^4 + p y^2 + q y + r = 0
+    let (a3, a2, a1, a0) = (b / a, c / a, d / a, e / a);
+    let sq_a = a3 * a3;
+    let p = -3.0 / 8.0 * sq_a + a2;
+    let q = sq_a * a3 / 8.0 - a3 * a2 / 2.0 + a1;
+    let r = -3.0 / 256.0 * sq_a * sq_a + sq_a * a2 / 16.0 - a3 * a1 / 4.0 + a0;
+
+    let mut roots = if is_zero(r) {
+        // y (y^3 + p y + q) = 0
+        let mut roots = solve_cubic(1.0, 0.0, p, q);
+        roots.push(0.0);
+        roots
+    } else {
+        // One root of the resolvent cubic splits the quartic into two
+        // quadratics
+        let z = solve_cubic(1.0, -p / 2.0, -r, r * p / 2.0 - q * q / 8.0)[0];
+
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+        let u = if is_zero(u) {
+            0.0
+        } else if u > 0.0 {
+            u.sqrt()
+        } else {
+            return vec![];
+        };
+        let v = if is_zero(v) {
+            0.0
+        } else if v > 0.0 {
+            v.sqrt()
+        } else {
+            return vec![];
+        };
+        let v = if q < 0.0 { -v } else { v };
+
+        let mut roots = solve_quadratic(1.0, v, z - u);
+        roots.extend(solve_quadratic(1.0, -v, z + u));
+        roots
+    };
+
+    let poly = |x: f64| (((a3 + x) * x + a2) * x + a1) * x + a0;
+    let derivative = |x: f64| ((4.0 * x + 3.0 * a3) * x + 2.0 * a2) * x + a1;
+    for root in roots.iter_mut() {
+        let mut x = *root - a3 / 4.0;
+        for _ in 0..2 {
+            let slope = derivative(x);
+            if slope != 0.0 {
+                x -= poly(x) / slope;
+            }
+        }
+        *root = x;
+    }
+
+    sorted(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_roots(actual: Vec<f64>, expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-7, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn quadratic_test() {
+        assert_roots(solve_quadratic(1.0, -3.0, 2.0), &[1.0, 2.0]);
+        assert_roots(solve_quadratic(1.0, 0.0, 1.0), &[]);
+        assert_roots(solve_quadratic(0.0, 2.0, -4.0), &[2.0]);
+    }
+
+    #[test]
+    fn cubic_test() {
+        // (x - 1)(x + 2)(x - 3)
+        assert_roots(solve_cubic(1.0, -2.0, -5.0, 6.0), &[-2.0, 1.0, 3.0]);
+        // (x - 2)(x^2 + 1)
+        assert_roots(solve_cubic(2.0, -4.0, 2.0, -4.0), &[2.0]);
+    }
+
+    #[test]
+    fn quartic_test() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        assert_roots(
+            solve_quartic(1.0, -10.0, 35.0, -50.0, 24.0),
+            &[1.0, 2.0, 3.0, 4.0],
+        );
+        // (x^2 - 4)(x^2 + 1)
+        assert_roots(solve_quartic(1.0, 0.0, -3.0, 0.0, -4.0), &[-2.0, 2.0]);
+        assert_roots(solve_quartic(1.0, 0.0, 1.0, 0.0, 1.0), &[]);
+    }
+}
@@ -0,0 +1,215 @@
+use std::sync::Arc;
+
+use crate::aabb::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::ray::*;
+use crate::vec3::*;
+
+/// A planar parallelogram spanned by two edge vectors `u` and `v` from the
+/// corner `q`.
+pub struct Quad {
+    q: Point3,
+    u: Vec3,
+    v: Vec3,
+    normal: Vec3,
+    d: f64,
+    w: Vec3,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+impl Quad {
+    pub fn new(q: Point3, u: Vec3, v: Vec3, material: Arc<dyn Material + Send + Sync>) -> Quad {
+        let n = cross(&u, &v);
+        let normal = unit_vector(n);
+        let d = dot(&normal, &q);
+        let w = n / dot(&n, &n);
+
+        Quad {
+            q,
+            u,
+            v,
+            normal,
+            d,
+            w,
+            material,
+        }
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let denom = dot(&self.normal, &r.direction());
+
+        // No hit if the ray is parallel to the plane
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+
+        let t = (self.d - dot(&self.normal, &r.origin())) / denom;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        // Express the hit point in the plane's (u, v) basis
+        let intersection = r.at(t);
+        let planar_hitpt = intersection - self.q;
+        let alpha = dot(&self.w, &cross(&planar_hitpt, &self.v));
+        let beta = dot(&self.w, &cross(&self.u, &planar_hitpt));
+
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+
+        let mut rec = HitRecord {
+            t,
+            p: intersection,
+            u: alpha,
+            v: beta,
+            material: self.material.clone(),
+            ..Default::default()
+        };
+        rec.set_face_normal(r, &self.normal);
+
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let diagonal1 = Aabb::new(self.q, self.q + self.u + self.v);
+        let diagonal2 = Aabb::new(self.q + self.u, self.q + self.v);
+        Some(surrounding_box(&diagonal1, &diagonal2).pad(0.0001))
+    }
+}
+
+/// An axis-aligned box made of six quads, given two opposite corners.
+pub struct Cuboid {
+    sides: HittableList,
+}
+
+impl Cuboid {
+    pub fn new(a: Point3, b: Point3, material: Arc<dyn Material + Send + Sync>) -> Cuboid {
+        let min = Point3::new(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z()));
+        let max = Point3::new(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z()));
+
+        let dx = Vec3::new(max.x() - min.x(), 0.0, 0.0);
+        let dy = Vec3::new(0.0, max.y() - min.y(), 0.0);
+        let dz = Vec3::new(0.0, 0.0, max.z() - min.z());
+
+        let mut sides = HittableList::default();
+        // front
+        sides.add(Arc::new(Quad::new(
+            Point3::new(min.x(), min.y(), max.z()),
+            dx,
+            dy,
+            material.clone(),
+        )));
+        // right
+        sides.add(Arc::new(Quad::new(
+            Point3::new(max.x(), min.y(), max.z()),
+            -1.0 * dz,
+            dy,
+            material.clone(),
+        )));
+        // back
+        sides.add(Arc::new(Quad::new(
+            Point3::new(max.x(), min.y(), min.z()),
+            -1.0 * dx,
+            dy,
+            material.clone(),
+        )));
+        // left
+        sides.add(Arc::new(Quad::new(
+            Point3::new(min.x(), min.y(), min.z()),
+            dz,
+            dy,
+            material.clone(),
+        )));
+        // top
+        sides.add(Arc::new(Quad::new(
+            Point3::new(min.x(), max.y(), max.z()),
+            dx,
+            -1.0 * dz,
+            material.clone(),
+        )));
+        // bottom
+        sides.add(Arc::new(Quad::new(
+            Point3::new(min.x(), min.y(), min.z()),
+            dx,
+            dz,
+            material,
+        )));
+
+        Cuboid { sides }
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.sides.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.sides.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn material() -> Arc<dyn Material + Send + Sync> {
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    #[test]
+    fn quad_hit_test() {
+        let quad = Quad::new(
+            Point3::new(-1.0, -1.0, -2.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+            material(),
+        );
+        let r = Ray::new(Point3::new(0.5, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let rec = quad.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 2.0);
+        assert_eq!((rec.u, rec.v), (0.75, 0.5));
+        assert!(rec.front_face);
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn quad_miss_test() {
+        let quad = Quad::new(
+            Point3::new(-1.0, -1.0, -2.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+            material(),
+        );
+        let outside = Ray::new(Point3::new(1.5, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let parallel = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+
+        assert!(quad.hit(&outside, 0.001, f64::INFINITY).is_none());
+        assert!(quad.hit(&parallel, 0.001, f64::INFINITY).is_none());
+        assert!(quad.hit(&r, 0.001, 1.0).is_none());
+    }
+
+    #[test]
+    fn cuboid_hit_test() {
+        let cuboid = Cuboid::new(
+            Point3::new(1.0, 1.0, 1.0),
+            Point3::new(-1.0, -1.0, -1.0),
+            material(),
+        );
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let rec = cuboid.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 4.0);
+        assert_eq!(rec.normal, Vec3::new(0.0, 0.0, 1.0));
+
+        let rec = cuboid.hit(&r, 4.5, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 6.0);
+        assert!(!rec.front_face);
+    }
+}
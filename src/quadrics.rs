@@ -0,0 +1,530 @@
+//! Analytic surfaces besides the sphere. Cylinders, cones, paraboloids and
+//! tori are built around a vertical axis through `base` (or `center`); use
+//! an `Instance` to orient them differently.
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use crate::aabb::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::polynomial::*;
+use crate::ray::*;
+use crate::vec3::*;
+
+// Angle around the y axis, mapped to [0, 1)
+fn azimuth_u(p: &Vec3) -> f64 {
+    let phi = p.z().atan2(p.x());
+    if phi < 0.0 {
+        (phi + 2.0 * PI) / (2.0 * PI)
+    } else {
+        phi / (2.0 * PI)
+    }
+}
+
+fn make_record(
+    r: &Ray,
+    t: f64,
+    outward_normal: &Vec3,
+    (u, v): (f64, f64),
+    material: &Arc<dyn Material + Send + Sync>,
+) -> HitRecord {
+    let mut rec = HitRecord {
+        t,
+        p: r.at(t),
+        u,
+        v,
+        material: material.clone(),
+        ..Default::default()
+    };
+    rec.set_face_normal(r, outward_normal);
+    rec
+}
+
+// Hit with the horizontal disk of `radius` at height `y` of the local frame,
+// as used for caps. `o` and `d` are the local ray origin and direction.
+fn hit_cap(o: &Vec3, d: &Vec3, y: f64, radius: f64, t_min: f64, t_max: f64) -> Option<f64> {
+    let t = (y - o.y()) / d.y();
+    if !t.is_finite() || t < t_min || t > t_max {
+        return None;
+    }
+    let p = *o + t * *d;
+    if p.x() * p.x() + p.z() * p.z() > radius * radius {
+        return None;
+    }
+    Some(t)
+}
+
+fn cap_uv(p: &Vec3, radius: f64) -> (f64, f64) {
+    (p.x() / (2.0 * radius) + 0.5, p.z() / (2.0 * radius) + 0.5)
+}
+
+fn vertical_bounds(base: &Point3, radius: f64, height: f64) -> Aabb {
+    Aabb::new(
+        *base - Vec3::new(radius, 0.0, radius),
+        *base + Vec3::new(radius, height, radius),
+    )
+}
+
+/// Nearest of the candidate hits `(t, outward normal, uv)`
+fn nearest(
+    candidates: impl Iterator<Item = (f64, Vec3, (f64, f64))>,
+) -> Option<(f64, Vec3, (f64, f64))> {
+    candidates.min_by(|a, b| a.0.total_cmp(&b.0))
+}
+
+/// Cylinder of `radius` from `base` up to `base + height` along y
+pub struct Cylinder {
+    base: Point3,
+    radius: f64,
+    height: f64,
+    capped: bool,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+impl Cylinder {
+    pub fn new(
+        base: Point3,
+        radius: f64,
+        height: f64,
+        capped: bool,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> Cylinder {
+        Cylinder {
+            base,
+            radius,
+            height,
+            capped,
+            material,
+        }
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let o = r.origin() - self.base;
+        let d = r.direction();
+
+        let a = d.x() * d.x() + d.z() * d.z();
+        let b = 2.0 * (o.x() * d.x() + o.z() * d.z());
+        let c = o.x() * o.x() + o.z() * o.z() - self.radius * self.radius;
+        let side = solve_quadratic(a, b, c).into_iter().filter_map(|t| {
+            let p = o + t * d;
+            (t_min <= t && t <= t_max && (0.0..=self.height).contains(&p.y())).then(|| {
+                let normal = Vec3::new(p.x(), 0.0, p.z()) / self.radius;
+                (t, normal, (azimuth_u(&p), p.y() / self.height))
+            })
+        });
+
+        let caps = [(0.0, -1.0), (self.height, 1.0)]
+            .into_iter()
+            .filter(|_| self.capped)
+            .filter_map(|(y, sign)| {
+                let t = hit_cap(&o, &d, y, self.radius, t_min, t_max)?;
+                Some((
+                    t,
+                    Vec3::new(0.0, sign, 0.0),
+                    cap_uv(&(o + t * d), self.radius),
+                ))
+            });
+
+        let (t, normal, uv) = nearest(side.chain(caps))?;
+        Some(make_record(r, t, &normal, uv, &self.material))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(vertical_bounds(&self.base, self.radius, self.height))
+    }
+}
+
+/// Cone with a base of `radius` at `base` and its apex `height` above it
+pub struct Cone {
+    base: Point3,
+    radius: f64,
+    height: f64,
+    capped: bool,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+impl Cone {
+    pub fn new(
+        base: Point3,
+        radius: f64,
+        height: f64,
+        capped: bool,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> Cone {
+        Cone {
+            base,
+            radius,
+            height,
+            capped,
+            material,
+        }
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let o = r.origin() - self.base;
+        let d = r.direction();
+
+        // x^2 + z^2 = k^2 (h - y)^2
+        let k2 = (self.radius / self.height).powi(2);
+        let oy = self.height - o.y();
+        let a = d.x() * d.x() + d.z() * d.z() - k2 * d.y() * d.y();
+        let b = 2.0 * (o.x() * d.x() + o.z() * d.z() + k2 * oy * d.y());
+        let c = o.x() * o.x() + o.z() * o.z() - k2 * oy * oy;
+        let side = solve_quadratic(a, b, c).into_iter().filter_map(|t| {
+            let p = o + t * d;
+            (t_min <= t && t <= t_max && (0.0..=self.height).contains(&p.y())).then(|| {
+                let normal = unit_vector(Vec3::new(p.x(), k2 * (self.height - p.y()), p.z()));
+                (t, normal, (azimuth_u(&p), p.y() / self.height))
+            })
+        });
+
+        let cap = self
+            .capped
+            .then(|| hit_cap(&o, &d, 0.0, self.radius, t_min, t_max))
+            .flatten()
+            .map(|t| {
+                (
+                    t,
+                    Vec3::new(0.0, -1.0, 0.0),
+                    cap_uv(&(o + t * d), self.radius),
+                )
+            });
+
+        let (t, normal, uv) = nearest(side.chain(cap))?;
+        Some(make_record(r, t, &normal, uv, &self.material))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(vertical_bounds(&self.base, self.radius, self.height))
+    }
+}
+
+/// Bowl `y = height (x^2 + z^2) / radius^2` with its vertex at `base`,
+/// reaching `radius` at the top
+pub struct Paraboloid {
+    base: Point3,
+    radius: f64,
+    height: f64,
+    capped: bool,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+impl Paraboloid {
+    pub fn new(
+        base: Point3,
+        radius: f64,
+        height: f64,
+        capped: bool,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> Paraboloid {
+        Paraboloid {
+            base,
+            radius,
+            height,
+            capped,
+            material,
+        }
+    }
+}
+
+impl Hittable for Paraboloid {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let o = r.origin() - self.base;
+        let d = r.direction();
+
+        // h (x^2 + z^2) - r^2 y = 0
+        let (h, r2) = (self.height, self.radius * self.radius);
+        let a = h * (d.x() * d.x() + d.z() * d.z());
+        let b = 2.0 * h * (o.x() * d.x() + o.z() * d.z()) - r2 * d.y();
+        let c = h * (o.x() * o.x() + o.z() * o.z()) - r2 * o.y();
+        let side = solve_quadratic(a, b, c).into_iter().filter_map(|t| {
+            let p = o + t * d;
+            (t_min <= t && t <= t_max && p.y() <= self.height).then(|| {
+                let normal = unit_vector(Vec3::new(2.0 * h * p.x(), -r2, 2.0 * h * p.z()));
+                (t, normal, (azimuth_u(&p), p.y() / self.height))
+            })
+        });
+
+        let cap = self
+            .capped
+            .then(|| hit_cap(&o, &d, self.height, self.radius, t_min, t_max))
+            .flatten()
+            .map(|t| {
+                (
+                    t,
+                    Vec3::new(0.0, 1.0, 0.0),
+                    cap_uv(&(o + t * d), self.radius),
+                )
+            });
+
+        let (t, normal, uv) = nearest(side.chain(cap))?;
+        Some(make_record(r, t, &normal, uv, &self.material))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(vertical_bounds(&self.base, self.radius, self.height))
+    }
+}
+
+/// Flat disk of `radius` around `center`, facing `normal`
+pub struct Disk {
+    center: Point3,
+    normal: Vec3,
+    radius: f64,
+    tangent: Vec3,
+    bitangent: Vec3,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+impl Disk {
+    pub fn new(
+        center: Point3,
+        normal: Vec3,
+        radius: f64,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> Disk {
+        let normal = unit_vector(normal);
+        let a = if normal.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let tangent = unit_vector(cross(&a, &normal));
+        let bitangent = cross(&normal, &tangent);
+
+        Disk {
+            center,
+            normal,
+            radius,
+            tangent,
+            bitangent,
+            material,
+        }
+    }
+}
+
+impl Hittable for Disk {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let denom = dot(&self.normal, &r.direction());
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+
+        let t = dot(&self.normal, &(self.center - r.origin())) / denom;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        let offset = r.at(t) - self.center;
+        let dist = offset.length();
+        if dist > self.radius {
+            return None;
+        }
+
+        // Polar coordinates: u is the angle, v the distance from the center
+        let phi = dot(&offset, &self.bitangent).atan2(dot(&offset, &self.tangent));
+        let u = if phi < 0.0 { phi + 2.0 * PI } else { phi } / (2.0 * PI);
+        let v = dist / self.radius;
+        Some(make_record(r, t, &self.normal, (u, v), &self.material))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let n = self.normal;
+        let extent = self.radius
+            * Vec3::new(
+                (1.0 - n.x() * n.x()).max(0.0).sqrt(),
+                (1.0 - n.y() * n.y()).max(0.0).sqrt(),
+                (1.0 - n.z() * n.z()).max(0.0).sqrt(),
+            );
+        Some(Aabb::new(self.center - extent, self.center + extent).pad(0.0001))
+    }
+}
+
+/// Torus around the vertical axis through `center`, with the tube of
+/// `minor_radius` at `major_radius` from the axis
+pub struct Torus {
+    center: Point3,
+    major_radius: f64,
+    minor_radius: f64,
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+impl Torus {
+    pub fn new(
+        center: Point3,
+        major_radius: f64,
+        minor_radius: f64,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> Torus {
+        Torus {
+            center,
+            major_radius,
+            minor_radius,
+            material,
+        }
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        // Solve with a unit direction for better conditioning
+        let length = r.direction().length();
+        let o = r.origin() - self.center;
+        let d = r.direction() / length;
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + z^2)
+        let r2 = self.major_radius * self.major_radius;
+        let n = dot(&o, &d);
+        let p = o.length_squared() + r2 - self.minor_radius * self.minor_radius;
+        let roots = solve_quartic(
+            1.0,
+            4.0 * n,
+            4.0 * n * n + 2.0 * p - 4.0 * r2 * (d.x() * d.x() + d.z() * d.z()),
+            4.0 * n * p - 8.0 * r2 * (o.x() * d.x() + o.z() * d.z()),
+            p * p - 4.0 * r2 * (o.x() * o.x() + o.z() * o.z()),
+        );
+
+        let t = roots
+            .into_iter()
+            .map(|t| t / length)
+            .find(|&t| t_min <= t && t <= t_max)?;
+
+        let q = o + t * r.direction();
+        let ring = (q.x() * q.x() + q.z() * q.z()).sqrt();
+        let tube_center = self.major_radius / ring * Vec3::new(q.x(), 0.0, q.z());
+        let normal = unit_vector(q - tube_center);
+
+        let v = q.y().atan2(ring - self.major_radius);
+        let v = if v < 0.0 { v + 2.0 * PI } else { v } / (2.0 * PI);
+        Some(make_record(
+            r,
+            t,
+            &normal,
+            (azimuth_u(&q), v),
+            &self.material,
+        ))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let extent = self.major_radius + self.minor_radius;
+        let half = Vec3::new(extent, self.minor_radius, extent);
+        Some(Aabb::new(self.center - half, self.center + half))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn material() -> Arc<dyn Material + Send + Sync> {
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn cylinder_test() {
+        let cylinder = Cylinder::new(Point3::new(0.0, 0.0, 0.0), 1.0, 2.0, true, material());
+        let side = Ray::new(Point3::new(5.0, 1.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        let rec = cylinder.hit(&side, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 4.0);
+        assert_eq!(rec.normal, Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!((rec.u, rec.v), (0.0, 0.5));
+
+        let top = Ray::new(Point3::new(0.5, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let rec = cylinder.hit(&top, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 3.0);
+        assert_eq!(rec.normal, Vec3::new(0.0, 1.0, 0.0));
+
+        let open = Cylinder::new(Point3::new(0.0, 0.0, 0.0), 1.0, 2.0, false, material());
+        let rec = open.hit(&top, 0.001, f64::INFINITY);
+        assert!(rec.is_none());
+
+        // From the inside the normal faces the ray
+        let inside = Ray::new(Point3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0));
+        let rec = cylinder.hit(&inside, 0.001, f64::INFINITY).unwrap();
+        assert!(!rec.front_face);
+        assert_eq!(rec.normal, Vec3::new(-1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn cone_test() {
+        let cone = Cone::new(Point3::new(0.0, 0.0, 0.0), 1.0, 1.0, true, material());
+        let side = Ray::new(Point3::new(5.0, 0.5, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        let rec = cone.hit(&side, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 4.5);
+        assert_near(rec.normal, unit_vector(Vec3::new(1.0, 1.0, 0.0)));
+
+        let bottom = Ray::new(Point3::new(0.2, -3.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+        let rec = cone.hit(&bottom, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 3.0);
+        assert_eq!(rec.normal, Vec3::new(0.0, -1.0, 0.0));
+
+        // Above the apex the mirrored nappe is ignored
+        let above = Ray::new(Point3::new(5.0, 1.5, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        assert!(cone.hit(&above, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn paraboloid_test() {
+        let bowl = Paraboloid::new(Point3::new(0.0, 0.0, 0.0), 1.0, 1.0, false, material());
+        let down = Ray::new(Point3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let rec = bowl.hit(&down, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 5.0);
+        // Looking into the bowl we see its inside
+        assert!(!rec.front_face);
+        assert_eq!(rec.normal, Vec3::new(0.0, 1.0, 0.0));
+
+        let side = Ray::new(Point3::new(5.0, 0.25, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        let rec = bowl.hit(&side, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 4.5);
+        assert!(rec.front_face);
+    }
+
+    #[test]
+    fn disk_test() {
+        let disk = Disk::new(
+            Point3::new(0.0, 0.0, -2.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            1.0,
+            material(),
+        );
+        let r = Ray::new(Point3::new(0.5, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = disk.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 2.0);
+        assert_eq!(rec.v, 0.5);
+        assert!(rec.front_face);
+
+        let outside = Ray::new(Point3::new(1.5, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(disk.hit(&outside, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn torus_test() {
+        let torus = Torus::new(Point3::new(0.0, 0.0, 0.0), 2.0, 0.5, material());
+        let r = Ray::new(Point3::new(5.0, 0.0, 0.0), Vec3::new(-2.0, 0.0, 0.0));
+        let rec = torus.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 1.25).abs() < 1e-9);
+        assert_near(rec.normal, Vec3::new(1.0, 0.0, 0.0));
+
+        // Through the hole along the axis
+        let axis = Ray::new(Point3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        assert!(torus.hit(&axis, 0.001, f64::INFINITY).is_none());
+
+        // The ray crosses the tube twice on each side
+        let spans: Vec<_> = torus
+            .intervals(&r)
+            .iter()
+            .map(|i| (i.enter.t, i.exit.t))
+            .collect();
+        assert_eq!(spans.len(), 2);
+        assert!((spans[1].1 - 3.75).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,241 @@
+use image::{ImageBuffer, RgbImage};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Mutex;
+
+use crate::camera::Camera;
+use crate::hittable::*;
+use crate::integrator::Integrator;
+use crate::sampler::{random_seed, with_sampler, SeededSampler};
+use crate::vec3::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderSettings {
+    pub image_width: u32,
+    pub image_height: u32,
+    pub samples_per_pixel: usize,
+}
+
+impl RenderSettings {
+    pub fn aspect_ratio(&self) -> f64 {
+        self.image_width as f64 / self.image_height as f64
+    }
+
+    /// The pixel whose samples use the film coordinates `(s, t)`
+    pub fn pixel(&self, s: f64, t: f64) -> Option<(u32, u32)> {
+        let (width, height) = (self.image_width as f64, self.image_height as f64);
+        let i = (s * (width - 1.0)).floor();
+        let row = (t * (height - 1.0)).floor();
+        if i < 0.0 || i >= width || row < 1.0 || row > height {
+            return None;
+        }
+        Some((i as u32, (height - row) as u32))
+    }
+
+    /// Area of film coordinates covered by the samples of all pixels. Each
+    /// pixel spans 1 / (width - 1) by 1 / (height - 1) of them.
+    pub fn film_extent(&self) -> f64 {
+        let (width, height) = (self.image_width as f64, self.image_height as f64);
+        width / (width - 1.0) * height / (height - 1.0)
+    }
+}
+
+/// Light that integrators add to arbitrary pixels while rendering, such as
+/// light paths reaching the camera. Each sample of every pixel contributes
+/// to it, so it is divided by the samples per pixel like the pixels are.
+pub struct SplatBuffer {
+    settings: RenderSettings,
+    pixels: Vec<Mutex<Color>>,
+}
+
+impl SplatBuffer {
+    pub fn new(settings: &RenderSettings) -> SplatBuffer {
+        let count = settings.image_width as usize * settings.image_height as usize;
+        SplatBuffer {
+            settings: *settings,
+            pixels: (0..count).map(|_| Mutex::new(Color::default())).collect(),
+        }
+    }
+
+    /// Adds to the pixel at film coordinates `(s, t)`, if there is one
+    pub fn add(&self, s: f64, t: f64, color: Color) {
+        if let Some((i, j)) = self.settings.pixel(s, t) {
+            *self.pixels[self.index(i, j)].lock().unwrap() += color;
+        }
+    }
+
+    pub fn get(&self, i: u32, j: u32) -> Color {
+        *self.pixels[self.index(i, j)].lock().unwrap()
+    }
+
+    fn index(&self, i: u32, j: u32) -> usize {
+        i as usize + self.settings.image_width as usize * j as usize
+    }
+}
+
+fn clamp(x: f64, min: f64, max: f64) -> f64 {
+    if x < min {
+        min
+    } else if x > max {
+        max
+    } else {
+        x
+    }
+}
+
+pub fn create_pixel(pixel_color: &Color, samples_per_pixel: usize) -> image::Rgb<u8> {
+    let mut r = pixel_color.x();
+    let mut g = pixel_color.y();
+    let mut b = pixel_color.z();
+
+    // Divide the color by the number of samples and gamma-correct for gamma=2.0
+    let scale = 1.0 / samples_per_pixel as f64;
+    r = (r * scale).sqrt();
+    g = (g * scale).sqrt();
+    b = (b * scale).sqrt();
+
+    image::Rgb([
+        (256.0 * clamp(r, 0.0, 0.999)) as u8,
+        (256.0 * clamp(g, 0.0, 0.999)) as u8,
+        (256.0 * clamp(b, 0.0, 0.999)) as u8,
+    ])
+}
+
+/// Renders one image, splitting the rows into a band per CPU
+pub fn render(
+    world: &(dyn Hittable + Send + Sync),
+    cam: &Camera,
+    integrator: &dyn Integrator,
+    settings: &RenderSettings,
+) -> RgbImage {
+    let RenderSettings {
+        image_width,
+        image_height,
+        samples_per_pixel,
+    } = *settings;
+    let mut sums = vec![Color::default(); (image_width * image_height) as usize];
+    let splats = SplatBuffer::new(settings);
+
+    for_each_row(&mut sums, image_width, |j, row| {
+        for (i, pixel_color) in row.iter_mut().enumerate() {
+            for _s in 0..samples_per_pixel {
+                let u = (i as f64 + random_double(0.0..1.0)) / (image_width - 1) as f64;
+                let v = (image_height as f64 - j as f64 + random_double(0.0..1.0))
+                    / (image_height - 1) as f64;
+                if cam.has_chromatic_aberration() {
+                    for channel in 0..3 {
+                        let r = cam.get_channel_ray(u, v, channel);
+                        let mut mask = Color::default();
+                        mask.e[channel] = 1.0;
+                        pixel_color.e[channel] +=
+                            integrator.li_splat(&r, world, &splats, &mask).e[channel];
+                    }
+                } else {
+                    let r = cam.get_ray(u, v);
+                    let mask = Color::new(1.0, 1.0, 1.0);
+
+                    *pixel_color += integrator.li_splat(&r, world, &splats, &mask);
+                }
+            }
+        }
+    });
+
+    // Splats can land on any pixel, so pixels are only final once every
+    // band is done
+    ImageBuffer::from_fn(image_width, image_height, |i, j| {
+        let pixel_color = sums[(i + image_width * j) as usize] + splats.get(i, j);
+        create_pixel(&pixel_color, samples_per_pixel)
+    })
+}
+
+/// Runs `f` on every row of `pixels`, an image `width` pixels wide, with a
+/// band of rows per CPU. `f` gets the row's number from the top. Rows draw
+/// their random numbers from generators seeded on the calling thread, so
+/// seeding it repeats the result on any number of CPUs.
+pub fn for_each_row<T, F>(pixels: &mut [T], width: u32, f: F)
+where
+    T: Send,
+    F: Fn(u32, &mut [T]) + Sync,
+{
+    let width = width as usize;
+    if pixels.is_empty() || width == 0 {
+        return;
+    }
+    let height = pixels.len() / width;
+    let seeds: Vec<u64> = (0..height).map(|_| random_seed()).collect();
+
+    let rows_per_band = height / num_cpus::get() + 1;
+    crossbeam::scope(|spawner| {
+        for (band, band_pixels) in pixels.chunks_mut(rows_per_band * width).enumerate() {
+            let (f, seeds) = (&f, &seeds);
+            spawner.spawn(move |_| {
+                for (k, row) in band_pixels.chunks_mut(width).enumerate() {
+                    let j = band * rows_per_band + k;
+                    let sampler = Rc::new(RefCell::new(SeededSampler::new(seeds[j])));
+                    with_sampler(sampler, || f(j as u32, row));
+                }
+            });
+        }
+    })
+    .expect("failed to spawn threads");
+}
+
+/// Image of radiance given row by row from the top, such as from
+/// integrators that render whole images at once
+pub fn radiance_image(radiance: &[Color], settings: &RenderSettings) -> RgbImage {
+    ImageBuffer::from_fn(settings.image_width, settings.image_height, |i, j| {
+        create_pixel(&radiance[(i + settings.image_width * j) as usize], 1)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::ChromaticAberration;
+    use crate::ray::Ray;
+
+    // Sends a little light from every sample to the center pixel
+    struct CenterSplat;
+
+    impl Integrator for CenterSplat {
+        fn li(&self, _r: &Ray, _world: &dyn Hittable) -> Color {
+            Color::default()
+        }
+
+        fn li_splat(
+            &self,
+            _r: &Ray,
+            _world: &dyn Hittable,
+            splats: &SplatBuffer,
+            mask: &Color,
+        ) -> Color {
+            splats.add(0.6, 1.2, *mask * 0.01);
+            Color::default()
+        }
+    }
+
+    #[test]
+    fn chromatic_aberration_splat_test() {
+        let settings = RenderSettings {
+            image_width: 3,
+            image_height: 3,
+            samples_per_pixel: 1,
+        };
+        let camera = Camera::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            90.0,
+            1.0,
+            0.0,
+            1.0,
+        );
+        let world = HittableList::default();
+
+        let plain = render(&world, &camera, &CenterSplat, &settings);
+        let camera = camera.with_chromatic_aberration(ChromaticAberration::new(1.01, 0.99));
+        let aberrated = render(&world, &camera, &CenterSplat, &settings);
+        assert_eq!(plain.get_pixel(1, 1), aberrated.get_pixel(1, 1));
+        assert_eq!(plain.get_pixel(1, 1).0, [76; 3]);
+    }
+}
@@ -0,0 +1,120 @@
+//! Where the random numbers of path construction come from.
+//!
+//! Everything that builds paths draws through `random_double`, which uses
+//! the thread's generator unless a `Sampler` is installed with
+//! `with_sampler`. A path is then a function of the numbers its sampler
+//! hands out, its primary samples, and integrators such as Metropolis light
+//! transport can explore paths by changing those numbers.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::vec3::random_double;
+
+pub trait Sampler {
+    /// The next primary sample of the current path, in [0, 1)
+    fn next(&mut self) -> f64;
+}
+
+/// Independent uniform numbers, like drawing without a sampler
+pub struct RandomSampler;
+
+impl Sampler for RandomSampler {
+    fn next(&mut self) -> f64 {
+        rand::thread_rng().gen_range(0.0..1.0)
+    }
+}
+
+/// Independent uniform numbers that repeat from run to run
+pub struct SeededSampler {
+    rng: StdRng,
+}
+
+impl SeededSampler {
+    pub fn new(seed: u64) -> SeededSampler {
+        SeededSampler {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Sampler for SeededSampler {
+    fn next(&mut self) -> f64 {
+        self.rng.gen_range(0.0..1.0)
+    }
+}
+
+/// A seed drawn from this thread's numbers, for a `SeededSampler` on
+/// another thread that should repeat whenever this thread does
+pub fn random_seed() -> u64 {
+    (random_double(0.0..1.0) * (1u64 << 53) as f64) as u64
+}
+
+thread_local! {
+    static SAMPLER: RefCell<Option<Rc<RefCell<dyn Sampler>>>> = const { RefCell::new(None) };
+}
+
+// Puts the previous sampler back even if `f` panics
+struct Restore(Option<Rc<RefCell<dyn Sampler>>>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        SAMPLER.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Runs `f` with `random_double` on this thread drawing from `sampler`.
+/// The sampler must not call `random_double` itself.
+pub fn with_sampler<R>(sampler: Rc<RefCell<dyn Sampler>>, f: impl FnOnce() -> R) -> R {
+    let previous = SAMPLER.with(|current| current.borrow_mut().replace(sampler));
+    let _restore = Restore(previous);
+    f()
+}
+
+/// A sample from the installed sampler, if there is one
+pub(crate) fn next_sample() -> Option<f64> {
+    SAMPLER.with(|current| {
+        current
+            .borrow()
+            .as_ref()
+            .map(|sampler| sampler.borrow_mut().next())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Counter(f64);
+
+    impl Sampler for Counter {
+        fn next(&mut self) -> f64 {
+            self.0 += 0.25;
+            self.0
+        }
+    }
+
+    #[test]
+    fn with_sampler_test() {
+        let counter = Rc::new(RefCell::new(Counter(0.0)));
+        let drawn = with_sampler(counter.clone(), || {
+            [random_double(0.0..1.0), random_double(2.0..4.0)]
+        });
+        assert_eq!(drawn, [0.25, 3.0]);
+        assert_eq!(counter.borrow().0, 0.5);
+        assert!(next_sample().is_none());
+    }
+
+    #[test]
+    fn seeded_sampler_test() {
+        let draw = |seed| {
+            let sampler = Rc::new(RefCell::new(SeededSampler::new(seed)));
+            with_sampler(sampler, || [0; 4].map(|_| random_double(0.0..1.0)))
+        };
+        assert_eq!(draw(3), draw(3));
+        assert_ne!(draw(3), draw(4));
+    }
+}
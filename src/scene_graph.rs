@@ -0,0 +1,343 @@
+//! Hierarchy of named nodes with parent-relative transforms.
+//!
+//! Nodes carry geometry, point lights and cameras. `SceneGraph::flatten`
+//! bakes the hierarchy into instances under a BVH for rendering, so nodes can
+//! be looked up by name and changed between renders.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::bvh;
+use crate::camera::Camera;
+use crate::hittable::*;
+use crate::instance::Instance;
+use crate::material::DiffuseLight;
+use crate::sphere::Sphere;
+use crate::vec3::*;
+
+pub type NodeId = usize;
+
+/// Spherical light, rendered as an emissive sphere at the node's origin
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointLight {
+    pub emit: Color,
+    pub radius: f64,
+}
+
+/// Camera at the node's origin, looking down its -z axis with +y up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraSettings {
+    pub vfov: f64,
+    pub aspect_ratio: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
+}
+
+pub struct SceneNode {
+    name: String,
+    /// Relative to the parent node
+    pub transform: Mat4,
+    pub geometry: Vec<Arc<dyn Hittable + Send + Sync>>,
+    pub light: Option<PointLight>,
+    pub camera: Option<CameraSettings>,
+    /// Hidden nodes are left out of `flatten` together with their subtree
+    pub visible: bool,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+}
+
+impl SceneNode {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+}
+
+pub struct SceneGraph {
+    nodes: Vec<SceneNode>,
+    names: HashMap<String, NodeId>,
+}
+
+impl Default for SceneGraph {
+    fn default() -> Self {
+        SceneGraph::new()
+    }
+}
+
+impl SceneGraph {
+    /// A graph with just a root node named "root"
+    pub fn new() -> SceneGraph {
+        let mut graph = SceneGraph {
+            nodes: Vec::new(),
+            names: HashMap::new(),
+        };
+        graph.insert(None, "root", Mat4::identity());
+        graph
+    }
+
+    pub fn root(&self) -> NodeId {
+        0
+    }
+
+    /// Returns `None` if `name` is already taken. Panics if there is no
+    /// node `parent`.
+    pub fn add_node(&mut self, parent: NodeId, name: &str, transform: Mat4) -> Option<NodeId> {
+        assert!(parent < self.nodes.len(), "no such parent node");
+        if self.names.contains_key(name) {
+            return None;
+        }
+        let id = self.insert(Some(parent), name, transform);
+        self.nodes[parent].children.push(id);
+        Some(id)
+    }
+
+    fn insert(&mut self, parent: Option<NodeId>, name: &str, transform: Mat4) -> NodeId {
+        let id = self.nodes.len();
+        self.names.insert(name.to_string(), id);
+        self.nodes.push(SceneNode {
+            name: name.to_string(),
+            transform,
+            geometry: Vec::new(),
+            light: None,
+            camera: None,
+            visible: true,
+            parent,
+            children: Vec::new(),
+        });
+        id
+    }
+
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.names.get(name).copied()
+    }
+
+    pub fn node(&self, id: NodeId) -> &SceneNode {
+        &self.nodes[id]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut SceneNode {
+        &mut self.nodes[id]
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&SceneNode> {
+        self.find(name).map(|id| &self.nodes[id])
+    }
+
+    pub fn by_name_mut(&mut self, name: &str) -> Option<&mut SceneNode> {
+        self.find(name).map(move |id| &mut self.nodes[id])
+    }
+
+    /// Returns false if `new_name` is already taken
+    pub fn rename(&mut self, id: NodeId, new_name: &str) -> bool {
+        if self.names.contains_key(new_name) {
+            return false;
+        }
+        self.names.remove(&self.nodes[id].name);
+        self.names.insert(new_name.to_string(), id);
+        self.nodes[id].name = new_name.to_string();
+        true
+    }
+
+    /// Moves a node and its subtree under a new parent. Returns false,
+    /// changing nothing, if that would create a cycle.
+    pub fn reparent(&mut self, id: NodeId, new_parent: NodeId) -> bool {
+        let mut ancestor = Some(new_parent);
+        while let Some(a) = ancestor {
+            if a == id {
+                return false;
+            }
+            ancestor = self.nodes[a].parent;
+        }
+
+        if let Some(old) = self.nodes[id].parent {
+            self.nodes[old].children.retain(|&child| child != id);
+        }
+        self.nodes[id].parent = Some(new_parent);
+        self.nodes[new_parent].children.push(id);
+        true
+    }
+
+    /// Transform from the node's space to world space
+    pub fn world_transform(&self, id: NodeId) -> Mat4 {
+        let node = &self.nodes[id];
+        match node.parent {
+            Some(parent) => self.world_transform(parent) * node.transform,
+            None => node.transform,
+        }
+    }
+
+    /// The camera of the named node, placed by its world transform
+    pub fn camera(&self, name: &str) -> Option<Camera> {
+        let id = self.find(name)?;
+        let settings = self.nodes[id].camera?;
+        let world = self.world_transform(id);
+        let origin = world.transform_point(&Point3::default());
+        Some(Camera::new(
+            origin,
+            origin + world.transform_vector(&Vec3::new(0.0, 0.0, -1.0)),
+            world.transform_vector(&Vec3::new(0.0, 1.0, 0.0)),
+            settings.vfov,
+            settings.aspect_ratio,
+            settings.aperture,
+            settings.focus_dist,
+        ))
+    }
+
+    /// All visible geometry and lights in world space, under a BVH
+    pub fn flatten(&self) -> HittableList {
+        let mut list = HittableList::default();
+        self.flatten_node(self.root(), Mat4::identity(), &mut list);
+        bvh::accelerate(list)
+    }
+
+    fn flatten_node(&self, id: NodeId, parent: Mat4, list: &mut HittableList) {
+        let node = &self.nodes[id];
+        if !node.visible {
+            return;
+        }
+        let world = parent * node.transform;
+
+        // Singular transforms squash the subtree to nothing, skip its objects
+        if let Some(transform) = Transform::new(world) {
+            let identity = world == Mat4::identity();
+            for object in &node.geometry {
+                if identity {
+                    list.add(object.clone());
+                } else {
+                    list.add(Arc::new(Instance::with_transform(
+                        object.clone(),
+                        transform,
+                    )));
+                }
+            }
+        }
+        if let Some(light) = node.light {
+            list.add(Arc::new(Sphere::new(
+                world.transform_point(&Point3::default()),
+                light.radius,
+                Arc::new(DiffuseLight::new(light.emit)),
+            )));
+        }
+
+        for &child in &node.children {
+            self.flatten_node(child, world, list);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::ray::Ray;
+
+    fn unit_sphere() -> Arc<dyn Hittable + Send + Sync> {
+        Arc::new(Sphere::new(
+            Point3::default(),
+            1.0,
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        ))
+    }
+
+    #[test]
+    fn hierarchy_test() {
+        let mut graph = SceneGraph::new();
+        let table = graph
+            .add_node(
+                graph.root(),
+                "table",
+                Mat4::translation(Vec3::new(0.0, 0.0, -10.0)),
+            )
+            .unwrap();
+        let ball = graph
+            .add_node(table, "ball", Mat4::translation(Vec3::new(3.0, 0.0, 0.0)))
+            .unwrap();
+        graph.node_mut(ball).geometry.push(unit_sphere());
+
+        let p = graph
+            .world_transform(ball)
+            .transform_point(&Point3::default());
+        assert_eq!(p, Point3::new(3.0, 0.0, -10.0));
+
+        let r = Ray::new(Point3::new(3.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(
+            graph.flatten().hit(&r, 0.001, f64::INFINITY).unwrap().t,
+            9.0
+        );
+
+        // Tweak by name and render again
+        graph.by_name_mut("ball").unwrap().transform = Mat4::translation(Vec3::new(0.0, 0.0, 2.0));
+        assert!(graph.flatten().hit(&r, 0.001, f64::INFINITY).is_none());
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(
+            graph.flatten().hit(&r, 0.001, f64::INFINITY).unwrap().t,
+            7.0
+        );
+
+        graph.by_name_mut("table").unwrap().visible = false;
+        assert!(graph.flatten().hit(&r, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn reparent_test() {
+        let mut graph = SceneGraph::new();
+        let a = graph.add_node(graph.root(), "a", Mat4::identity()).unwrap();
+        let b = graph.add_node(a, "b", Mat4::identity()).unwrap();
+
+        assert!(!graph.reparent(a, b));
+        assert!(graph.reparent(b, graph.root()));
+        assert!(graph.node(a).children().is_empty());
+        assert_eq!(graph.node(b).parent(), Some(graph.root()));
+
+        assert!(!graph.rename(a, "b"));
+        assert!(graph.rename(a, "c"));
+        assert_eq!(graph.find("c"), Some(a));
+        assert_eq!(graph.find("a"), None);
+    }
+
+    #[test]
+    fn camera_test() {
+        let mut graph = SceneGraph::new();
+        let rig = graph
+            .add_node(
+                graph.root(),
+                "rig",
+                Mat4::translation(Vec3::new(0.0, 1.0, 0.0)),
+            )
+            .unwrap();
+        let camera = graph
+            .add_node(
+                rig,
+                "camera",
+                Mat4::rotation(Vec3::new(0.0, 1.0, 0.0), 90.0),
+            )
+            .unwrap();
+        graph.node_mut(camera).camera = Some(CameraSettings {
+            vfov: 40.0,
+            aspect_ratio: 1.0,
+            aperture: 0.0,
+            focus_dist: 1.0,
+        });
+
+        let r = graph.camera("camera").unwrap().get_ray(0.5, 0.5);
+        assert_eq!(r.origin(), Point3::new(0.0, 1.0, 0.0));
+        // Rotating -z by 90 degrees about y looks down -x
+        assert!((unit_vector(r.direction()) - Vec3::new(-1.0, 0.0, 0.0)).length() < 1e-9);
+        assert!(graph.camera("rig").is_none());
+    }
+
+    #[test]
+    fn duplicate_name_test() {
+        let mut graph = SceneGraph::new();
+        let a = graph.add_node(graph.root(), "a", Mat4::identity()).unwrap();
+        assert_eq!(graph.add_node(graph.root(), "a", Mat4::identity()), None);
+        assert_eq!(graph.node(graph.root()).children(), &[a]);
+    }
+}
@@ -0,0 +1,355 @@
+//! Shapes defined by signed distance functions and rendered by sphere
+//! tracing.
+//!
+//! Distances are negative inside a shape. Nodes compose through `Arc` so a
+//! tree of primitives and operators can share subtrees.
+
+use std::sync::Arc;
+
+use crate::aabb::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::ray::*;
+use crate::sphere::Sphere;
+use crate::vec3::*;
+
+pub trait Sdf {
+    fn distance(&self, p: &Point3) -> f64;
+}
+
+pub struct SdfSphere {
+    pub center: Point3,
+    pub radius: f64,
+}
+
+impl Sdf for SdfSphere {
+    fn distance(&self, p: &Point3) -> f64 {
+        (*p - self.center).length() - self.radius
+    }
+}
+
+/// Box with `half_extents` around `center`, its edges rounded off by
+/// `radius`
+pub struct RoundedBox {
+    pub center: Point3,
+    pub half_extents: Vec3,
+    pub radius: f64,
+}
+
+impl Sdf for RoundedBox {
+    fn distance(&self, p: &Point3) -> f64 {
+        let d = *p - self.center;
+        let q = Vec3::new(d.x().abs(), d.y().abs(), d.z().abs()) - self.half_extents
+            + Vec3::new(self.radius, self.radius, self.radius);
+        q.max(&Vec3::default()).length() + q.max_component().min(0.0) - self.radius
+    }
+}
+
+/// Distance estimator of the power `power` Mandelbulb fractal, centered at
+/// the origin with a radius of about 1.2 for the classic power 8
+pub struct Mandelbulb {
+    pub power: f64,
+    pub iterations: usize,
+}
+
+impl Sdf for Mandelbulb {
+    fn distance(&self, p: &Point3) -> f64 {
+        let mut z = *p;
+        let mut dr = 1.0;
+        let mut r = 0.0;
+
+        for _ in 0..self.iterations {
+            r = z.length();
+            if r > 2.0 {
+                break;
+            }
+
+            // Raise z to the power in spherical coordinates
+            let theta = (z.z() / r).acos() * self.power;
+            let phi = z.y().atan2(z.x()) * self.power;
+            dr = r.powf(self.power - 1.0) * self.power * dr + 1.0;
+            let zr = r.powf(self.power);
+            z =
+                zr * Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                ) + *p;
+        }
+
+        if r == 0.0 {
+            return 0.0;
+        }
+        0.5 * r.ln() * r / dr
+    }
+}
+
+pub struct Union {
+    pub a: Arc<dyn Sdf + Send + Sync>,
+    pub b: Arc<dyn Sdf + Send + Sync>,
+}
+
+impl Sdf for Union {
+    fn distance(&self, p: &Point3) -> f64 {
+        self.a.distance(p).min(self.b.distance(p))
+    }
+}
+
+/// Union blended over a distance of about `k` with the polynomial smooth
+/// minimum
+pub struct SmoothUnion {
+    pub a: Arc<dyn Sdf + Send + Sync>,
+    pub b: Arc<dyn Sdf + Send + Sync>,
+    pub k: f64,
+}
+
+impl Sdf for SmoothUnion {
+    fn distance(&self, p: &Point3) -> f64 {
+        let (da, db) = (self.a.distance(p), self.b.distance(p));
+        let h = (0.5 + 0.5 * (db - da) / self.k).clamp(0.0, 1.0);
+        db + h * (da - db) - self.k * h * (1.0 - h)
+    }
+}
+
+/// Offsets the surface by `displacement(p)`. Displacements make the field
+/// overestimate distances, so lower the tracer's step scale to match.
+pub struct Displace {
+    pub inner: Arc<dyn Sdf + Send + Sync>,
+    pub displacement: Box<dyn Fn(&Point3) -> f64 + Send + Sync>,
+}
+
+impl Sdf for Displace {
+    fn distance(&self, p: &Point3) -> f64 {
+        self.inner.distance(p) + (self.displacement)(p)
+    }
+}
+
+/// Repeats the inner shape infinitely with the given period along each
+/// axis. A period of zero leaves that axis alone.
+pub struct Repeat {
+    pub inner: Arc<dyn Sdf + Send + Sync>,
+    pub period: Vec3,
+}
+
+impl Sdf for Repeat {
+    fn distance(&self, p: &Point3) -> f64 {
+        let mut q = *p;
+        for a in 0..3 {
+            let c = self.period[a];
+            if c > 0.0 {
+                q[a] -= c * (q[a] / c).round();
+            }
+        }
+        self.inner.distance(&q)
+    }
+}
+
+pub struct Translate {
+    pub inner: Arc<dyn Sdf + Send + Sync>,
+    pub offset: Vec3,
+}
+
+impl Sdf for Translate {
+    fn distance(&self, p: &Point3) -> f64 {
+        self.inner.distance(&(*p - self.offset))
+    }
+}
+
+/// Sphere traces a signed distance field inside `bounds`
+pub struct SdfHittable {
+    sdf: Arc<dyn Sdf + Send + Sync>,
+    bounds: Aabb,
+    material: Arc<dyn Material + Send + Sync>,
+    max_steps: usize,
+    step_scale: f64,
+    epsilon: f64,
+}
+
+impl SdfHittable {
+    pub fn new(
+        sdf: Arc<dyn Sdf + Send + Sync>,
+        bounds: Aabb,
+        material: Arc<dyn Material + Send + Sync>,
+    ) -> SdfHittable {
+        SdfHittable {
+            sdf,
+            bounds,
+            material,
+            max_steps: 256,
+            step_scale: 1.0,
+            epsilon: 1e-5,
+        }
+    }
+
+    /// Fraction of the distance bound to advance per step, below 1 for
+    /// fields that are not exact distances
+    pub fn with_step_scale(mut self, step_scale: f64) -> SdfHittable {
+        self.step_scale = step_scale;
+        self
+    }
+
+    pub fn with_max_steps(mut self, max_steps: usize) -> SdfHittable {
+        self.max_steps = max_steps;
+        self
+    }
+
+    fn gradient(&self, p: &Point3) -> Vec3 {
+        let h = self.epsilon;
+        let mut g = Vec3::default();
+        for a in 0..3 {
+            let mut offset = Vec3::default();
+            offset[a] = h;
+            g[a] = self.sdf.distance(&(*p + offset)) - self.sdf.distance(&(*p - offset));
+        }
+        unit_vector(g)
+    }
+}
+
+impl Hittable for SdfHittable {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (mut t, t_exit) = self.bounds.intersect(r, t_min, t_max)?;
+        let speed = r.direction().length();
+
+        // Rays starting inside the shape march towards its boundary too
+        let side = self.sdf.distance(&r.at(t)).signum();
+        for _ in 0..self.max_steps {
+            let p = r.at(t);
+            let distance = side * self.sdf.distance(&p);
+            if distance < self.epsilon {
+                let outward_normal = self.gradient(&p);
+                let (u, v) = Sphere::get_sphere_uv(&outward_normal);
+                let mut rec = HitRecord {
+                    t,
+                    p,
+                    u,
+                    v,
+                    material: self.material.clone(),
+                    ..Default::default()
+                };
+                rec.set_face_normal(r, &outward_normal);
+                return Some(rec);
+            }
+
+            t += self.step_scale * distance / speed;
+            if t > t_exit {
+                return None;
+            }
+        }
+
+        None
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn material() -> Arc<dyn Material + Send + Sync> {
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    fn unit_sphere() -> Arc<dyn Sdf + Send + Sync> {
+        Arc::new(SdfSphere {
+            center: Point3::default(),
+            radius: 1.0,
+        })
+    }
+
+    fn unit_bounds() -> Aabb {
+        Aabb::new(Point3::new(-1.5, -1.5, -1.5), Point3::new(1.5, 1.5, 1.5))
+    }
+
+    #[test]
+    fn sphere_trace_test() {
+        let shape = SdfHittable::new(unit_sphere(), unit_bounds(), material());
+        let r = Ray::new(Point3::new(0.3, 0.0, 5.0), Vec3::new(0.0, 0.0, -2.0));
+        let rec = shape.hit(&r, 0.001, f64::INFINITY).unwrap();
+
+        let expected = (5.0 - (1.0 - 0.09_f64).sqrt()) / 2.0;
+        assert!((rec.t - expected).abs() < 1e-4);
+        assert!((rec.normal - unit_vector(rec.p)).length() < 1e-4);
+        assert!(rec.front_face);
+
+        // Leaving the sphere from the inside
+        let inside = Ray::new(Point3::default(), Vec3::new(1.0, 0.0, 0.0));
+        let rec = shape.hit(&inside, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 1.0).abs() < 1e-4);
+        assert!(!rec.front_face);
+    }
+
+    #[test]
+    fn rounded_box_test() {
+        let b = RoundedBox {
+            center: Point3::default(),
+            half_extents: Vec3::new(1.0, 2.0, 3.0),
+            radius: 0.5,
+        };
+        assert_eq!(b.distance(&Point3::new(3.0, 0.0, 0.0)), 2.0);
+        assert_eq!(b.distance(&Point3::new(0.0, 0.0, 0.0)), -1.0);
+        // The rounded corner is further away than the sharp one would be
+        assert!(b.distance(&Point3::new(1.0, 2.0, 3.0)) > 0.0);
+    }
+
+    #[test]
+    fn smooth_union_test() {
+        let smooth = SmoothUnion {
+            a: unit_sphere(),
+            b: Arc::new(Translate {
+                inner: unit_sphere(),
+                offset: Vec3::new(1.5, 0.0, 0.0),
+            }),
+            k: 0.5,
+        };
+        let union = Union {
+            a: smooth.a.clone(),
+            b: smooth.b.clone(),
+        };
+        let between = Point3::new(0.75, 0.8, 0.0);
+        assert!(smooth.distance(&between) < union.distance(&between));
+
+        let far = Point3::new(-3.0, 0.0, 0.0);
+        assert_eq!(smooth.distance(&far), union.distance(&far));
+    }
+
+    #[test]
+    fn repeat_test() {
+        let grid = Repeat {
+            inner: unit_sphere(),
+            period: Vec3::new(4.0, 0.0, 0.0),
+        };
+        assert_eq!(grid.distance(&Point3::new(8.0, 0.0, 0.0)), -1.0);
+        assert_eq!(grid.distance(&Point3::new(10.0, 0.0, 0.0)), 1.0);
+        assert_eq!(grid.distance(&Point3::new(0.0, 4.0, 0.0)), 3.0);
+    }
+
+    #[test]
+    fn displace_test() {
+        let bumpy = Displace {
+            inner: unit_sphere(),
+            displacement: Box::new(|p| 0.1 * (5.0 * p.x()).sin()),
+        };
+        let shape =
+            SdfHittable::new(Arc::new(bumpy), unit_bounds(), material()).with_step_scale(0.5);
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = shape.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 4.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn mandelbulb_test() {
+        let bulb = Mandelbulb {
+            power: 8.0,
+            iterations: 12,
+        };
+        assert!(bulb.distance(&Point3::new(0.0, 0.0, 3.0)) > 0.5);
+
+        let shape = SdfHittable::new(Arc::new(bulb), unit_bounds(), material());
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = shape.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!(rec.p.z() > 0.5 && rec.p.z() < 1.5);
+    }
+}
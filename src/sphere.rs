@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use crate::aabb::*;
 use crate::hittable::*;
 use crate::material::*;
 use crate::ray::*;
@@ -19,6 +20,19 @@ impl Sphere {
             material,
         }
     }
+
+    // p: a given point on the sphere of radius one, centered at the origin.
+    // u: returned value [0,1] of angle around the Y axis from X=-1.
+    // v: returned value [0,1] of angle from Y=-1 to Y=+1.
+    pub fn get_sphere_uv(p: &Point3) -> (f64, f64) {
+        let theta = (-p.y()).acos();
+        let phi = (-p.z()).atan2(p.x()) + std::f64::consts::PI;
+
+        (
+            phi / (2.0 * std::f64::consts::PI),
+            theta / std::f64::consts::PI,
+        )
+    }
 }
 
 impl Hittable for Sphere {
@@ -45,6 +59,7 @@ impl Hittable for Sphere {
             rec.p = r.at(rec.t);
             let outward_normal = (rec.p - self.center) / self.radius;
             rec.set_face_normal(r, &outward_normal);
+            (rec.u, rec.v) = Sphere::get_sphere_uv(&outward_normal);
             rec.material = self.material.clone();
 
             Some(rec)
@@ -52,4 +67,9 @@ impl Hittable for Sphere {
             None
         }
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let extent = Vec3::new(self.radius, self.radius, self.radius);
+        Some(Aabb::new(self.center - extent, self.center + extent))
+    }
 }
@@ -0,0 +1,114 @@
+//! Scenes and helpers shared by the tests of several integrators.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::camera::Camera;
+use crate::hittable::HittableList;
+use crate::light::AreaLight;
+use crate::material::{Dielectric, Lambertian};
+use crate::quad::Quad;
+use crate::ray::Ray;
+use crate::render::RenderSettings;
+use crate::sampler::{with_sampler, SeededSampler};
+use crate::sphere::Sphere;
+use crate::vec3::*;
+
+/// A closed grey box lit by a panel under its ceiling, with a glass ball
+pub fn box_scene() -> (HittableList, AreaLight) {
+    let grey = Arc::new(Lambertian::new(Color::new(0.6, 0.6, 0.6)));
+    let mut world = HittableList::default();
+    let (lo, hi) = (-2.0, 2.0);
+    let size = hi - lo;
+    let walls = [
+        (
+            Point3::new(lo, lo, lo),
+            Vec3::new(size, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, size),
+        ),
+        (
+            Point3::new(lo, hi, lo),
+            Vec3::new(size, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, size),
+        ),
+        (
+            Point3::new(lo, lo, lo),
+            Vec3::new(0.0, size, 0.0),
+            Vec3::new(0.0, 0.0, size),
+        ),
+        (
+            Point3::new(hi, lo, lo),
+            Vec3::new(0.0, size, 0.0),
+            Vec3::new(0.0, 0.0, size),
+        ),
+        (
+            Point3::new(lo, lo, lo),
+            Vec3::new(size, 0.0, 0.0),
+            Vec3::new(0.0, size, 0.0),
+        ),
+        (
+            Point3::new(lo, lo, hi),
+            Vec3::new(size, 0.0, 0.0),
+            Vec3::new(0.0, size, 0.0),
+        ),
+    ];
+    for (q, u, v) in walls {
+        world.add(Arc::new(Quad::new(q, u, v, grey.clone())));
+    }
+    world.add(Arc::new(Sphere::new(
+        Point3::new(0.5, -1.4, 0.0),
+        0.6,
+        Arc::new(Dielectric::new(1.5)),
+    )));
+    let light = AreaLight::quad(
+        Point3::new(-0.5, 1.9, -0.5),
+        Vec3::new(1.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 1.0),
+        Color::new(8.0, 8.0, 8.0),
+    );
+    world.add(light.hittable());
+    (world, light)
+}
+
+/// Looks into `box_scene` from just inside its front wall
+pub fn box_camera(settings: &RenderSettings) -> Camera {
+    Camera::new(
+        Point3::new(0.0, 0.0, 1.9),
+        Point3::new(0.0, -0.5, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        70.0,
+        settings.aspect_ratio(),
+        0.0,
+        1.0,
+    )
+}
+
+/// Runs `f` with the random numbers of this thread seeded, so that
+/// statistical comparisons come out the same on every run
+pub fn seeded<R>(seed: u64, f: impl FnOnce() -> R) -> R {
+    with_sampler(Rc::new(RefCell::new(SeededSampler::new(seed))), f)
+}
+
+/// Mean of `li` over the camera rays `render` shoots through each pixel,
+/// row by row from the top
+pub fn pixel_means(
+    camera: &Camera,
+    settings: &RenderSettings,
+    mut li: impl FnMut(&Ray) -> Color,
+) -> Vec<Color> {
+    let (w, h) = (settings.image_width as f64, settings.image_height as f64);
+    let mut means = Vec::new();
+    for j in 0..settings.image_height {
+        for i in 0..settings.image_width {
+            let mut sum = Color::default();
+            for _ in 0..settings.samples_per_pixel {
+                let u = (i as f64 + random_double(0.0..1.0)) / (w - 1.0);
+                let v = (h - j as f64 + random_double(0.0..1.0)) / (h - 1.0);
+                sum += li(&camera.get_ray(u, v));
+            }
+            means.push(sum / settings.samples_per_pixel as f64);
+        }
+    }
+    means
+}
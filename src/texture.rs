@@ -0,0 +1,108 @@
+use crate::vec3::*;
+
+/// RGBA image sampled with bilinear filtering and repeating wrap. Rows are
+/// stored top to bottom while v points up, as in the book's image texture.
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    texels: Vec<[f32; 4]>,
+}
+
+impl ImageTexture {
+    /// Panics unless there are `width * height` texels
+    pub fn new(width: usize, height: usize, texels: Vec<[f32; 4]>) -> ImageTexture {
+        assert!(width > 0 && height > 0, "texture must not be empty");
+        assert_eq!(
+            texels.len(),
+            width * height,
+            "texel count does not match size"
+        );
+        ImageTexture {
+            width,
+            height,
+            texels,
+        }
+    }
+
+    /// A single texel of the given color
+    pub fn solid(color: Color) -> ImageTexture {
+        let c = [color.x() as f32, color.y() as f32, color.z() as f32, 1.0];
+        ImageTexture::new(1, 1, vec![c])
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn texel(&self, i: i64, j: i64) -> [f32; 4] {
+        let i = i.rem_euclid(self.width as i64) as usize;
+        let j = j.rem_euclid(self.height as i64) as usize;
+        self.texels[i + self.width * j]
+    }
+
+    pub fn sample(&self, u: f64, v: f64) -> [f64; 4] {
+        // Texel centers sit at half-integer coordinates
+        let x = u * self.width as f64 - 0.5;
+        let y = (1.0 - v) * self.height as f64 - 0.5;
+        let (i, j) = (x.floor(), y.floor());
+        let (fx, fy) = (x - i, y - j);
+        let (i, j) = (i as i64, j as i64);
+
+        let mut result = [0.0; 4];
+        for (di, dj, w) in [
+            (0, 0, (1.0 - fx) * (1.0 - fy)),
+            (1, 0, fx * (1.0 - fy)),
+            (0, 1, (1.0 - fx) * fy),
+            (1, 1, fx * fy),
+        ] {
+            let t = self.texel(i + di, j + dj);
+            for c in 0..4 {
+                result[c] += w * t[c] as f64;
+            }
+        }
+        result
+    }
+
+    pub fn color(&self, u: f64, v: f64) -> Color {
+        let [r, g, b, _] = self.sample(u, v);
+        Color::new(r, g, b)
+    }
+}
+
+/// Converts an sRGB encoded channel in [0, 1] to linear
+pub fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bilinear_test() {
+        let black = [0.0, 0.0, 0.0, 1.0];
+        let white = [1.0, 1.0, 1.0, 1.0];
+        let texture = ImageTexture::new(2, 1, vec![black, white]);
+
+        assert_eq!(texture.color(0.25, 0.5), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(texture.color(0.75, 0.5), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(texture.color(0.5, 0.5), Color::new(0.5, 0.5, 0.5));
+        // Wraps around at the edges
+        assert_eq!(texture.color(1.25, 0.5), Color::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn srgb_test() {
+        assert_eq!(srgb_to_linear(0.0), 0.0);
+        assert!((srgb_to_linear(1.0) - 1.0).abs() < 1e-12);
+        assert!((srgb_to_linear(0.5) - 0.214).abs() < 1e-3);
+    }
+}
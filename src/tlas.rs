@@ -0,0 +1,226 @@
+//! Two-level acceleration structure for heavily instanced scenes.
+//!
+//! Every mesh keeps its own bottom-level BVH in object space, and the
+//! top-level BVH only covers the instances placing those meshes in the
+//! world. A forest of a thousand trees then stores the tree once. For
+//! animation, `Mesh::set_positions` refits a bottom-level BVH to its moved
+//! vertices and `Tlas::refit` then updates the top level, neither building a
+//! new tree.
+
+use crate::aabb::*;
+use crate::bvh::FlatBvh;
+use crate::hittable::*;
+use crate::instance::{object_ray, to_world, transform_bounds};
+use crate::mesh::Mesh;
+use crate::ray::*;
+use crate::vec3::*;
+
+/// A mesh of the `Tlas` placed in the world
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshInstance {
+    /// Index into the meshes of the `Tlas`
+    pub mesh: usize,
+    pub transform: Transform,
+}
+
+pub struct Tlas {
+    meshes: Vec<Mesh>,
+    instances: Vec<MeshInstance>,
+    // World space bounds of every instance
+    bounds: Vec<Aabb>,
+    // Instances with geometry, in the order the top-level BVH left them
+    order: Vec<u32>,
+    bvh: FlatBvh,
+}
+
+impl Tlas {
+    /// Panics if an instance refers to a mesh that does not exist
+    pub fn new(meshes: Vec<Mesh>, instances: Vec<MeshInstance>) -> Tlas {
+        for instance in &instances {
+            assert!(instance.mesh < meshes.len(), "no such mesh");
+        }
+        let mut tlas = Tlas {
+            meshes,
+            instances,
+            bounds: Vec::new(),
+            order: Vec::new(),
+            bvh: FlatBvh::default(),
+        };
+        tlas.rebuild();
+        tlas
+    }
+
+    pub fn meshes(&self) -> &[Mesh] {
+        &self.meshes
+    }
+
+    pub fn instances(&self) -> &[MeshInstance] {
+        &self.instances
+    }
+
+    /// For animating vertices with `Mesh::set_positions`, call `refit`
+    /// afterwards
+    pub fn mesh_mut(&mut self, mesh: usize) -> &mut Mesh {
+        &mut self.meshes[mesh]
+    }
+
+    /// Takes effect on the next `refit` or `rebuild`
+    pub fn set_transform(&mut self, instance: usize, transform: Transform) {
+        self.instances[instance].transform = transform;
+    }
+
+    fn update_bounds(&mut self) {
+        let meshes = &self.meshes;
+        self.bounds = self
+            .instances
+            .iter()
+            .map(|instance| {
+                meshes[instance.mesh]
+                    .bounding_box()
+                    .map_or_else(Aabb::default, |bbox| {
+                        transform_bounds(&instance.transform, &bbox)
+                    })
+            })
+            .collect();
+    }
+
+    /// Updates the top-level BVH for moved instances and meshes, keeping
+    /// its tree
+    pub fn refit(&mut self) {
+        self.update_bounds();
+        let bounds = &self.bounds;
+        self.bvh
+            .refit(&self.order, |&instance| bounds[instance as usize]);
+    }
+
+    /// Builds the top-level BVH from scratch, better than `refit` once
+    /// instances have moved far
+    pub fn rebuild(&mut self) {
+        self.update_bounds();
+        // Empty meshes stay empty, their vertex count cannot change
+        let meshes = &self.meshes;
+        self.order = (0..self.instances.len() as u32)
+            .filter(|&i| {
+                meshes[self.instances[i as usize].mesh]
+                    .bounding_box()
+                    .is_some()
+            })
+            .collect();
+        let bounds = &self.bounds;
+        self.bvh = FlatBvh::build(&mut self.order, |&instance| bounds[instance as usize]);
+    }
+
+    fn hit_instances(
+        &self,
+        instances: &[u32],
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<HitRecord> {
+        let mut closest: Option<HitRecord> = None;
+        for &index in instances {
+            let instance = &self.instances[index as usize];
+            let limit = closest.as_ref().map_or(t_max, |rec| rec.t);
+            let object_ray = object_ray(&instance.transform, r);
+            if let Some(rec) = self.meshes[instance.mesh].hit(&object_ray, t_min, limit) {
+                closest = Some(to_world(&instance.transform, rec));
+            }
+        }
+        closest
+    }
+}
+
+impl Hittable for Tlas {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.bvh.hit(r, t_min, t_max, |range, t_max| {
+            self.hit_instances(&self.order[range], r, t_min, t_max)
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bvh.bounds()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::mesh::TriangleMesh;
+    use std::sync::Arc;
+
+    fn random_mesh() -> Mesh {
+        let positions: Vec<Point3> = (0..60).map(|_| Vec3::random(-1.0..1.0)).collect();
+        let indices: Vec<[u32; 3]> = (0..20).map(|i| [3 * i, 3 * i + 1, 3 * i + 2]).collect();
+        let material = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        Mesh::new(TriangleMesh::new(positions, indices), material)
+    }
+
+    // Closest hit of every instance tested on its own
+    fn brute_force(tlas: &Tlas, r: &Ray) -> Option<f64> {
+        let all: Vec<u32> = (0..tlas.instances().len() as u32).collect();
+        tlas.hit_instances(&all, r, 0.001, f64::INFINITY)
+            .map(|rec| rec.t)
+    }
+
+    fn forest() -> Tlas {
+        let instances = (0..50)
+            .map(|i| MeshInstance {
+                mesh: i % 2,
+                transform: Transform::new(
+                    Mat4::translation(Vec3::new(
+                        (i % 10) as f64 * 2.5,
+                        0.0,
+                        -((i / 10) as f64) * 2.5,
+                    )) * Mat4::rotation(Vec3::new(0.0, 1.0, 0.0), i as f64 * 17.0),
+                )
+                .unwrap(),
+            })
+            .collect();
+        Tlas::new(vec![random_mesh(), random_mesh()], instances)
+    }
+
+    fn random_ray() -> Ray {
+        Ray::new(
+            Point3::new(11.0, 2.0, 5.0) + Vec3::random(-3.0..3.0),
+            Vec3::new(0.0, -0.2, -1.0) + Vec3::random(-0.6..0.6),
+        )
+    }
+
+    #[test]
+    fn matches_brute_force_test() {
+        let tlas = forest();
+        for _ in 0..300 {
+            let r = random_ray();
+            assert_eq!(
+                tlas.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t),
+                brute_force(&tlas, &r)
+            );
+        }
+    }
+
+    #[test]
+    fn refit_test() {
+        let mut tlas = forest();
+        let lifted = Transform::new(Mat4::translation(Vec3::new(3.0, 4.0, 0.0))).unwrap();
+        tlas.set_transform(7, lifted);
+        let moved = tlas.meshes()[1]
+            .mesh()
+            .positions
+            .iter()
+            .map(|&p| 1.5 * p + Vec3::new(0.0, 0.5, 0.0))
+            .collect();
+        tlas.mesh_mut(1).set_positions(moved);
+        tlas.refit();
+
+        let bounds = tlas.bounding_box().unwrap();
+        assert!(bounds.maximum.y() >= 4.5);
+        for _ in 0..300 {
+            let r = random_ray();
+            assert_eq!(
+                tlas.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t),
+                brute_force(&tlas, &r)
+            );
+        }
+    }
+}
@@ -1,7 +1,11 @@
-use std::ops::{Add, AddAssign, Div, Mul, Range, Sub};
+use std::ops::{
+    Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Range, Sub, SubAssign,
+};
 
 use rand::Rng;
 
+use crate::sampler::next_sample;
+
 #[derive(Debug, PartialEq, Clone, Copy, Default)]
 pub struct Vec3 {
     pub e: [f64; 3],
@@ -29,7 +33,7 @@ impl Vec3 {
     }
 
     pub fn length_squared(&self) -> f64 {
-        return self.e[0].powf(2.0) + self.e[1].powf(2.0) + self.e[2].powf(2.0);
+        self.e[0].powf(2.0) + self.e[1].powf(2.0) + self.e[2].powf(2.0)
     }
 
     pub fn random(range: Range<f64>) -> Vec3 {
@@ -58,17 +62,15 @@ impl Vec3 {
         let in_unit_sphere = Vec3::random_in_unit_sphere();
         if dot(&in_unit_sphere, normal) > 0.0 {
             // In the same hemisphere as the normal
-            return in_unit_sphere;
+            in_unit_sphere
         } else {
-            return -1.0 * in_unit_sphere;
+            -1.0 * in_unit_sphere
         }
     }
 
     pub fn random_in_unit_disk() -> Vec3 {
-        let mut rng = rand::thread_rng();
-
         loop {
-            let p = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0);
+            let p = Vec3::new(random_double(-1.0..1.0), random_double(-1.0..1.0), 0.0);
             if p.length_squared() >= 1.0 {
                 continue;
             }
@@ -92,6 +94,32 @@ impl Vec3 {
 
         r_out_parallel + r_out_perpendicular
     }
+
+    /// Component-wise minimum
+    pub fn min(&self, other: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.x().min(other.x()),
+            self.y().min(other.y()),
+            self.z().min(other.z()),
+        )
+    }
+
+    /// Component-wise maximum
+    pub fn max(&self, other: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.x().max(other.x()),
+            self.y().max(other.y()),
+            self.z().max(other.z()),
+        )
+    }
+
+    pub fn min_component(&self) -> f64 {
+        self.x().min(self.y()).min(self.z())
+    }
+
+    pub fn max_component(&self) -> f64 {
+        self.x().max(self.y()).max(self.z())
+    }
 }
 
 // Utility functions
@@ -113,9 +141,12 @@ pub fn cross(u: &Vec3, v: &Vec3) -> Vec3 {
     }
 }
 
+/// A uniform random number, from the installed `Sampler` if there is one
 pub fn random_double(range: Range<f64>) -> f64 {
-    let mut rng = rand::thread_rng();
-    rng.gen_range(range)
+    match next_sample() {
+        Some(u) => range.start + u * (range.end - range.start),
+        None => rand::thread_rng().gen_range(range),
+    }
 }
 
 impl Add for Vec3 {
@@ -182,6 +213,466 @@ impl AddAssign for Vec3 {
     }
 }
 
+impl SubAssign for Vec3 {
+    fn sub_assign(&mut self, other: Self) {
+        *self = *self - other
+    }
+}
+
+impl MulAssign<f64> for Vec3 {
+    fn mul_assign(&mut self, rhs: f64) {
+        *self = *self * rhs
+    }
+}
+
+impl DivAssign<f64> for Vec3 {
+    fn div_assign(&mut self, rhs: f64) {
+        *self = *self / rhs
+    }
+}
+
+impl Neg for Vec3 {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Vec3::new(-self.x(), -self.y(), -self.z())
+    }
+}
+
+impl Index<usize> for Vec3 {
+    type Output = f64;
+    fn index(&self, i: usize) -> &f64 {
+        &self.e[i]
+    }
+}
+
+impl IndexMut<usize> for Vec3 {
+    fn index_mut(&mut self, i: usize) -> &mut f64 {
+        &mut self.e[i]
+    }
+}
+
+/// Row-major 3x3 matrix for linear maps of vectors
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Mat3 {
+    pub m: [[f64; 3]; 3],
+}
+
+impl Default for Mat3 {
+    fn default() -> Mat3 {
+        Mat3::identity()
+    }
+}
+
+impl Mat3 {
+    pub fn new(m: [[f64; 3]; 3]) -> Mat3 {
+        Mat3 { m }
+    }
+
+    pub fn identity() -> Mat3 {
+        Mat3::new([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]])
+    }
+
+    /// Matrix whose columns are the given vectors
+    pub fn from_cols(a: Vec3, b: Vec3, c: Vec3) -> Mat3 {
+        Mat3::new([a.e, b.e, c.e]).transpose()
+    }
+
+    pub fn transpose(&self) -> Mat3 {
+        let m = &self.m;
+        Mat3::new([
+            [m[0][0], m[1][0], m[2][0]],
+            [m[0][1], m[1][1], m[2][1]],
+            [m[0][2], m[1][2], m[2][2]],
+        ])
+    }
+
+    pub fn determinant(&self) -> f64 {
+        let [a, b, c] = self.m.map(|row| Vec3 { e: row });
+        dot(&a, &cross(&b, &c))
+    }
+
+    /// Inverse through the adjugate, `None` if singular
+    pub fn inverse(&self) -> Option<Mat3> {
+        let [a, b, c] = self.m.map(|row| Vec3 { e: row });
+        let det = dot(&a, &cross(&b, &c));
+        if det.abs() < 1e-12 {
+            return None;
+        }
+
+        // The columns of the inverse are the cross products of the rows
+        Some(Mat3::from_cols(
+            cross(&b, &c) / det,
+            cross(&c, &a) / det,
+            cross(&a, &b) / det,
+        ))
+    }
+}
+
+impl Mul for Mat3 {
+    type Output = Mat3;
+    fn mul(self, rhs: Mat3) -> Mat3 {
+        let cols = rhs.transpose().m.map(|col| Vec3 { e: col });
+        Mat3::new(self.m.map(|row| {
+            let row = Vec3 { e: row };
+            cols.map(|col| dot(&row, &col))
+        }))
+    }
+}
+
+impl Mul<Vec3> for Mat3 {
+    type Output = Vec3;
+    fn mul(self, rhs: Vec3) -> Vec3 {
+        Vec3 {
+            e: self.m.map(|row| dot(&Vec3 { e: row }, &rhs)),
+        }
+    }
+}
+
+/// Row-major 4x4 matrix for affine transforms of points, vectors and normals
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Mat4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Default for Mat4 {
+    fn default() -> Mat4 {
+        Mat4::identity()
+    }
+}
+
+impl Mat4 {
+    pub fn new(m: [[f64; 4]; 4]) -> Mat4 {
+        Mat4 { m }
+    }
+
+    pub fn identity() -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        Mat4 { m }
+    }
+
+    pub fn translation(offset: Vec3) -> Mat4 {
+        let mut t = Mat4::identity();
+        for i in 0..3 {
+            t.m[i][3] = offset.e[i];
+        }
+        t
+    }
+
+    pub fn scaling(factors: Vec3) -> Mat4 {
+        let mut s = Mat4::identity();
+        for i in 0..3 {
+            s.m[i][i] = factors.e[i];
+        }
+        s
+    }
+
+    /// Rotation by `degrees` counter-clockwise around `axis`
+    pub fn rotation(axis: Vec3, degrees: f64) -> Mat4 {
+        let a = unit_vector(axis);
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let t = 1.0 - cos;
+        let (x, y, z) = (a.x(), a.y(), a.z());
+        let (xy, xz, yz) = (t * x * y, t * x * z, t * y * z);
+
+        Mat4::new([
+            [t * x * x + cos, xy - sin * z, xz + sin * y, 0.0],
+            [xy + sin * z, t * y * y + cos, yz - sin * x, 0.0],
+            [xz - sin * y, yz + sin * x, t * z * z + cos, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut t = Mat4::identity();
+        for i in 0..4 {
+            for j in 0..4 {
+                t.m[i][j] = self.m[j][i];
+            }
+        }
+        t
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting, `None` if singular
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inv = Mat4::identity().m;
+
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let p = a[col][col];
+            for j in 0..4 {
+                a[col][j] /= p;
+                inv[col][j] /= p;
+            }
+
+            for row in 0..4 {
+                if row != col {
+                    let f = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= f * a[col][j];
+                        inv[row][j] -= f * inv[col][j];
+                    }
+                }
+            }
+        }
+
+        Some(Mat4 { m: inv })
+    }
+
+    /// Applies the full affine transform, including translation
+    pub fn transform_point(&self, p: &Point3) -> Point3 {
+        let [x, y, z, w] = self
+            .m
+            .map(|row| row[0] * p.x() + row[1] * p.y() + row[2] * p.z() + row[3]);
+        if w == 1.0 {
+            Point3::new(x, y, z)
+        } else {
+            Point3::new(x, y, z) / w
+        }
+    }
+
+    /// Applies only the linear part, ignoring translation
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let [x, y, z, _] = self
+            .m
+            .map(|row| row[0] * v.x() + row[1] * v.y() + row[2] * v.z());
+        Vec3::new(x, y, z)
+    }
+
+    /// Transforms a normal, `self` being the inverse of the transform
+    /// applied to the surface
+    pub fn transform_normal(&self, n: &Vec3) -> Vec3 {
+        self.transpose().transform_vector(n)
+    }
+
+    /// The linear part of the transform
+    pub fn to_mat3(&self) -> Mat3 {
+        let m = &self.m;
+        Mat3::new([
+            [m[0][0], m[0][1], m[0][2]],
+            [m[1][0], m[1][1], m[1][2]],
+            [m[2][0], m[2][1], m[2][2]],
+        ])
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+    fn mul(self, rhs: Mat4) -> Mat4 {
+        let mut out = [[0.0; 4]; 4];
+        for (i, row) in out.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Mat4 { m: out }
+    }
+}
+
+impl From<Mat3> for Mat4 {
+    fn from(linear: Mat3) -> Mat4 {
+        let mut m = Mat4::identity();
+        for (row, linear_row) in m.m.iter_mut().zip(linear.m) {
+            row[..3].copy_from_slice(&linear_row);
+        }
+        m
+    }
+}
+
+/// An invertible transform kept together with its inverse, so points,
+/// vectors and normals can each be mapped with the right rule.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Transform {
+    pub matrix: Mat4,
+    pub inverse: Mat4,
+}
+
+impl Transform {
+    /// `None` if `matrix` is singular
+    pub fn new(matrix: Mat4) -> Option<Transform> {
+        Some(Transform {
+            matrix,
+            inverse: matrix.inverse()?,
+        })
+    }
+
+    pub fn translation(offset: Vec3) -> Transform {
+        Transform {
+            matrix: Mat4::translation(offset),
+            inverse: Mat4::translation(-offset),
+        }
+    }
+
+    pub fn rotation(q: Quat) -> Transform {
+        let q = q.normalized();
+        Transform {
+            matrix: q.to_mat4(),
+            inverse: q.conjugate().to_mat4(),
+        }
+    }
+
+    pub fn inverted(&self) -> Transform {
+        Transform {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    pub fn point(&self, p: &Point3) -> Point3 {
+        self.matrix.transform_point(p)
+    }
+
+    pub fn vector(&self, v: &Vec3) -> Vec3 {
+        self.matrix.transform_vector(v)
+    }
+
+    /// Normals are mapped by the inverse transpose to stay perpendicular to
+    /// the transformed surface. The result is not normalized.
+    pub fn normal(&self, n: &Vec3) -> Vec3 {
+        self.inverse.transform_normal(n)
+    }
+}
+
+/// `a * b` applies `b` first, then `a`
+impl Mul for Transform {
+    type Output = Transform;
+    fn mul(self, rhs: Transform) -> Transform {
+        Transform {
+            matrix: self.matrix * rhs.matrix,
+            inverse: rhs.inverse * self.inverse,
+        }
+    }
+}
+
+/// Quaternion `w + xi + yj + zk`, used for rotations
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Quat {
+    pub w: f64,
+    pub v: Vec3,
+}
+
+impl Default for Quat {
+    fn default() -> Quat {
+        Quat::identity()
+    }
+}
+
+impl Quat {
+    pub fn new(w: f64, x: f64, y: f64, z: f64) -> Quat {
+        Quat {
+            w,
+            v: Vec3::new(x, y, z),
+        }
+    }
+
+    pub fn identity() -> Quat {
+        Quat::new(1.0, 0.0, 0.0, 0.0)
+    }
+
+    /// Rotation by `degrees` counter-clockwise around `axis`
+    pub fn from_axis_angle(axis: Vec3, degrees: f64) -> Quat {
+        let (sin, cos) = (degrees.to_radians() / 2.0).sin_cos();
+        Quat {
+            w: cos,
+            v: sin * unit_vector(axis),
+        }
+    }
+
+    pub fn dot(&self, other: &Quat) -> f64 {
+        self.w * other.w + dot(&self.v, &other.v)
+    }
+
+    pub fn length(&self) -> f64 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn normalized(&self) -> Quat {
+        let len = self.length();
+        Quat {
+            w: self.w / len,
+            v: self.v / len,
+        }
+    }
+
+    pub fn conjugate(&self) -> Quat {
+        Quat {
+            w: self.w,
+            v: -self.v,
+        }
+    }
+
+    /// Rotates `v` by this unit quaternion
+    pub fn rotate(&self, v: &Vec3) -> Vec3 {
+        let t = 2.0 * cross(&self.v, v);
+        *v + self.w * t + cross(&self.v, &t)
+    }
+
+    pub fn to_mat3(&self) -> Mat3 {
+        Mat3::from_cols(
+            self.rotate(&Vec3::new(1.0, 0.0, 0.0)),
+            self.rotate(&Vec3::new(0.0, 1.0, 0.0)),
+            self.rotate(&Vec3::new(0.0, 0.0, 1.0)),
+        )
+    }
+
+    pub fn to_mat4(&self) -> Mat4 {
+        Mat4::from(self.to_mat3())
+    }
+
+    /// Spherical linear interpolation along the shortest arc
+    pub fn slerp(&self, other: &Quat, t: f64) -> Quat {
+        let mut end = *other;
+        let mut cos_theta = self.dot(other);
+        if cos_theta < 0.0 {
+            end = Quat {
+                w: -end.w,
+                v: -end.v,
+            };
+            cos_theta = -cos_theta;
+        }
+
+        // Nearly parallel quaternions fall back to a normalized lerp
+        let (a, b) = if cos_theta > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let theta = cos_theta.acos();
+            let sin_theta = theta.sin();
+            (
+                ((1.0 - t) * theta).sin() / sin_theta,
+                (t * theta).sin() / sin_theta,
+            )
+        };
+
+        Quat {
+            w: a * self.w + b * end.w,
+            v: a * self.v + b * end.v,
+        }
+        .normalized()
+    }
+}
+
+/// Hamilton product, `a * b` rotates by `b` first
+impl Mul for Quat {
+    type Output = Quat;
+    fn mul(self, rhs: Quat) -> Quat {
+        Quat {
+            w: self.w * rhs.w - dot(&self.v, &rhs.v),
+            v: self.w * rhs.v + rhs.w * self.v + cross(&self.v, &rhs.v),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -285,7 +776,217 @@ mod tests {
         let v1 = Vec3::new(1e-9, 1e-9, 1e-9);
         let v2 = Vec3::new(1e-7, 1e-7, 1e-7);
 
-        assert_eq!(v1.near_zero(), true);
-        assert_eq!(v2.near_zero(), false);
+        assert!(v1.near_zero());
+        assert!(!v2.near_zero());
+    }
+
+    #[test]
+    fn mat4_inverse_test() {
+        let m = Mat4::translation(Vec3::new(1.0, 2.0, 3.0))
+            * Mat4::rotation(Vec3::new(0.0, 1.0, 0.0), 30.0)
+            * Mat4::scaling(Vec3::new(2.0, 2.0, 2.0));
+        let product = m * m.inverse().unwrap();
+        for i in 0..4 {
+            for j in 0..4 {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((product.m[i][j] - expected).abs() < 1e-12);
+            }
+        }
+        assert_eq!(Mat4::scaling(Vec3::new(1.0, 0.0, 1.0)).inverse(), None);
+    }
+
+    #[test]
+    fn mat4_transform_test() {
+        let t = Mat4::translation(Vec3::new(1.0, 2.0, 3.0));
+        let v = Vec3::new(1.0, 1.0, 1.0);
+        assert_eq!(t.transform_point(&v), Vec3::new(2.0, 3.0, 4.0));
+        assert_eq!(t.transform_vector(&v), v);
+    }
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn neg_test() {
+        let v = Vec3::new(1.0, -2.0, 0.0);
+        assert_eq!(-v, Vec3::new(-1.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn sub_assign_test() {
+        let mut u = Vec3::new(1.0, 1.0, 1.0);
+        u -= Vec3::new(2.0, 0.5, 1.0);
+        assert_eq!(u, Vec3::new(-1.0, 0.5, 0.0));
+    }
+
+    #[test]
+    fn mul_assign_test() {
+        let mut u = Vec3::new(1.0, -1.0, 2.0);
+        u *= 3.0;
+        assert_eq!(u, Vec3::new(3.0, -3.0, 6.0));
+    }
+
+    #[test]
+    fn div_assign_test() {
+        let mut u = Vec3::new(1.0, -1.0, 2.0);
+        u /= 2.0;
+        assert_eq!(u, Vec3::new(0.5, -0.5, 1.0));
+    }
+
+    #[test]
+    fn index_test() {
+        let mut v = Vec3::new(1.0, 2.0, 3.0);
+        assert_eq!(v[1], 2.0);
+        v[2] = 5.0;
+        assert_eq!(v, Vec3::new(1.0, 2.0, 5.0));
+    }
+
+    #[test]
+    fn min_max_test() {
+        let u = Vec3::new(1.0, 5.0, -3.0);
+        let v = Vec3::new(2.0, -1.0, -4.0);
+        assert_eq!(u.min(&v), Vec3::new(1.0, -1.0, -4.0));
+        assert_eq!(u.max(&v), Vec3::new(2.0, 5.0, -3.0));
+        assert_eq!(u.min_component(), -3.0);
+        assert_eq!(u.max_component(), 5.0);
+    }
+
+    #[test]
+    fn cross_test() {
+        let x = Vec3::new(1.0, 0.0, 0.0);
+        let y = Vec3::new(0.0, 1.0, 0.0);
+        assert_eq!(cross(&x, &y), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(cross(&y, &x), Vec3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn mat3_transpose_test() {
+        let m = Mat3::new([[1.0, 2.0, 3.0], [4.0, 5.0, 6.0], [7.0, 8.0, 9.0]]);
+        assert_eq!(
+            m.transpose(),
+            Mat3::new([[1.0, 4.0, 7.0], [2.0, 5.0, 8.0], [3.0, 6.0, 9.0]])
+        );
+        assert_eq!(m.transpose().transpose(), m);
+    }
+
+    #[test]
+    fn mat3_determinant_test() {
+        let m = Mat3::new([[2.0, 1.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 3.0]]);
+        assert_eq!(m.determinant(), 3.0);
+        assert_eq!(Mat3::identity().determinant(), 1.0);
+    }
+
+    #[test]
+    fn mat3_inverse_test() {
+        let m = Mat3::new([[2.0, 1.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 3.0]]);
+        assert_eq!(m * m.inverse().unwrap(), Mat3::identity());
+        assert_eq!(m.inverse().unwrap() * m, Mat3::identity());
+
+        let singular = Mat3::new([[1.0, 2.0, 3.0], [2.0, 4.0, 6.0], [0.0, 0.0, 1.0]]);
+        assert_eq!(singular.inverse(), None);
+    }
+
+    #[test]
+    fn mat3_mul_vector_test() {
+        let m = Mat3::new([[1.0, 2.0, 3.0], [0.0, 1.0, 0.0], [0.0, 0.0, 2.0]]);
+        assert_eq!(m * Vec3::new(1.0, 1.0, 1.0), Vec3::new(6.0, 1.0, 2.0));
+    }
+
+    #[test]
+    fn mat4_transpose_test() {
+        let t = Mat4::translation(Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(t.transpose().m[3], [1.0, 2.0, 3.0, 1.0]);
+        assert_eq!(t.transpose().transpose(), t);
+    }
+
+    #[test]
+    fn mat4_from_mat3_test() {
+        let m = Mat3::new([[1.0, 2.0, 3.0], [4.0, 5.0, 6.0], [7.0, 8.0, 9.0]]);
+        let m4 = Mat4::from(m);
+        assert_eq!(m4.to_mat3(), m);
+        assert_eq!(m4.m[3], [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(m4.transform_point(&Vec3::default()), Vec3::default());
+    }
+
+    #[test]
+    fn transform_semantics_test() {
+        let t = Transform::translation(Vec3::new(0.0, 0.0, 5.0))
+            * Transform::new(Mat4::scaling(Vec3::new(2.0, 1.0, 1.0))).unwrap();
+        let v = Vec3::new(1.0, 1.0, 0.0);
+
+        assert_eq!(t.point(&v), Vec3::new(2.0, 1.0, 5.0));
+        assert_eq!(t.vector(&v), Vec3::new(2.0, 1.0, 0.0));
+        assert_eq!(t.normal(&v), Vec3::new(0.5, 1.0, 0.0));
+        assert_eq!(t.inverted().point(&t.point(&v)), v);
+    }
+
+    #[test]
+    fn transform_normal_stays_perpendicular_test() {
+        let t = Transform::new(Mat4::scaling(Vec3::new(3.0, 1.0, 0.5))).unwrap();
+        let tangent = Vec3::new(1.0, -1.0, 0.0);
+        let normal = Vec3::new(1.0, 1.0, 0.0);
+        assert!(dot(&t.vector(&tangent), &t.normal(&normal)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn quat_rotate_test() {
+        let q = Quat::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), 90.0);
+        assert_near(
+            q.rotate(&Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+
+        let m = Mat4::rotation(Vec3::new(1.0, 1.0, 0.0), 40.0);
+        let q = Quat::from_axis_angle(Vec3::new(1.0, 1.0, 0.0), 40.0);
+        let v = Vec3::new(0.3, -2.0, 1.5);
+        assert_near(q.rotate(&v), m.transform_vector(&v));
+        assert_near(q.to_mat3() * v, m.transform_vector(&v));
+    }
+
+    #[test]
+    fn quat_mul_test() {
+        let a = Quat::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), 90.0);
+        let b = Quat::from_axis_angle(Vec3::new(1.0, 0.0, 0.0), 90.0);
+        let v = Vec3::new(0.0, 1.0, 0.0);
+        assert_near((a * b).rotate(&v), a.rotate(&b.rotate(&v)));
+        assert_near((a * a.conjugate()).v, Vec3::default());
+    }
+
+    #[test]
+    fn quat_slerp_test() {
+        let axis = Vec3::new(0.0, 1.0, 0.0);
+        let a = Quat::identity();
+        let b = Quat::from_axis_angle(axis, 90.0);
+        let half = a.slerp(&b, 0.5);
+        let expected = Quat::from_axis_angle(axis, 45.0);
+
+        assert!((half.w - expected.w).abs() < 1e-12);
+        assert_near(half.v, expected.v);
+        assert_eq!(a.slerp(&b, 0.0), a);
+        assert!((a.slerp(&b, 1.0).dot(&b) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn quat_slerp_shortest_arc_test() {
+        let a = Quat::identity();
+        let b = Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 90.0);
+        let negated = Quat { w: -b.w, v: -b.v };
+        let half = a.slerp(&negated, 0.5);
+        let v = Vec3::new(1.0, 0.0, 0.0);
+        assert_near(half.rotate(&v), a.slerp(&b, 0.5).rotate(&v));
+    }
+
+    #[test]
+    fn transform_rotation_test() {
+        let t = Transform::rotation(Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 90.0));
+        assert_near(
+            t.point(&Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(0.0, 0.0, -1.0),
+        );
+        assert_near(
+            t.inverted().point(&Vec3::new(0.0, 0.0, -1.0)),
+            Vec3::new(1.0, 0.0, 0.0),
+        );
     }
 }
@@ -0,0 +1,636 @@
+//! Heterogeneous participating media driven by voxel density grids.
+//!
+//! Dense grids are read from a small little-endian binary format:
+//!
+//! ```text
+//! magic      b"DVOX"
+//! nx, ny, nz u32
+//! bounds     6 x f32 (min x, y, z, then max x, y, z)
+//! densities  nx * ny * nz x f32, x varying fastest, then y, then z
+//! ```
+//!
+//! Densities are sampled at voxel centers and interpolated trilinearly. The
+//! same grids also hold other scalar fields, such as temperatures.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::aabb::*;
+use crate::blackbody::*;
+use crate::hittable::*;
+use crate::material::*;
+use crate::ray::*;
+use crate::vec3::*;
+
+const MAGIC: &[u8; 4] = b"DVOX";
+/// Largest grid `VoxelGrid::read` accepts, 16 GiB of densities
+const MAX_VOXELS: u64 = 1 << 32;
+
+/// A scalar field over a box, e.g. the density tracked by `GridMedium`
+pub trait DensityGrid {
+    fn bounds(&self) -> Aabb;
+    fn density(&self, p: &Point3) -> f64;
+    /// Upper bound of `density` anywhere in the grid
+    fn max_density(&self) -> f64;
+}
+
+/// Dense voxel grid storing every voxel
+pub struct VoxelGrid {
+    dims: [usize; 3],
+    bounds: Aabb,
+    data: Vec<f32>,
+    max: f64,
+}
+
+impl VoxelGrid {
+    /// Panics if a dimension is zero or `data` does not hold exactly one
+    /// value per voxel
+    pub fn new(dims: [usize; 3], bounds: Aabb, data: Vec<f32>) -> VoxelGrid {
+        assert!(dims.iter().all(|&d| d > 0), "empty voxel grid");
+        assert_eq!(data.len(), dims[0] * dims[1] * dims[2]);
+        let max = data.iter().fold(0.0_f32, |acc, &d| acc.max(d)) as f64;
+
+        VoxelGrid {
+            dims,
+            bounds,
+            data,
+            max,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<VoxelGrid> {
+        VoxelGrid::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<VoxelGrid> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a dense voxel grid file",
+            ));
+        }
+
+        let mut dims = [0; 3];
+        for d in dims.iter_mut() {
+            *d = read_u32(reader)? as usize;
+        }
+        let mut corners = [0.0; 6];
+        for c in corners.iter_mut() {
+            *c = read_f32(reader)? as f64;
+        }
+        let bounds = Aabb::new(
+            Point3::new(corners[0], corners[1], corners[2]),
+            Point3::new(corners[3], corners[4], corners[5]),
+        );
+
+        let count = dims
+            .iter()
+            .try_fold(1_u64, |n, &d| n.checked_mul(d as u64))
+            .filter(|&n| n > 0 && n <= MAX_VOXELS)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid voxel grid dimensions {:?}", dims),
+                )
+            })?;
+
+        // The header is not trusted with an allocation, the buffer grows
+        // with the data actually read
+        let mut bytes = Vec::new();
+        reader.take(count * 4).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != count * 4 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let data = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+
+        Ok(VoxelGrid::new(dims, bounds, data))
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        for d in self.dims {
+            writer.write_all(&(d as u32).to_le_bytes())?;
+        }
+        for c in self.bounds.minimum.e.iter().chain(&self.bounds.maximum.e) {
+            writer.write_all(&(*c as f32).to_le_bytes())?;
+        }
+        for d in &self.data {
+            writer.write_all(&d.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn dims(&self) -> [usize; 3] {
+        self.dims
+    }
+
+    pub fn voxel(&self, i: usize, j: usize, k: usize) -> f64 {
+        self.data[i + self.dims[0] * (j + self.dims[1] * k)] as f64
+    }
+}
+
+impl DensityGrid for VoxelGrid {
+    fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    fn density(&self, p: &Point3) -> f64 {
+        trilinear(self.dims, &self.bounds, p, |i, j, k| self.voxel(i, j, k))
+    }
+
+    fn max_density(&self) -> f64 {
+        self.max
+    }
+}
+
+const BRICK_SIZE: usize = 8;
+const EMPTY_BRICK: u32 = u32::MAX;
+
+/// Sparse grid in the spirit of NanoVDB: voxels are grouped into 8^3 bricks
+/// and only bricks with non-zero density are stored.
+pub struct SparseVoxelGrid {
+    dims: [usize; 3],
+    bounds: Aabb,
+    brick_dims: [usize; 3],
+    brick_index: Vec<u32>,
+    bricks: Vec<[f32; BRICK_SIZE * BRICK_SIZE * BRICK_SIZE]>,
+    max: f64,
+}
+
+impl SparseVoxelGrid {
+    pub fn from_dense(grid: &VoxelGrid) -> SparseVoxelGrid {
+        let dims = grid.dims;
+        let brick_dims = dims.map(|d| d.div_ceil(BRICK_SIZE));
+        let mut brick_index = vec![EMPTY_BRICK; brick_dims[0] * brick_dims[1] * brick_dims[2]];
+        let mut bricks = Vec::new();
+
+        for bk in 0..brick_dims[2] {
+            for bj in 0..brick_dims[1] {
+                for bi in 0..brick_dims[0] {
+                    let mut brick = [0.0; BRICK_SIZE * BRICK_SIZE * BRICK_SIZE];
+                    let mut occupied = false;
+                    for (n, value) in brick.iter_mut().enumerate() {
+                        let i = bi * BRICK_SIZE + n % BRICK_SIZE;
+                        let j = bj * BRICK_SIZE + (n / BRICK_SIZE) % BRICK_SIZE;
+                        let k = bk * BRICK_SIZE + n / (BRICK_SIZE * BRICK_SIZE);
+                        if i < dims[0] && j < dims[1] && k < dims[2] {
+                            *value = grid.voxel(i, j, k) as f32;
+                            occupied |= *value != 0.0;
+                        }
+                    }
+                    if occupied {
+                        brick_index[bi + brick_dims[0] * (bj + brick_dims[1] * bk)] =
+                            bricks.len() as u32;
+                        bricks.push(brick);
+                    }
+                }
+            }
+        }
+
+        SparseVoxelGrid {
+            dims,
+            bounds: grid.bounds,
+            brick_dims,
+            brick_index,
+            bricks,
+            max: grid.max,
+        }
+    }
+
+    /// Number of stored (non-empty) bricks
+    pub fn brick_count(&self) -> usize {
+        self.bricks.len()
+    }
+
+    pub fn voxel(&self, i: usize, j: usize, k: usize) -> f64 {
+        let (bi, bj, bk) = (i / BRICK_SIZE, j / BRICK_SIZE, k / BRICK_SIZE);
+        let index = self.brick_index[bi + self.brick_dims[0] * (bj + self.brick_dims[1] * bk)];
+        if index == EMPTY_BRICK {
+            return 0.0;
+        }
+
+        let (li, lj, lk) = (i % BRICK_SIZE, j % BRICK_SIZE, k % BRICK_SIZE);
+        self.bricks[index as usize][li + BRICK_SIZE * (lj + BRICK_SIZE * lk)] as f64
+    }
+}
+
+impl DensityGrid for SparseVoxelGrid {
+    fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    fn density(&self, p: &Point3) -> f64 {
+        trilinear(self.dims, &self.bounds, p, |i, j, k| self.voxel(i, j, k))
+    }
+
+    fn max_density(&self) -> f64 {
+        self.max
+    }
+}
+
+// Interpolates between voxel centers, clamping at the grid border
+fn trilinear<F: Fn(usize, usize, usize) -> f64>(
+    dims: [usize; 3],
+    bounds: &Aabb,
+    p: &Point3,
+    voxel: F,
+) -> f64 {
+    let mut lo = [0; 3];
+    let mut hi = [0; 3];
+    let mut frac = [0.0; 3];
+    for a in 0..3 {
+        let extent = bounds.maximum[a] - bounds.minimum[a];
+        let x = (p[a] - bounds.minimum[a]) / extent * dims[a] as f64 - 0.5;
+        let x = x.clamp(0.0, (dims[a] - 1) as f64);
+        lo[a] = x.floor() as usize;
+        hi[a] = (lo[a] + 1).min(dims[a] - 1);
+        frac[a] = x - lo[a] as f64;
+    }
+
+    let lerp = |a: f64, b: f64, t: f64| a + t * (b - a);
+    let plane = |k: usize| {
+        lerp(
+            lerp(voxel(lo[0], lo[1], k), voxel(hi[0], lo[1], k), frac[0]),
+            lerp(voxel(lo[0], hi[1], k), voxel(hi[0], hi[1], k), frac[0]),
+            frac[1],
+        )
+    };
+    lerp(plane(lo[2]), plane(hi[2]), frac[2])
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut b = [0; 4];
+    reader.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn read_f32<R: Read>(reader: &mut R) -> io::Result<f32> {
+    let mut b = [0; 4];
+    reader.read_exact(&mut b)?;
+    Ok(f32::from_le_bytes(b))
+}
+
+/// Heterogeneous medium filling the bounds of a density grid.
+///
+/// Scattering distances are sampled with delta tracking against the grid's
+/// maximum density as majorant, which is unbiased for any density field.
+pub struct GridMedium {
+    grid: Arc<dyn DensityGrid + Send + Sync>,
+    density_scale: f64,
+    phase_function: Arc<dyn Material + Send + Sync>,
+}
+
+impl GridMedium {
+    pub fn new(
+        grid: Arc<dyn DensityGrid + Send + Sync>,
+        density_scale: f64,
+        albedo: Color,
+    ) -> Self {
+        Self::with_phase_function(grid, density_scale, Arc::new(Isotropic::new(albedo)))
+    }
+
+    pub fn with_phase_function(
+        grid: Arc<dyn DensityGrid + Send + Sync>,
+        density_scale: f64,
+        phase_function: Arc<dyn Material + Send + Sync>,
+    ) -> Self {
+        GridMedium {
+            grid,
+            density_scale,
+            phase_function,
+        }
+    }
+
+    fn majorant(&self) -> f64 {
+        self.grid.max_density() * self.density_scale
+    }
+
+    /// Estimates the fraction of light passing between `t_min` and `t_max`
+    /// along the ray, using ratio tracking
+    pub fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+        let majorant = self.majorant();
+        let (mut t, t_exit) = match self.grid.bounds().intersect(r, t_min, t_max) {
+            Some(interval) if majorant > 0.0 => interval,
+            _ => return 1.0,
+        };
+
+        let step = 1.0 / (majorant * r.direction().length());
+        let mut transmittance = 1.0;
+        loop {
+            t -= (1.0 - random_double(0.0..1.0)).ln() * step;
+            if t >= t_exit {
+                return transmittance;
+            }
+            transmittance *= 1.0 - self.grid.density(&r.at(t)) * self.density_scale / majorant;
+        }
+    }
+}
+
+impl Hittable for GridMedium {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let majorant = self.majorant();
+        if majorant <= 0.0 {
+            return None;
+        }
+        let (mut t, t_exit) = self.grid.bounds().intersect(r, t_min, t_max)?;
+
+        // Delta tracking: sample tentative collisions against the majorant
+        // and accept each one with probability density / majorant
+        let step = 1.0 / (majorant * r.direction().length());
+        loop {
+            t -= (1.0 - random_double(0.0..1.0)).ln() * step;
+            if t >= t_exit {
+                return None;
+            }
+
+            let p = r.at(t);
+            let density = self.grid.density(&p) * self.density_scale;
+            if random_double(0.0..1.0) * majorant < density {
+                return Some(HitRecord {
+                    t,
+                    p,
+                    normal: Vec3::new(1.0, 0.0, 0.0),
+                    front_face: true,
+                    material: self.phase_function.clone(),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.grid.bounds())
+    }
+}
+
+/// Temperature at which a `BlackbodyEmitter` emits its nominal intensity
+pub const BLACKBODY_REFERENCE_KELVIN: f64 = 1500.0;
+
+/// Volume emission from a temperature grid.
+///
+/// Each point glows with the blackbody color of its temperature. Brightness
+/// follows Planck's law relative to a blackbody at
+/// `BLACKBODY_REFERENCE_KELVIN`, which emits exactly `intensity`.
+pub struct BlackbodyEmitter {
+    temperature: Arc<dyn DensityGrid + Send + Sync>,
+    temperature_scale: f64,
+    intensity: f64,
+    reference_luminance: f64,
+}
+
+impl BlackbodyEmitter {
+    /// Grid values times `temperature_scale` are temperatures in kelvin
+    pub fn new(
+        temperature: Arc<dyn DensityGrid + Send + Sync>,
+        temperature_scale: f64,
+        intensity: f64,
+    ) -> BlackbodyEmitter {
+        BlackbodyEmitter {
+            temperature,
+            temperature_scale,
+            intensity,
+            reference_luminance: blackbody_xyz(BLACKBODY_REFERENCE_KELVIN).y(),
+        }
+    }
+
+    pub fn radiance(&self, p: &Point3) -> Color {
+        let kelvin = self.temperature.density(p) * self.temperature_scale;
+        let xyz = blackbody_xyz(kelvin);
+        self.intensity / self.reference_luminance * xyz_to_rgb(&xyz).max(&Color::default())
+    }
+}
+
+impl Material for BlackbodyEmitter {
+    // Reaching the emitter means the path was absorbed
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<(Color, Ray)> {
+        None
+    }
+
+    fn emitted(&self, _u: f64, _v: f64, p: &Point3) -> Color {
+        self.radiance(p)
+    }
+}
+
+/// Absorbing, scattering and emitting medium for fire and explosions.
+///
+/// Delta tracking picks collisions against the majorant of the extinction
+/// `(sigma_a + sigma_s) * density`. A collision is an absorption with
+/// probability `sigma_a * density / majorant`, ending the path with the
+/// blackbody emission at that point, a scattering with probability
+/// `sigma_s * density / majorant`, and a null collision otherwise.
+pub struct EmissiveMedium {
+    density: Arc<dyn DensityGrid + Send + Sync>,
+    sigma_a: f64,
+    sigma_s: f64,
+    phase_function: Arc<dyn Material + Send + Sync>,
+    emitter: Arc<BlackbodyEmitter>,
+}
+
+impl EmissiveMedium {
+    pub fn new(
+        density: Arc<dyn DensityGrid + Send + Sync>,
+        sigma_a: f64,
+        sigma_s: f64,
+        emitter: BlackbodyEmitter,
+    ) -> Self {
+        // Event selection already accounts for the single-scattering albedo
+        let phase_function = Arc::new(Isotropic::new(Color::new(1.0, 1.0, 1.0)));
+        Self::with_phase_function(density, sigma_a, sigma_s, emitter, phase_function)
+    }
+
+    pub fn with_phase_function(
+        density: Arc<dyn DensityGrid + Send + Sync>,
+        sigma_a: f64,
+        sigma_s: f64,
+        emitter: BlackbodyEmitter,
+        phase_function: Arc<dyn Material + Send + Sync>,
+    ) -> Self {
+        EmissiveMedium {
+            density,
+            sigma_a,
+            sigma_s,
+            phase_function,
+            emitter: Arc::new(emitter),
+        }
+    }
+}
+
+impl Hittable for EmissiveMedium {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let majorant = self.density.max_density() * (self.sigma_a + self.sigma_s);
+        if majorant <= 0.0 {
+            return None;
+        }
+        let (mut t, t_exit) = self.density.bounds().intersect(r, t_min, t_max)?;
+
+        let step = 1.0 / (majorant * r.direction().length());
+        loop {
+            t -= (1.0 - random_double(0.0..1.0)).ln() * step;
+            if t >= t_exit {
+                return None;
+            }
+
+            let p = r.at(t);
+            let density = self.density.density(&p);
+            let xi = random_double(0.0..1.0) * majorant;
+            let material: Arc<dyn Material + Send + Sync> = if xi < self.sigma_a * density {
+                self.emitter.clone()
+            } else if xi < (self.sigma_a + self.sigma_s) * density {
+                self.phase_function.clone()
+            } else {
+                continue;
+            };
+
+            return Some(HitRecord {
+                t,
+                p,
+                normal: Vec3::new(1.0, 0.0, 0.0),
+                front_face: true,
+                material,
+                ..Default::default()
+            });
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.density.bounds())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp_grid() -> VoxelGrid {
+        let dims = [10, 4, 3];
+        let data = (0..120)
+            .map(|n| if n % 10 < 5 { 0.0 } else { n as f32 })
+            .collect();
+        VoxelGrid::new(
+            dims,
+            Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(10.0, 4.0, 3.0)),
+            data,
+        )
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let grid = ramp_grid();
+        let mut bytes = Vec::new();
+        grid.write(&mut bytes).unwrap();
+        let loaded = VoxelGrid::read(&mut bytes.as_slice()).unwrap();
+
+        assert_eq!(loaded.dims(), grid.dims());
+        assert_eq!(loaded.bounds(), grid.bounds());
+        assert_eq!(loaded.data, grid.data);
+        assert!(VoxelGrid::read(&mut &b"NOPE"[..]).is_err());
+    }
+
+    #[test]
+    fn invalid_header_test() {
+        let header = |dims: [u32; 3]| {
+            let mut bytes = MAGIC.to_vec();
+            for d in dims {
+                bytes.extend(d.to_le_bytes());
+            }
+            bytes.extend([0; 24]);
+            bytes
+        };
+        for dims in [[0, 4, 4], [u32::MAX; 3], [1 << 16, 1 << 16, 2]] {
+            let err = VoxelGrid::read(&mut header(dims).as_slice()).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+        // Truncated data fails without allocating for the whole grid
+        let err = VoxelGrid::read(&mut header([1024, 1024, 1024]).as_slice())
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn trilinear_test() {
+        let grid = ramp_grid();
+        // Voxel centers return the stored value
+        assert_eq!(
+            grid.density(&Point3::new(5.5, 0.5, 0.5)),
+            grid.voxel(5, 0, 0)
+        );
+        // Halfway between two centers along x
+        let expected = 0.5 * (grid.voxel(6, 1, 0) + grid.voxel(7, 1, 0));
+        assert_eq!(grid.density(&Point3::new(7.0, 1.5, 0.5)), expected);
+        // Outside points clamp to the border
+        assert_eq!(grid.density(&Point3::new(-1.0, 0.5, 0.5)), 0.0);
+    }
+
+    #[test]
+    fn sparse_matches_dense_test() {
+        let dense = ramp_grid();
+        let sparse = SparseVoxelGrid::from_dense(&dense);
+        assert_eq!(sparse.brick_count(), 2);
+        assert_eq!(sparse.max_density(), dense.max_density());
+
+        for n in 0..50 {
+            let p = Point3::new(n as f64 * 0.2, (n % 4) as f64, (n % 3) as f64 * 0.9);
+            assert_eq!(sparse.density(&p), dense.density(&p));
+        }
+    }
+
+    #[test]
+    fn delta_tracking_test() {
+        let medium = GridMedium::new(Arc::new(ramp_grid()), 1.0, Color::new(1.0, 1.0, 1.0));
+        let r = Ray::new(Point3::new(-5.0, 1.5, 1.5), Vec3::new(1.0, 0.0, 0.0));
+
+        // The left half of the grid is empty, so collisions land in the right half
+        for _ in 0..100 {
+            let rec = medium.hit(&r, 0.001, f64::INFINITY).unwrap();
+            assert!(rec.p.x() > 4.5 && rec.p.x() < 10.0);
+        }
+        assert_eq!(medium.transmittance(&r, 0.001, 9.0), 1.0);
+        assert!(medium.transmittance(&r, 0.001, f64::INFINITY) < 1e-6);
+    }
+
+    fn uniform_grid(value: f32) -> Arc<VoxelGrid> {
+        Arc::new(VoxelGrid::new(
+            [2, 2, 2],
+            Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0)),
+            vec![value; 8],
+        ))
+    }
+
+    #[test]
+    fn blackbody_emitter_test() {
+        let emitter = BlackbodyEmitter::new(uniform_grid(1.5), 1000.0, 2.0);
+        let radiance = emitter.radiance(&Point3::default());
+        let luminance = 0.2126 * radiance.x() + 0.7152 * radiance.y() + 0.0722 * radiance.z();
+        assert!((luminance - 2.0).abs() < 0.05);
+        assert!(radiance.x() > radiance.z());
+
+        let cold = BlackbodyEmitter::new(uniform_grid(0.0), 1000.0, 2.0);
+        assert_eq!(cold.radiance(&Point3::default()), Color::default());
+    }
+
+    #[test]
+    fn emissive_absorber_test() {
+        let emitter = BlackbodyEmitter::new(uniform_grid(1.0), 2000.0, 1.0);
+        let medium = EmissiveMedium::new(uniform_grid(1.0), 50.0, 0.0, emitter);
+        let r = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let rec = medium.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!(rec.t >= 4.0 && rec.t <= 6.0);
+        assert!(rec.material.scatter(&r, &rec).is_none());
+        assert!(rec.material.emitted(rec.u, rec.v, &rec.p).x() > 0.0);
+    }
+}
@@ -0,0 +1,95 @@
+//! Uncompressed YUV4MPEG2 video, which ffmpeg and most players read directly.
+
+use image::RgbImage;
+use std::io::{self, Write};
+
+/// Writes 4:4:4 frames with BT.601 limited range colors
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    width: u32,
+    height: u32,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Writes the stream header. Players reject a frame rate of zero, so
+    /// it is an error.
+    pub fn new(mut writer: W, width: u32, height: u32, fps: u32) -> io::Result<Y4mWriter<W>> {
+        if fps == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame rate must be positive",
+            ));
+        }
+        writeln!(
+            writer,
+            "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+            width, height, fps
+        )?;
+        Ok(Y4mWriter {
+            writer,
+            width,
+            height,
+        })
+    }
+
+    pub fn write_frame(&mut self, image: &RgbImage) -> io::Result<()> {
+        if image.dimensions() != (self.width, self.height) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "frame is {}x{}, the video {}x{}",
+                    image.width(),
+                    image.height(),
+                    self.width,
+                    self.height
+                ),
+            ));
+        }
+
+        let pixels = (self.width * self.height) as usize;
+        let mut planes = vec![0u8; 3 * pixels];
+        for (i, pixel) in image.pixels().enumerate() {
+            let [r, g, b] = pixel.0.map(|c| c as f64 / 255.0);
+            let y = 16.0 + 65.481 * r + 128.553 * g + 24.966 * b;
+            let cb = 128.0 - 37.797 * r - 74.203 * g + 112.0 * b;
+            let cr = 128.0 + 112.0 * r - 93.786 * g - 18.214 * b;
+            planes[i] = y.round() as u8;
+            planes[pixels + i] = cb.round() as u8;
+            planes[2 * pixels + i] = cr.round() as u8;
+        }
+
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&planes)
+    }
+
+    /// Flushes the stream and returns the writer. Dropping the writer
+    /// instead loses errors of buffered frames.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_test() {
+        let mut video = Y4mWriter::new(Vec::new(), 2, 1, 24).unwrap();
+        let image = RgbImage::from_raw(2, 1, vec![0, 0, 0, 255, 255, 255]).unwrap();
+        video.write_frame(&image).unwrap();
+        assert!(video.write_frame(&RgbImage::new(1, 1)).is_err());
+
+        let header = b"YUV4MPEG2 W2 H1 F24:1 Ip A1:1 C444\n";
+        let bytes = video.finish().unwrap();
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(&bytes[header.len()..], b"FRAME\n\x10\xeb\x80\x80\x80\x80");
+
+        assert!(Y4mWriter::new(Vec::new(), 2, 1, 0).is_err());
+    }
+}